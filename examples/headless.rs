@@ -0,0 +1,109 @@
+//! Renders a single frame without a window and reads the default attachment back.
+//!
+//! Usage: `cargo run --example headless [output.ppm]`, run from the repository root so the
+//! pipeline and shaders are found. The frame is written as a binary PPM image.
+
+use glam::{Mat4, Vec3, Vec4};
+use rend_vk::{
+    renderer,
+    shader_resource::{Frustum, ResourceKind, SingleResource, Timing, View, ViewRay},
+};
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 360;
+
+fn main() {
+    let out_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "headless.ppm".to_string());
+    let mut renderer = renderer::make_headless_renderer(WIDTH, HEIGHT, 2, false, false)
+        .expect("couldn't create the renderer!");
+
+    let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, -10.0), Vec3::ZERO, Vec3::Y);
+    let (near_plane, far_plane) = (0.3f32, 256f32);
+    let proj = Mat4::perspective_rh(
+        60.0f32.to_radians(),
+        WIDTH as f32 / HEIGHT as f32,
+        near_plane,
+        far_plane,
+    );
+    let corner = |x: f32, y: f32| {
+        let v = proj.inverse() * Vec4::new(x, y, -1.0, 1.0);
+        let v = v / v.w;
+        (v / v.z).truncate()
+    };
+    let (width, height) = (WIDTH as f32, HEIGHT as f32);
+    // Every pass of the default pipeline reads some of these
+    let resources = [
+        (
+            ResourceKind::View,
+            SingleResource::View(View {
+                view,
+                inv_view: view.inverse(),
+                proj,
+                view_proj: proj * view,
+                prev_view: view,
+                prev_inv_view: view.inverse(),
+                prev_proj: proj,
+                prev_view_proj: proj * view,
+            }),
+        ),
+        (
+            ResourceKind::ViewRay,
+            SingleResource::ViewRay(ViewRay {
+                bleft: corner(-1.0, -1.0),
+                m22: proj.z_axis[2],
+                bright: corner(1.0, -1.0),
+                m23: proj.z_axis[3],
+                tright: corner(1.0, 1.0),
+                m32: proj.w_axis[2],
+                tleft: corner(-1.0, 1.0),
+                m33: proj.w_axis[3],
+            }),
+        ),
+        (
+            ResourceKind::Frustum,
+            SingleResource::Frustum(Frustum {
+                width,
+                height,
+                inv_width: 1.0 / width,
+                inv_height: 1.0 / height,
+                ext_width: width,
+                ext_height: height,
+                ext_inv_width: 1.0 / width,
+                ext_inv_height: 1.0 / height,
+                near_plane,
+                far_plane,
+                fragments_per_meter_plane: 600.0,
+                pad0: 0,
+            }),
+        ),
+        (
+            ResourceKind::Timing,
+            SingleResource::Timing(Timing {
+                interpolation: 0.5,
+                pad0: 0,
+                pad1: 0,
+                pad2: 0,
+            }),
+        ),
+    ];
+    // Readback needs a finished frame, the first one only sets things up
+    while renderer.get_current_frame() < 2 {
+        for (kind, resource) in &resources {
+            renderer.place_shader_resource(*kind, resource.clone());
+        }
+        renderer.render().expect("render failed!");
+    }
+    let pixels = renderer
+        .read_default_attachment()
+        .expect("couldn't read the default attachment!");
+    let texel_size = pixels.len() / (WIDTH * HEIGHT) as usize;
+    let mut ppm = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+    for texel in pixels.chunks_exact(texel_size) {
+        ppm.extend_from_slice(&texel[..3]);
+    }
+    std::fs::write(&out_path, ppm).expect("couldn't write the image!");
+    println!("wrote {}x{} frame to {}", WIDTH, HEIGHT, out_path);
+    renderer.destroy();
+}
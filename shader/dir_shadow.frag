@@ -35,11 +35,8 @@ SAMPLING(gbAlbedo, SMP_RT, 2D, 0)
 SAMPLING(gbNormal, SMP_RT, 2D, 1)
 SAMPLING(gbMisc, SMP_RT, 2D, 2)
 SAMPLING(gbDepth, SMP_RT, 2D, 3)
-SAMPLING(gbCascade0, SMP_RT, 2DShadow, 4)
-SAMPLING(gbCascade1, SMP_RT, 2DShadow, 5)
-SAMPLING(gbCascade2, SMP_RT, 2DShadow, 6)
-SAMPLING(gbCascade3, SMP_RT, 2DShadow, 7)
-SAMPLING(gbOcclusion, SMP_RT, 2D, 8)
+SAMPLING(gbCascades, SMP_RT, 2DArrayShadow, 4)
+SAMPLING(gbOcclusion, SMP_RT, 2D, 5)
 
 const float MIN_SHADOW_DIFFUSE = 0.15;
 
@@ -60,16 +57,8 @@ uint selectCascade(float viewZ, vec4 splits) {
 }
 
 float sampleCascade(uint cascadei, vec3 coords) {
-	if (cascadei == 3u) {
-	  return texture(gbCascade3, coords);
-	} 
-	if (cascadei == 2u) {
-		return texture(gbCascade2, coords);
-	}
-	if (cascadei == 1u) {
-		return texture(gbCascade1, coords);
-	}
-	return texture(gbCascade0, coords);
+	// Layer goes before the reference depth for array shadow samplers
+	return texture(gbCascades, vec4(coords.xy, float(cascadei), coords.z));
 }
 
 void main() {
@@ -78,6 +78,7 @@ BUFFER_REF(TransformExtras, TransformExtra)
 #define DESCRIPTOR_SAMPLER_DEFAULT_MACRO(BIND) layout (set = BIND, binding = 0) uniform sampler[] samplers;
 #define DESCRIPTOR_TEXTURE_DEFAULT_MACRO(BIND) layout (set = BIND, binding = 0) uniform texture2D[] textures;
 #define DESCRIPTOR_TEXTURE_CUBE_MACRO(BIND) layout (set = BIND, binding = 0) uniform textureCube[] textures;
+#define DESCRIPTOR_TEXTURE_3D_MACRO(BIND) layout (set = BIND, binding = 0) uniform texture3D[] textures;
 #define DESCRIPTOR_SAMPLER_MACRO(NAME, BIND) DESCRIPTOR_SAMPLER_##NAME##_MACRO(BIND)
 #define DESCRIPTOR_TEXTURE_MACRO(NAME, BIND) DESCRIPTOR_TEXTURE_##NAME##_MACRO(BIND)
 #define DESCRIPTOR_TARGET_IMAGE_MACRO(NAME, BIND) layout (set = DESC_SET_TARGET_IMAGE, binding = BIND) uniform texture2D NAME;
@@ -133,8 +134,8 @@ layout(scalar, buffer_reference, buffer_reference_align = 8) readonly buffer Pas
 #define WRITING(NAME, TYPE, INDEX) layout ( location = INDEX ) out TYPE NAME
 // Output attribute location
 #define ATTR_LOC(POS) layout (location = POS)
-// Separate image-sampler usage
-#define SAMPLER_FOR(NAME, TYPE, TIDX, SIDX) sampler##TYPE## \( textures[nonuniformEXT(TIDX)], samplers[nonuniformEXT(SIDX)] )
+// Separate image-sampler usage, texture ids carry a generation above the 16 bits of the slot
+#define SAMPLER_FOR(NAME, TYPE, TIDX, SIDX) sampler##TYPE## \( textures[nonuniformEXT((TIDX) & 0xFFFF)], samplers[nonuniformEXT(SIDX)] )
 /* 
 * These macros are unused in the Vulkan pipeline, 
 * define them here to avoid compiler errors.
@@ -0,0 +1,67 @@
+//! Checks a pipeline description without creating a renderer, ie, before launching the game.
+//!
+//! Usage: `pipeline_check [pipeline.json] [--dot graph.dot] [--json graph.json]`, paths are
+//! relative to the working directory same as when the renderer loads them. A valid pipeline
+//! can also be exported as a graph of its passes, targets and barriers.
+
+use rend_vk::pipeline::file::Pipeline;
+
+fn main() {
+    let mut name = None;
+    let mut dot_path = None;
+    let mut json_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" => dot_path = args.next(),
+            "--json" => json_path = args.next(),
+            _ => name = Some(arg),
+        }
+    }
+    let name = name.as_deref().unwrap_or("pipeline.json");
+    let diagnostics = Pipeline::validate(Some(name));
+    if diagnostics.is_empty() {
+        println!("{} is valid!", name);
+        export(name, dot_path.as_deref(), json_path.as_deref());
+        return;
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    eprintln!("{} problems found in {}", diagnostics.len(), name);
+    std::process::exit(1);
+}
+
+fn export(name: &str, dot_path: Option<&str>, json_path: Option<&str>) {
+    if dot_path.is_none() && json_path.is_none() {
+        return;
+    }
+    let pipeline = match Pipeline::read(Some(name)) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let outputs = [
+        (dot_path, pipeline.to_dot()),
+        (json_path, pipeline.to_graph_json().map(|e| e.to_string())),
+    ];
+    for (path, contents) in outputs {
+        let Some(path) = path else {
+            continue;
+        };
+        let contents = match contents {
+            Ok(contents) => contents,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = std::fs::write(path, contents) {
+            eprintln!("failed writing the graph to {}: {}", path, e);
+            std::process::exit(1);
+        }
+        println!("graph written to {}", path);
+    }
+}
@@ -1,5 +1,5 @@
 use ash::vk;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::clone::Clone;
 use std::marker::Copy;
 use std::os::raw::c_void;
@@ -66,18 +66,21 @@ impl DeviceAllocator {
         }
     }
 
+    /// Returns None if the size doesn't fits in a chunk.
     pub fn alloc(&self, size: u64) -> Option<DeviceSlice> {
         if let Some(slice) = self.try_alloc(size) {
             return Some(slice);
         }
-        let mut chunk = Chunk::new(&self.context, Self::CHUNK_SIZE, self.kind());
-        if let Some(slice) = chunk.alloc(size) {
-            let chunk_ref = Rc::new(RefCell::new(chunk));
-            let mut chunks = self.chunks.borrow_mut();
-            chunks.push(chunk_ref);
-            return Some(slice);
+        if size > Self::CHUNK_SIZE {
+            // Wouldn't fit in a new chunk either
+            return None;
         }
-        panic!("can't allocate a buffer of this size {}!", size)
+        let mut chunk = Chunk::new(&self.context, Self::CHUNK_SIZE, self.kind());
+        let slice = chunk.alloc(size);
+        let chunk_ref = Rc::new(RefCell::new(chunk));
+        let mut chunks = self.chunks.borrow_mut();
+        chunks.push(chunk_ref);
+        slice
     }
 
     fn try_alloc(&self, size: u64) -> Option<DeviceSlice> {
@@ -154,6 +157,71 @@ impl DeviceAllocator {
     }
 }
 
+/// Bump allocator for transient data, ie, per frame uploads.
+/// Slices can't be freed individually, all of them are released at once on `reset`.
+pub struct LinearAllocator {
+    context: Rc<VulkanContext>,
+    chunks: RefCell<Vec<Chunk>>,
+    // Index of the chunk allocations are being bumped from
+    current: Cell<usize>,
+}
+
+impl LinearAllocator {
+    pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+    pub fn new(ctx: Rc<VulkanContext>) -> Self {
+        let chunk = Chunk::new(&ctx, Self::CHUNK_SIZE, BufferKind::General);
+        Self {
+            context: ctx,
+            chunks: RefCell::new(vec![chunk]),
+            current: Cell::new(0),
+        }
+    }
+
+    pub fn alloc(&self, size: u64) -> DeviceSlice {
+        let mut chunks = self.chunks.borrow_mut();
+        loop {
+            let current = self.current.get();
+            if let Some(slice) = chunks[current].alloc(size) {
+                return slice;
+            }
+            if current + 1 < chunks.len() {
+                // Keep bumping from the next chunk
+                self.current.set(current + 1);
+                continue;
+            }
+            // Out of chunks, allocation might not fit in the default size either
+            let chunk_size = std::cmp::max(size, Self::CHUNK_SIZE);
+            chunks.push(Chunk::new(&self.context, chunk_size, BufferKind::General));
+            self.current.set(chunks.len() - 1);
+        }
+    }
+
+    /// Releases all the slices handed out. Caller must ensure the device isn't using them anymore.
+    pub fn reset(&self) {
+        for chunk in self.chunks.borrow_mut().iter_mut() {
+            chunk.reset();
+        }
+        self.current.set(0);
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        let mut chunks = self.chunks.borrow_mut();
+        for chunk in chunks.iter() {
+            chunk.destroy(device);
+        }
+        chunks.clear();
+    }
+
+    pub fn used(&self) -> u64 {
+        self.chunks
+            .borrow()
+            .iter()
+            .map(|c| c.buffer.size - c.available())
+            .sum()
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug, strum_macros::Display)]
 pub enum BufferKind {
     Undefined,
@@ -361,6 +429,14 @@ impl Chunk {
         None
     }
 
+    fn reset(&mut self) {
+        self.ranges.clear();
+        self.ranges.push(Range {
+            start: 0,
+            end: self.buffer.size,
+        });
+    }
+
     fn free(&mut self, slice: DeviceSlice) {
         // | | | | | |
         let slice_start = unsafe { slice.addr.offset(-(self.buffer.addr as isize)) as u64 };
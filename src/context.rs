@@ -17,13 +17,22 @@ pub struct ExtensionContext {
 
 impl VulkanContext {
     pub fn wait_and_reset_fence(&self, fence: vk::Fence) {
-        let fences = [fence];
+        self.wait_fence(fence);
+        self.reset_fence(fence);
+    }
+
+    pub fn wait_fence(&self, fence: vk::Fence) {
         unsafe {
             self.device
-                .wait_for_fences(&fences, true, u64::MAX)
+                .wait_for_fences(&[fence], true, u64::MAX)
                 .expect("fence wait failed!");
+        }
+    }
+
+    pub fn reset_fence(&self, fence: vk::Fence) {
+        unsafe {
             self.device
-                .reset_fences(&fences)
+                .reset_fences(&[fence])
                 .expect("fence reset failed!");
         }
     }
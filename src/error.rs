@@ -0,0 +1,66 @@
+use ash::vk;
+
+/// Errors the renderer reports back to the caller instead of panicking
+#[derive(Clone, Debug)]
+pub enum RendError {
+    /// Vulkan call failed, along with what was being attempted
+    Vulkan(vk::Result, String),
+    /// Pipeline description couldn't be read or built
+    Pipeline(String),
+    /// Shader couldn't be compiled or loaded
+    Shader(String),
+    /// Not enough device memory for the requested size
+    OutOfMemory(String),
+    /// All the ids for this kind of resource are in use
+    OutOfIds(String),
+    MissingMesh(u32),
+    MissingTexture(u32),
+    FreedTexture(u32),
+    /// Value doesn't map to any known kind, ie, task or resource kinds
+    InvalidKind(String),
+    /// Argument passed doesn't make sense for the operation
+    InvalidArgument(String),
+}
+
+pub type RendResult<T> = Result<T, RendError>;
+
+impl RendError {
+    /// Status code to report to callers through the C ABI, zero is reserved for success
+    pub const fn code(&self) -> i32 {
+        match self {
+            RendError::Vulkan(..) => 1,
+            RendError::Pipeline(..) => 2,
+            RendError::Shader(..) => 3,
+            RendError::OutOfMemory(..) => 4,
+            RendError::OutOfIds(..) => 5,
+            RendError::MissingMesh(..) => 6,
+            RendError::MissingTexture(..) => 7,
+            RendError::FreedTexture(..) => 8,
+            RendError::InvalidKind(..) => 9,
+            RendError::InvalidArgument(..) => 10,
+        }
+    }
+
+    pub fn vulkan(result: vk::Result, doing: &str) -> Self {
+        RendError::Vulkan(result, doing.to_string())
+    }
+}
+
+impl std::fmt::Display for RendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RendError::Vulkan(res, doing) => write!(f, "vulkan error {} while {}", res, doing),
+            RendError::Pipeline(msg) => write!(f, "pipeline error: {}", msg),
+            RendError::Shader(msg) => write!(f, "shader error: {}", msg),
+            RendError::OutOfMemory(msg) => write!(f, "out of memory: {}", msg),
+            RendError::OutOfIds(msg) => write!(f, "out of ids: {}", msg),
+            RendError::MissingMesh(id) => write!(f, "couldn't find mesh with id {}", id),
+            RendError::MissingTexture(id) => write!(f, "couldn't find texture with id {}", id),
+            RendError::FreedTexture(id) => write!(f, "texture with id {} was already freed", id),
+            RendError::InvalidKind(msg) => write!(f, "invalid kind: {}", msg),
+            RendError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RendError {}
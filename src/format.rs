@@ -1,7 +1,10 @@
 use ash::vk;
 use serde::Deserialize;
 
-use crate::UsedAsIndex;
+use crate::{
+    error::{RendError, RendResult},
+    UsedAsIndex,
+};
 
 impl Format {
     pub fn is_compressed(self) -> bool {
@@ -46,6 +49,17 @@ impl Format {
         )
     }
 
+    /// Integer formats can't be filtered linearly
+    pub fn is_integer(self) -> bool {
+        let name = self.to_string();
+        name.contains("_UINT") || name.contains("_SINT")
+    }
+
+    /// Whether it can be written from compute shaders as a storage image
+    pub fn supports_storage(self) -> bool {
+        self.has_color() && !self.is_srgb() && !self.is_compressed()
+    }
+
     pub const fn has_color(self) -> bool {
         !self.has_depth_or_stencil()
     }
@@ -129,10 +143,46 @@ impl Format {
         }
     }
 
+    /// Bytes per texel of uncompressed color formats, None for block compressed and depth/stencil ones
+    pub fn texel_size(self) -> Option<u32> {
+        if self == Self::UNDEFINED || self.is_compressed() || !self.has_color() {
+            return None;
+        }
+        let name = self.to_string();
+        if name.ends_with("_PACK8") {
+            return Some(1);
+        }
+        if name.ends_with("_PACK16") {
+            return Some(2);
+        }
+        if name.ends_with("_PACK32") {
+            return Some(4);
+        }
+        // Add up the channel bit counts, ie, R16G16 => 32 bits
+        let channels = name.split('_').next().unwrap_or_default();
+        let bits: u32 = channels
+            .split(|c: char| c.is_ascii_alphabetic())
+            .filter_map(|bits| bits.parse::<u32>().ok())
+            .sum();
+        if bits == 0 || !bits.is_multiple_of(8) {
+            None
+        } else {
+            Some(bits / 8)
+        }
+    }
+
     pub fn size_for_extent(self, extent: vk::Extent2D) -> u32 {
         self.size_for(extent.width, extent.height)
     }
 
+    pub fn try_of_u32(v: u32) -> RendResult<Self> {
+        if v > (Self::MAX_VALUE as u32) {
+            Err(RendError::InvalidKind(format!("format {}", v)))
+        } else {
+            Ok(Self::of_u32(v))
+        }
+    }
+
     pub const fn of_u8(v: u8) -> Self {
         if v > Self::MAX_VALUE {
             panic!()
@@ -565,3 +615,26 @@ impl Format {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+
+    #[test]
+    fn texel_size_of_uncompressed_color_formats() {
+        assert_eq!(Format::R8G8B8A8_SRGB.texel_size(), Some(4));
+        assert_eq!(Format::B8G8R8_UNORM.texel_size(), Some(3));
+        assert_eq!(Format::R16G16_SFLOAT.texel_size(), Some(4));
+        assert_eq!(Format::R32G32B32A32_SFLOAT.texel_size(), Some(16));
+        assert_eq!(Format::R5G6B5_UNORM_PACK16.texel_size(), Some(2));
+        assert_eq!(Format::B10G11R11_UFLOAT_PACK32.texel_size(), Some(4));
+    }
+
+    #[test]
+    fn no_texel_size_for_compressed_or_depth_formats() {
+        assert_eq!(Format::BC1_RGBA_SRGB_BLOCK.texel_size(), None);
+        assert_eq!(Format::EAC_R11_UNORM_BLOCK.texel_size(), None);
+        assert_eq!(Format::D32_SFLOAT.texel_size(), None);
+        assert_eq!(Format::UNDEFINED.texel_size(), None);
+    }
+}
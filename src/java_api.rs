@@ -1,4 +1,5 @@
 use std::{
+    cell::RefCell,
     collections::HashMap,
     mem::size_of,
     str::FromStr,
@@ -9,13 +10,14 @@ use ash::vk;
 use bitvec::view::BitView;
 
 use crate::{
+    error::{RendError, RendResult},
     format::Format,
     pipeline::{
         file::{CompareFunc, Filtering, WrapMode},
         sampler::SamplerKey,
     },
     pos_mul,
-    render_task::{self, TaskKind},
+    render_task::{self, TaskKind, TaskScissor},
     renderer::{self, AllocatorStats, MeshBuffer, Renderer},
     shader_resource::*,
     texture::{MipMap, Texture, TextureKind},
@@ -28,6 +30,51 @@ const JNI_FALSE: u8 = 0;
 const JNI_TRUE: u8 = 1;
 
 const MISSING_SAMPLER_ID: u8 = u8::MAX;
+// Returned instead of an id when generating a resource fails
+const INVALID_ID: u32 = u32::MAX;
+const STATUS_OK: i32 = 0;
+
+thread_local! {
+    // Message of the last error reported on this thread, see getLastError
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+fn set_last_error(err: &RendError) {
+    log::error!("{}", err);
+    LAST_ERROR.with_borrow_mut(|e| *e = err.to_string());
+}
+
+/// Maps the result to a status code, zero means success, otherwise see [RendError::code]
+fn status_of(result: RendResult<()>) -> i32 {
+    match result {
+        Ok(_) => STATUS_OK,
+        Err(err) => {
+            set_last_error(&err);
+            err.code()
+        }
+    }
+}
+
+/// Borrows the UTF-8 string Java passed as an address and length
+fn str_of<'a>(chars: u64, len: u32) -> RendResult<&'a str> {
+    let chars = unsafe { std::slice::from_raw_parts(chars as *const u8, len as usize) };
+    std::str::from_utf8(chars)
+        .map_err(|e| RendError::InvalidArgument(format!("invalid utf8 string: {}", e)))
+}
+
+fn sampler_key_of(
+    filter: u8,
+    wrap_mode: u8,
+    compare_func: u8,
+    anisotropy: u8,
+) -> RendResult<SamplerKey> {
+    Ok(SamplerKey {
+        filter: Filtering::try_of_u8(filter)?,
+        wrap_mode: WrapMode::try_of_u8(wrap_mode)?,
+        compare_func: CompareFunc::try_of_u8(compare_func)?,
+        anisotropy,
+    })
+}
 
 trait ToJava<T> {
     fn to_java(&self) -> T;
@@ -69,6 +116,8 @@ pub struct JavaMipMap {
     pub index: u32,
     pub width: u32,
     pub height: u32,
+    pub depth: u32,
+    pub layers: u32,
     pub size: u32,
     pub offset: u32,
 }
@@ -79,6 +128,8 @@ impl ToJava<JavaMipMap> for MipMap {
             index: self.index,
             width: self.width,
             height: self.height,
+            depth: self.depth,
+            layers: self.layers,
             size: self.size,
             offset: self.offset,
         }
@@ -161,6 +212,7 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_makeRenderer(
     _unused_jclazz: usize,
     render_width: u32,
     render_height: u32,
+    frames_in_flight: u32,
     window: u64,
     instance_extensions: u64,
     instance_extensions_len: u32,
@@ -192,29 +244,61 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_makeRenderer(
             )
         }
     };
-    let renderer = renderer::make_renderer(
+    let renderer = match renderer::make_renderer(
         render_width,
         render_height,
+        frames_in_flight,
         is_vsync_enabled == JNI_TRUE,
         is_debug_enabled == JNI_TRUE,
         is_validation_layer_enabled == JNI_TRUE,
         instance_extensions,
         |_, instance, surface| glfw_create_window_surface(instance.handle(), window, 0, surface),
-    );
+    ) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            set_last_error(&err);
+            return 0;
+        }
+    };
     let boxed = Box::from(renderer);
     let ptr = Box::into_raw(boxed) as u64;
     log::trace!("renderer finished!");
     ptr
 }
 
+/// Copies the last error message of the calling thread as UTF-8 into dest, truncating
+/// it if it doesn't fits. Returns the full length of the message in bytes.
+#[no_mangle]
+pub extern "C" fn Java_game_render_vulkan_RendVkApi_getLastError(
+    _unused_jnienv: usize,
+    _unused_jclazz: usize,
+    dest: u64,
+    dest_len: u32,
+) -> u32 {
+    LAST_ERROR.with_borrow(|msg| {
+        let bytes = msg.as_bytes();
+        let len = std::cmp::min(bytes.len(), dest_len as usize);
+        if len > 0 {
+            let dest = unsafe { std::slice::from_raw_parts_mut(dest as *mut u8, len) };
+            dest.copy_from_slice(&bytes[..len]);
+        }
+        bytes.len() as u32
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn Java_game_render_vulkan_RendVkApi_resourceAlignOf(
     _unused_jnienv: usize,
     _unused_jclazz: usize,
     kind: u32,
 ) -> u32 {
-    let kind = ResourceKind::of_u32(kind);
-    kind.resource_align() as u32
+    match ResourceKind::try_of_u32(kind) {
+        Ok(kind) => kind.resource_align() as u32,
+        Err(err) => {
+            set_last_error(&err);
+            0
+        }
+    }
 }
 
 #[no_mangle]
@@ -224,9 +308,13 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_formatValueForName(
     name: u64,
     name_len: u32,
 ) -> u32 {
-    let name_chars = unsafe { std::slice::from_raw_parts(name as *const u8, name_len as usize) };
-    let v = Format::from_str(std::str::from_utf8(name_chars).expect("invalid name utf8 string!"))
-        .unwrap_or(Format::UNDEFINED);
+    let v = match str_of(name, name_len) {
+        Ok(name) => Format::from_str(name).unwrap_or(Format::UNDEFINED),
+        Err(err) => {
+            set_last_error(&err);
+            Format::UNDEFINED
+        }
+    };
     v.to_u32()
 }
 
@@ -236,8 +324,13 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_resourceSizeOf(
     _unused_jclazz: usize,
     kind: u32,
 ) -> u32 {
-    let kind = ResourceKind::of_u32(kind);
-    kind.resource_size() as u32
+    match ResourceKind::try_of_u32(kind) {
+        Ok(kind) => kind.resource_size() as u32,
+        Err(err) => {
+            set_last_error(&err);
+            0
+        }
+    }
 }
 
 #[no_mangle]
@@ -271,10 +364,51 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_render(
     _unused_jnienv: usize,
     _unused_jclazz: usize,
     renderer: u64,
-) {
+) -> i32 {
+    let mut renderer = to_renderer(renderer);
+    let status = status_of(renderer.render());
+    Box::leak(renderer);
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn Java_game_render_vulkan_RendVkApi_resize(
+    _unused_jnienv: usize,
+    _unused_jclazz: usize,
+    renderer: u64,
+    width: u32,
+    height: u32,
+) -> i32 {
+    let mut renderer = to_renderer(renderer);
+    let status = status_of(renderer.resize(width, height));
+    Box::leak(renderer);
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn Java_game_render_vulkan_RendVkApi_setRenderExtent(
+    _unused_jnienv: usize,
+    _unused_jclazz: usize,
+    renderer: u64,
+    width: u32,
+    height: u32,
+) -> i32 {
+    let mut renderer = to_renderer(renderer);
+    let status = status_of(renderer.set_render_extent(width, height));
+    Box::leak(renderer);
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn Java_game_render_vulkan_RendVkApi_reloadPipeline(
+    _unused_jnienv: usize,
+    _unused_jclazz: usize,
+    renderer: u64,
+) -> i32 {
     let mut renderer = to_renderer(renderer);
-    renderer.render();
+    let status = status_of(renderer.reload_pipeline());
     Box::leak(renderer);
+    status
 }
 
 #[no_mangle]
@@ -287,13 +421,15 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_tryGetSampler(
     compare_func: u8,
     anisotropy: u8,
 ) -> u8 {
+    let key = match sampler_key_of(filter, wrap_mode, compare_func, anisotropy) {
+        Ok(key) => key,
+        Err(err) => {
+            set_last_error(&err);
+            return MISSING_SAMPLER_ID;
+        }
+    };
     let renderer = to_renderer(renderer);
-    let sampler = renderer.try_get_sampler(SamplerKey {
-        filter: Filtering::of_u8(filter),
-        wrap_mode: WrapMode::of_u8(wrap_mode),
-        compare_func: CompareFunc::of_u8(compare_func),
-        anisotropy,
-    });
+    let sampler = renderer.try_get_sampler(key);
     Box::leak(renderer);
     match sampler {
         Some(id) => id,
@@ -311,15 +447,20 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_getSampler(
     compare_func: u8,
     anisotropy: u8,
 ) -> u8 {
+    let key = match sampler_key_of(filter, wrap_mode, compare_func, anisotropy) {
+        Ok(key) => key,
+        Err(err) => {
+            set_last_error(&err);
+            return MISSING_SAMPLER_ID;
+        }
+    };
     let mut renderer = to_renderer(renderer);
-    let sampler = renderer.get_sampler(SamplerKey {
-        filter: Filtering::of_u8(filter),
-        wrap_mode: WrapMode::of_u8(wrap_mode),
-        compare_func: CompareFunc::of_u8(compare_func),
-        anisotropy,
-    });
+    let sampler = renderer.get_sampler(key);
     Box::leak(renderer);
-    sampler
+    sampler.unwrap_or_else(|err| {
+        set_last_error(&err);
+        MISSING_SAMPLER_ID
+    })
 }
 
 #[no_mangle]
@@ -342,7 +483,10 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_genMesh(
         count,
     );
     Box::leak(renderer);
-    mesh_id
+    mesh_id.unwrap_or_else(|err| {
+        set_last_error(&err);
+        INVALID_ID
+    })
 }
 
 #[no_mangle]
@@ -352,12 +496,14 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_fetchMesh(
     renderer: u64,
     id: u32,
     dest: u64,
-) {
+) -> i32 {
     let renderer = to_renderer(renderer);
-    let mesh = renderer.fetch_mesh_or_fail(id);
-    let dest = unsafe { std::slice::from_raw_parts_mut(dest as *mut JavaMesh, 1) };
-    dest[0] = mesh.to_java();
+    let status = status_of(renderer.fetch_mesh_or_fail(id).map(|mesh| {
+        let dest = unsafe { std::slice::from_raw_parts_mut(dest as *mut JavaMesh, 1) };
+        dest[0] = mesh.to_java();
+    }));
     Box::leak(renderer);
+    status
 }
 
 #[no_mangle]
@@ -366,10 +512,11 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_freeMesh(
     _unused_jclazz: usize,
     renderer: u64,
     id: u32,
-) {
+) -> i32 {
     let mut renderer = to_renderer(renderer);
-    renderer.free_mesh(id);
+    let status = status_of(renderer.free_mesh(id));
     Box::leak(renderer);
+    status
 }
 
 #[no_mangle]
@@ -385,19 +532,30 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_genTexture(
     name_len: u32,
     staging_size: u32,
 ) -> u32 {
-    let mut renderer = to_renderer(renderer);
     let mip_map_count = mip_maps_len / size_of::<JavaMipMap>() as u32;
     let expected_mip_map_size = size_of::<JavaMipMap>() as u32 * mip_map_count;
-    assert!(
-        expected_mip_map_size == mip_maps_len,
-        "mip_maps_len can't hold an exact count of mip maps!"
-    );
+    if expected_mip_map_size != mip_maps_len {
+        set_last_error(&RendError::InvalidArgument(format!(
+            "mip maps length {} can't hold an exact count of mip maps",
+            mip_maps_len
+        )));
+        return INVALID_ID;
+    }
     let name = if name_len > 0 {
-        let name_chars =
-            unsafe { std::slice::from_raw_parts(name as *const u8, name_len as usize) };
-        std::str::from_utf8(name_chars).expect("invalid name utf8 string!")
+        str_of(name, name_len)
     } else {
-        "java_texture"
+        Ok("java_texture")
+    };
+    let (name, format, kind) = match (
+        name,
+        Format::try_of_u32(format),
+        TextureKind::try_of_u32(kind),
+    ) {
+        (Ok(name), Ok(format), Ok(kind)) => (name, format, kind),
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+            set_last_error(&err);
+            return INVALID_ID;
+        }
     };
     let mip_maps: Vec<_> = unsafe {
         std::slice::from_raw_parts(mip_maps as *const JavaMipMap, mip_map_count as usize)
@@ -406,20 +564,33 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_genTexture(
     .map(|e| MipMap {
         width: e.width,
         height: e.height,
+        depth: e.depth,
+        layers: e.layers,
         index: e.index,
         offset: e.offset,
         size: e.size,
     })
     .collect();
-    let texture_id = renderer.gen_texture(
-        name.to_string(),
-        Format::of_u32(format),
-        TextureKind::of_u32(kind),
-        &mip_maps,
-        staging_size,
-    );
+    let mut renderer = to_renderer(renderer);
+    let texture_id = renderer.gen_texture(name.to_string(), format, kind, &mip_maps, staging_size);
+    Box::leak(renderer);
+    texture_id.unwrap_or_else(|err| {
+        set_last_error(&err);
+        INVALID_ID
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn Java_game_render_vulkan_RendVkApi_freeTexture(
+    _unused_jnienv: usize,
+    _unused_jclazz: usize,
+    renderer: u64,
+    id: u32,
+) -> i32 {
+    let mut renderer = to_renderer(renderer);
+    let status = status_of(renderer.free_texture(id));
     Box::leak(renderer);
-    texture_id
+    status
 }
 
 #[no_mangle]
@@ -429,12 +600,14 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_fetchTexture(
     renderer: u64,
     id: u32,
     dest: u64,
-) {
+) -> i32 {
     let renderer = to_renderer(renderer);
-    let texture = renderer.fetch_texture_or_fail(id);
-    let dest = unsafe { std::slice::from_raw_parts_mut(dest as *mut JavaTexture, 1) };
-    dest[0] = texture.to_java();
+    let status = status_of(renderer.fetch_texture_or_fail(id).map(|texture| {
+        let dest = unsafe { std::slice::from_raw_parts_mut(dest as *mut JavaTexture, 1) };
+        dest[0] = texture.to_java();
+    }));
     Box::leak(renderer);
+    status
 }
 
 #[no_mangle]
@@ -444,18 +617,21 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_fetchTextureMipMaps(
     renderer: u64,
     id: u32,
     dest: u64,
-) {
+) -> i32 {
     let renderer = to_renderer(renderer);
-    let texture = renderer
-        .fetch_texture(id)
-        .unwrap_or_else(|| panic!("couldn't find texture with id {}", id));
-    let dest = unsafe {
-        std::slice::from_raw_parts_mut(dest as *mut JavaMipMap, texture.mip_map_count() as usize)
-    };
-    for (i, item) in texture.mip_maps.iter().enumerate() {
-        dest[i] = item.to_java();
-    }
+    let status = status_of(renderer.fetch_texture_or_fail(id).map(|texture| {
+        let dest = unsafe {
+            std::slice::from_raw_parts_mut(
+                dest as *mut JavaMipMap,
+                texture.mip_map_count() as usize,
+            )
+        };
+        for (i, item) in texture.mip_maps.iter().enumerate() {
+            dest[i] = item.to_java();
+        }
+    }));
     Box::leak(renderer);
+    status
 }
 
 #[no_mangle]
@@ -464,10 +640,11 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_queueTextureForUploading(
     _unused_jclazz: usize,
     renderer: u64,
     id: u32,
-) {
+) -> i32 {
     let mut renderer = to_renderer(renderer);
-    renderer.queue_texture_for_uploading(id);
+    let status = status_of(renderer.queue_texture_for_uploading(id));
     Box::leak(renderer);
+    status
 }
 
 #[no_mangle]
@@ -480,10 +657,14 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_isTextureUploaded(
     let renderer = to_renderer(renderer);
     let is_uploaded = renderer.is_texture_uploaded(id);
     Box::leak(renderer);
-    if is_uploaded {
-        JNI_TRUE
-    } else {
-        JNI_FALSE
+    match is_uploaded {
+        Ok(true) => JNI_TRUE,
+        Ok(false) => JNI_FALSE,
+        Err(err) => {
+            // Caller can tell it apart by checking getLastError
+            set_last_error(&err);
+            JNI_FALSE
+        }
     }
 }
 
@@ -495,10 +676,19 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_placeShaderResource(
     kind: u32,
     resource: u64,
     resource_len: u32,
-) {
+) -> i32 {
     let mut renderer = to_renderer(renderer);
-    let kind = ResourceKind::of_u32(kind);
     let data = unsafe { std::slice::from_raw_parts(resource as *const u8, resource_len as usize) };
+    let status = status_of(
+        ResourceKind::try_of_u32(kind)
+            .and_then(|kind| unpack_shader_resource(kind, data).map(|res| (kind, res)))
+            .map(|(kind, resource)| renderer.place_shader_resource(kind, resource)),
+    );
+    Box::leak(renderer);
+    status
+}
+
+fn unpack_shader_resource(kind: ResourceKind, data: &[u8]) -> RendResult<SingleResource> {
     let (resource, _) = match kind {
         ResourceKind::Transform => unpack_single_resource::<Transform>(data),
         ResourceKind::Material => unpack_single_resource::<Material>(data),
@@ -513,9 +703,8 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_placeShaderResource(
         ResourceKind::TransformExtra => unpack_single_resource::<TransformExtra>(data),
         ResourceKind::View => unpack_single_resource::<View>(data),
         ResourceKind::Timing => unpack_single_resource::<Timing>(data),
-    };
-    renderer.place_shader_resource(kind, resource);
-    Box::leak(renderer);
+    }?;
+    Ok(resource)
 }
 
 #[no_mangle]
@@ -532,35 +721,50 @@ pub extern "C" fn Java_game_render_vulkan_RendVkApi_addTaskToQueue(
     resource_bits: u32,
     resources: u64,
     resources_len: u32,
-) {
+    scissor_x: i32,
+    scissor_y: i32,
+    scissor_width: u32,
+    scissor_height: u32,
+) -> i32 {
     let mut renderer = to_renderer(renderer);
-    let kind = TaskKind::of_u32(kind);
+    // Empty scissor means the task isn't clipped
+    let scissor = (scissor_width > 0 && scissor_height > 0).then_some(TaskScissor {
+        x: scissor_x,
+        y: scissor_y,
+        width: scissor_width,
+        height: scissor_height,
+    });
     let data =
         unsafe { std::slice::from_raw_parts(resources as *const u8, resources_len as usize) };
-    let resources = unpack_render_task_resources(data, resource_bits, instance_count);
-    let task = render_task::RenderTask {
-        kind,
-        resources,
-        instance_count,
-        vertex_count,
-        indices_offset,
-        mesh_buffer_id: mesh_id,
-    };
-    renderer.add_task_to_queue(task, parent_id);
+    let result = TaskKind::try_of_u32(kind).and_then(|kind| {
+        let resources = unpack_render_task_resources(data, resource_bits, instance_count)?;
+        let task = render_task::RenderTask {
+            kind,
+            resources,
+            instance_count,
+            vertex_count,
+            indices_offset,
+            mesh_buffer_id: mesh_id,
+            scissor,
+        };
+        renderer.add_task_to_queue(task, parent_id)
+    });
+    let status = status_of(result);
     Box::leak(renderer);
+    status
 }
 
 fn unpack_render_task_resources(
     data: &[u8],
     resource_bits: u32,
     instances: u32,
-) -> HashMap<ResourceKind, MultiResource> {
+) -> RendResult<HashMap<ResourceKind, MultiResource>> {
     let instances = instances as usize;
     let resource_bits = resource_bits.view_bits::<bitvec::order::Lsb0>();
     let mut offset = 0usize;
     let mut resources_by_kind = HashMap::with_capacity(resource_bits.count_ones());
     for b in resource_bits.iter_ones() {
-        let kind = ResourceKind::of_usize(b);
+        let kind = ResourceKind::try_of_usize(b)?;
         let (wrapper, next_end) = match kind {
             ResourceKind::Transform => unpack_multi_resource::<Transform>(offset, instances, data),
             ResourceKind::Material => unpack_multi_resource::<Material>(offset, instances, data),
@@ -575,46 +779,95 @@ fn unpack_render_task_resources(
             ResourceKind::TransformExtra => {
                 unpack_multi_resource::<TransformExtra>(offset, instances, data)
             }
-            _ => panic!("unrecognized resource kind {}", kind),
-        };
+            _ => {
+                return Err(RendError::InvalidKind(format!(
+                    "resource kind {} can't be used in render tasks",
+                    kind
+                )))
+            }
+        }?;
         offset = next_end;
         resources_by_kind.insert(kind, wrapper);
     }
-    resources_by_kind
+    Ok(resources_by_kind)
 }
 
-fn unpack_single_resource<T>(data: &[u8]) -> (SingleResource, usize)
+fn unpack_single_resource<T>(data: &[u8]) -> RendResult<(SingleResource, usize)>
 where
     T: WrapResource<T>,
 {
-    let (res, next_end) = unpack_resource::<T>(0, 1, data);
-    (T::single_wrapper_for(res), next_end)
+    let (res, next_end) = unpack_resource::<T>(0, 1, data)?;
+    Ok((T::single_wrapper_for(res), next_end))
 }
 
-fn unpack_multi_resource<T>(start: usize, count: usize, data: &[u8]) -> (MultiResource, usize)
+fn unpack_multi_resource<T>(
+    start: usize,
+    count: usize,
+    data: &[u8],
+) -> RendResult<(MultiResource, usize)>
 where
     T: WrapResource<T>,
 {
-    let (res, next_end) = unpack_resource::<T>(start, count, data);
-    (T::multi_wrapper_for(res), next_end)
+    let (res, next_end) = unpack_resource::<T>(start, count, data)?;
+    Ok((T::multi_wrapper_for(res), next_end))
 }
 
-fn unpack_resource<T>(start: usize, count: usize, data: &[u8]) -> (&[T], usize)
+fn unpack_resource<T>(start: usize, count: usize, data: &[u8]) -> RendResult<(&[T], usize)>
 where
     T: WrapResource<T>,
 {
     let start_aligned = pos_mul(core::mem::align_of::<T>(), start);
+    if start_aligned > data.len() {
+        return Err(RendError::InvalidArgument(format!(
+            "resource {} starts at {}, past the {} bytes of data",
+            std::any::type_name::<T>(),
+            start_aligned,
+            data.len()
+        )));
+    }
+    let available = data.len() - start_aligned;
+
+    if available < count * std::mem::size_of::<T>() {
+        return Err(RendError::InvalidArgument(format!(
+            "unexpected resource {} count! expected {count}, got only {}",
+            std::any::type_name::<T>(),
+            available / std::mem::size_of::<T>()
+        )));
+    }
     let slice_aligned = &data[start_aligned..];
 
-    assert!(
-        slice_aligned.len() >= count * std::mem::size_of::<T>(),
-        "unexpected resource {} count! expected {count}, got only {}",
-        std::any::type_name::<T>(),
-        slice_aligned.len() / std::mem::size_of::<T>()
-    );
-
     let items = unsafe { std::slice::from_raw_parts(slice_aligned.as_ptr().cast::<T>(), count) };
 
     let next_end = items.as_ptr_range().end as usize - data.as_ptr() as usize;
-    (items, next_end)
+    Ok((items, next_end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unpack_resource;
+    use crate::shader_resource::Timing;
+
+    fn aligned_bytes(words: &[u32]) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(words.as_ptr().cast::<u8>(), size_of_val(words)) }
+    }
+
+    #[test]
+    fn unpack_reads_consecutive_items() {
+        let words = [1u32, 0, 0, 0, 2, 0, 0, 0];
+        let data = aligned_bytes(&words);
+        let (items, next_end) = unpack_resource::<Timing>(0, 2, data).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].interpolation.to_bits(), 2);
+        assert_eq!(next_end, data.len());
+        let (items, _) = unpack_resource::<Timing>(next_end, 0, data).unwrap();
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn unpack_rejects_items_past_the_data() {
+        let words = [0u32; 4];
+        let data = aligned_bytes(&words);
+        assert!(unpack_resource::<Timing>(0, 2, data).is_err());
+        assert!(unpack_resource::<Timing>(64, 0, data).is_err());
+    }
 }
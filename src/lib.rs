@@ -1,6 +1,7 @@
 pub mod buffer;
 pub mod context;
 pub mod debug;
+pub mod error;
 pub mod format;
 pub mod java_api;
 pub mod pipeline;
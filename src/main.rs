@@ -30,6 +30,7 @@ fn main() {
     let mut renderer = renderer::make_renderer(
         window_size.width,
         window_size.height,
+        2,
         true,
         true,
         true,
@@ -52,7 +53,8 @@ fn main() {
                 }
             }
         },
-    );
+    )
+    .expect("couldn't create the renderer!");
     let view = {
         let pos = Vec3::new(0.0, 0.0, -10.0);
         Mat4::look_at_rh(
@@ -77,13 +79,15 @@ fn main() {
             0.0f32, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0,
         ];
         let indices = [2u16, 3, 1, 2, 1, 0];
-        let id = renderer.gen_mesh(
-            size_of_val(&vertices) as u32,
-            size_of_val(&normals) as u32,
-            size_of_val(&tex_coords) as u32,
-            size_of_val(&indices) as u32,
-            indices.len() as u32,
-        );
+        let id = renderer
+            .gen_mesh(
+                size_of_val(&vertices) as u32,
+                size_of_val(&normals) as u32,
+                size_of_val(&tex_coords) as u32,
+                size_of_val(&indices) as u32,
+                indices.len() as u32,
+            )
+            .expect("couldn't generate the quad mesh!");
         let mesh = renderer.fetch_mesh(id).expect("missing mesh!");
         mesh.write_vertices(&vertices);
         mesh.write_normals(&normals);
@@ -132,24 +136,30 @@ fn main() {
     let quad_texture_mips = [MipMap {
         height: 2,
         width: 2,
+        depth: 1,
+        layers: 1,
         offset: 0,
         index: 0,
         size: 16,
     }];
-    let quad_texture_id = renderer.gen_texture(
-        "quad_texture".to_string(),
-        format::Format::R8G8B8A8_UNORM,
-        TextureKind::T2D,
-        &quad_texture_mips,
-        quad_texture_mips.iter().map(|e| e.size).sum(),
-    );
-    let quad_normal_id = renderer.gen_texture(
-        "quad_normal".to_string(),
-        format::Format::R8G8B8A8_UNORM,
-        TextureKind::T2D,
-        &quad_texture_mips,
-        quad_texture_mips.iter().map(|e| e.size).sum(),
-    );
+    let quad_texture_id = renderer
+        .gen_texture(
+            "quad_texture".to_string(),
+            format::Format::R8G8B8A8_UNORM,
+            TextureKind::T2D,
+            &quad_texture_mips,
+            quad_texture_mips.iter().map(|e| e.size).sum(),
+        )
+        .expect("couldn't generate the quad texture!");
+    let quad_normal_id = renderer
+        .gen_texture(
+            "quad_normal".to_string(),
+            format::Format::R8G8B8A8_UNORM,
+            TextureKind::T2D,
+            &quad_texture_mips,
+            quad_texture_mips.iter().map(|e| e.size).sum(),
+        )
+        .expect("couldn't generate the quad texture!");
     let quad_albedo = renderer
         .fetch_texture(quad_texture_id)
         .expect("missing texture!");
@@ -166,24 +176,32 @@ fn main() {
             std::ptr::copy_nonoverlapping(normals.as_ptr(), b.addr as *mut u32, normals.len());
         }
     }
-    renderer.queue_texture_for_uploading(quad_texture_id);
-    renderer.queue_texture_for_uploading(quad_normal_id);
+    renderer
+        .queue_texture_for_uploading(quad_texture_id)
+        .expect("missing texture!");
+    renderer
+        .queue_texture_for_uploading(quad_normal_id)
+        .expect("missing texture!");
 
-    let fullscreen_mesh_id = renderer.gen_mesh(3, 0, 0, 0, 3);
+    let fullscreen_mesh_id = renderer
+        .gen_mesh(3, 0, 0, 0, 3)
+        .expect("couldn't generate the fullscreen mesh!");
     let quad_mesh_id = gen_quad(&mut renderer);
 
-    let sampler_id = renderer.get_sampler(SamplerKey {
-        anisotropy: 1,
-        filter: pipeline::file::Filtering::Linear,
-        wrap_mode: pipeline::file::WrapMode::ClampToEdge,
-        compare_func: pipeline::file::CompareFunc::None,
-    });
+    let sampler_id = renderer
+        .get_sampler(SamplerKey {
+            anisotropy: 1,
+            filter: pipeline::file::Filtering::Linear,
+            wrap_mode: pipeline::file::WrapMode::ClampToEdge,
+            compare_func: pipeline::file::CompareFunc::None,
+        })
+        .expect("couldn't create the sampler!");
 
-    while !renderer.is_texture_uploaded(quad_texture_id)
-        || !renderer.is_texture_uploaded(quad_normal_id)
+    while !renderer.is_texture_uploaded(quad_texture_id).unwrap()
+        || !renderer.is_texture_uploaded(quad_normal_id).unwrap()
     {
         std::thread::sleep(std::time::Duration::from_millis(100));
-        renderer.render();
+        renderer.render().expect("render failed!");
     }
 
     let _ = window_context.event_loop(|| {
@@ -237,34 +255,40 @@ fn main() {
             ResourceKind::DirLight,
             rend_vk::shader_resource::SingleResource::DirLight(dir_light.clone()),
         );
-        renderer.add_task_to_queue(
-            render_task::RenderTask {
-                mesh_buffer_id: fullscreen_mesh_id,
-                instance_count: 1,
-                vertex_count: 3,
-                indices_offset: 0,
-                kind: render_task::TaskKind::Fullscreen,
-                resources: HashMap::new(),
-            },
-            0,
-        );
+        renderer
+            .add_task_to_queue(
+                render_task::RenderTask {
+                    mesh_buffer_id: fullscreen_mesh_id,
+                    instance_count: 1,
+                    vertex_count: 3,
+                    indices_offset: 0,
+                    kind: render_task::TaskKind::Fullscreen,
+                    resources: HashMap::new(),
+                    scissor: None,
+                },
+                0,
+            )
+            .expect("missing mesh!");
 
         let mut dir_light_res = HashMap::new();
         dir_light_res.insert(
             ResourceKind::DirLight,
             MultiResource::DirLight(vec![dir_light]),
         );
-        renderer.add_task_to_queue(
-            render_task::RenderTask {
-                mesh_buffer_id: fullscreen_mesh_id,
-                instance_count: 1,
-                vertex_count: 3,
-                indices_offset: 0,
-                kind: render_task::TaskKind::LightDir,
-                resources: dir_light_res,
-            },
-            0,
-        );
+        renderer
+            .add_task_to_queue(
+                render_task::RenderTask {
+                    mesh_buffer_id: fullscreen_mesh_id,
+                    instance_count: 1,
+                    vertex_count: 3,
+                    indices_offset: 0,
+                    kind: render_task::TaskKind::LightDir,
+                    resources: dir_light_res,
+                    scissor: None,
+                },
+                0,
+            )
+            .expect("missing mesh!");
 
         let mut quad_resources: HashMap<ResourceKind, MultiResource> = HashMap::new();
         let quad1_model = Mat4::from_scale_rotation_translation(
@@ -323,21 +347,24 @@ fn main() {
                         prev_model: model_mat,
                     }]),
                 );
-                renderer.add_task_to_queue(
-                    render_task::RenderTask {
-                        mesh_buffer_id: quad_mesh_id,
-                        instance_count: 1,
-                        vertex_count: 6,
-                        indices_offset: 0,
-                        kind,
-                        resources: task_res,
-                    },
-                    0,
-                );
+                renderer
+                    .add_task_to_queue(
+                        render_task::RenderTask {
+                            mesh_buffer_id: quad_mesh_id,
+                            instance_count: 1,
+                            vertex_count: 6,
+                            indices_offset: 0,
+                            kind,
+                            resources: task_res,
+                            scissor: None,
+                        },
+                        0,
+                    )
+                    .expect("missing mesh!");
             }
         }
 
-        renderer.render();
+        renderer.render().expect("render failed!");
         // renderer.get_current_frame() < 2
     });
     let mut renderer = renderer;
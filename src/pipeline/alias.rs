@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use ash::vk::{self, Extent2D};
+
+use super::{
+    attachment::Attachment,
+    barrier_gen::{BarrierGen, Image},
+    file::*,
+};
+use crate::{
+    context::VulkanContext,
+    error::{RendError, RendResult},
+    texture,
+};
+
+/// Allocation shared by targets whose lifetimes don't overlap
+struct Block {
+    memory_type_bits: u32,
+    size: vk::DeviceSize,
+    /// Indices of the candidates placed in it
+    members: Vec<usize>,
+}
+
+/// Places the candidates, given by their memory requirements and lifetimes, into blocks where
+/// no two lifetimes overlap. Memory type bits are compatible if a suitable memory type exists.
+fn pack_blocks(
+    candidates: &[(vk::MemoryRequirements, RangeInclusive<usize>)],
+    is_compatible: &dyn Fn(u32) -> bool,
+) -> Vec<Block> {
+    // Biggest first, so the smaller ones fill the gaps between their lifetimes
+    let mut order: Vec<_> = (0..candidates.len()).collect();
+    order.sort_by_key(|e| std::cmp::Reverse(candidates[*e].0.size));
+    let mut blocks: Vec<Block> = Vec::new();
+    for candidate in order {
+        let (requirements, lifetime) = &candidates[candidate];
+        let fits = |block: &Block| {
+            is_compatible(block.memory_type_bits & requirements.memory_type_bits)
+                && block.members.iter().all(|member| {
+                    let other = &candidates[*member].1;
+                    other.end() < lifetime.start() || lifetime.end() < other.start()
+                })
+        };
+        match blocks.iter_mut().find(|e| fits(e)) {
+            Some(block) => {
+                block.memory_type_bits &= requirements.memory_type_bits;
+                block.size = block.size.max(requirements.size);
+                block.members.push(candidate);
+            }
+            None => blocks.push(Block {
+                memory_type_bits: requirements.memory_type_bits,
+                size: requirements.size,
+                members: vec![candidate],
+            }),
+        }
+    }
+    blocks
+}
+
+fn mib_of(size: vk::DeviceSize) -> f64 {
+    size as f64 / (1024.0 * 1024.0)
+}
+
+impl Pipeline {
+    /// Range of passes each target is used in, only for the targets whose contents don't need
+    /// to outlive the frame, ie, every image of it gets overwritten before anything reads it.
+    pub(super) fn transient_lifetimes(&self) -> RendResult<HashMap<String, RangeInclusive<usize>>> {
+        let enabled_passes: Vec<_> = self.passes.iter().filter(|e| !e.is_disabled()).collect();
+        let barrier_gen = BarrierGen::new(&self.targets, &enabled_passes, &|e| self.state_of(e))?;
+        let mut lifetimes = HashMap::<String, RangeInclusive<usize>>::new();
+        let mut persistent = HashSet::new();
+        let mut written = HashSet::new();
+        for (i, (step, pass)) in enabled_passes.iter().zip(barrier_gen.passes()).enumerate() {
+            /*
+             * Reading before writing means it reads what the previous frame left, same with
+             * writing without clearing first. generateMips passes read the levels they write,
+             * so their targets never end up transient.
+             */
+            for image in &pass.inputs {
+                if !written.contains(&image.key()) {
+                    persistent.insert(image.name.as_str());
+                }
+            }
+            for image in &pass.outputs {
+                if !written.contains(&image.key()) && !self.overwrites(step, image)? {
+                    persistent.insert(image.name.as_str());
+                }
+            }
+            written.extend(pass.outputs.iter().map(|e| e.key()));
+            for image in pass.inputs.iter().chain(&pass.outputs) {
+                lifetimes
+                    .entry(image.name.clone())
+                    .and_modify(|e| *e = *e.start()..=i)
+                    .or_insert(i..=i);
+            }
+        }
+        // History targets are read in the next frame too
+        lifetimes.retain(|name, _| {
+            !persistent.contains(name.as_str())
+                && self.targets.iter().any(|t| &t.name == name && !t.history)
+        });
+        Ok(lifetimes)
+    }
+
+    /// If the pass discards what the image had before writing it
+    fn overwrites(&self, step: &PipelineStep, image: &Image) -> RendResult<bool> {
+        match step {
+            PipelineStep::Render(p) => {
+                // Resolves always write the whole target
+                let resolve = Some(image.name.as_str());
+                if p.outputs
+                    .iter()
+                    .any(|e| e.get().resolve.as_deref() == resolve)
+                {
+                    return Ok(true);
+                }
+                let clearing = Self::handle_option(self.state_of(&p.state)?.clearing)?;
+                let is_depth_or_stencil = self
+                    .targets
+                    .iter()
+                    .find(|t| t.name == image.name)
+                    .is_some_and(|t| t.format.has_depth_or_stencil());
+                if is_depth_or_stencil {
+                    Ok(clearing.to_vk_depth_stencil().is_some())
+                } else {
+                    Ok(clearing.to_vk_color().is_some())
+                }
+            }
+            // Blits and dispatches are expected to cover the whole target
+            PipelineStep::Blit(_) | PipelineStep::Compute(_) => Ok(true),
+            PipelineStep::GenerateMips(_) | PipelineStep::Include(_) | PipelineStep::Repeat(_) => {
+                Ok(false)
+            }
+        }
+    }
+
+    /// Makes the attachments of the targets in the same order. Targets with a lifetime that
+    /// doesn't overlap with another's get placed in the same memory, returned separately
+    /// since it's owned by the pipeline rather than by any of the attachments.
+    /// Nothing made is kept on failure.
+    pub(super) fn make_attachments(
+        ctx: &VulkanContext,
+        targets: &[&Target],
+        lifetimes: &HashMap<String, RangeInclusive<usize>>,
+        internal_extent: Extent2D,
+        external_extent: Extent2D,
+    ) -> RendResult<(Vec<Attachment>, Vec<vk::DeviceMemory>)> {
+        let mut made = MadeAttachments {
+            attachments: targets.iter().map(|_| None).collect(),
+            unbound_images: HashMap::new(),
+            block_memory: Vec::new(),
+        };
+        let result = Self::make_attachments_into(
+            ctx,
+            targets,
+            lifetimes,
+            internal_extent,
+            external_extent,
+            &mut made,
+        );
+        if let Err(err) = result {
+            made.destroy(&ctx.device);
+            return Err(err);
+        }
+        let mut aliased_memory = Vec::new();
+        for (memory, members) in made.block_memory {
+            // Alone in its block, the attachment can own the memory same as any other
+            if members.len() > 1 {
+                aliased_memory.push(memory);
+            } else {
+                for i in members {
+                    if let Some(attachment) = &mut made.attachments[i] {
+                        attachment.is_aliased = false;
+                    }
+                }
+            }
+        }
+        let attachments = made.attachments.into_iter().flatten().collect();
+        Ok((attachments, aliased_memory))
+    }
+
+    fn make_attachments_into(
+        ctx: &VulkanContext,
+        targets: &[&Target],
+        lifetimes: &HashMap<String, RangeInclusive<usize>>,
+        internal_extent: Extent2D,
+        external_extent: Extent2D,
+        made: &mut MadeAttachments,
+    ) -> RendResult<()> {
+        let extents: Vec<_> = targets
+            .iter()
+            .map(|f| {
+                Self::attachment_extent_of(f.width, f.height, internal_extent, external_extent)
+            })
+            .collect();
+        let mut candidates = Vec::new();
+        for (i, f) in targets.iter().enumerate() {
+            let Some(lifetime) = lifetimes.get(&f.name) else {
+                made.attachments[i] = Some(Self::make_attachment(ctx, f, extents[i])?);
+                continue;
+            };
+            let image = texture::image_of_usage(
+                ctx,
+                extents[i].into(),
+                f.level,
+                f.layers as u32,
+                f.format,
+                f.kind(),
+                Self::attachment_usage_of(f),
+                f.sample_count(),
+            )?;
+            let (requirements, requires_dedicated) = texture::memory_requirements_of(ctx, image);
+            if requires_dedicated {
+                unsafe { ctx.device.destroy_image(image, None) };
+                made.attachments[i] = Some(Self::make_attachment(ctx, f, extents[i])?);
+                continue;
+            }
+            made.unbound_images.insert(i, image);
+            candidates.push((i, image, requirements, lifetime.clone()));
+        }
+
+        let sizes: Vec<_> = candidates
+            .iter()
+            .map(|(_, _, requirements, lifetime)| (*requirements, lifetime.clone()))
+            .collect();
+        let blocks = pack_blocks(&sizes, &|memory_type_bits| {
+            ctx.memory_type_index_for(memory_type_bits, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+                .is_some()
+        });
+
+        let (mut separate_size, mut aliased_size) = (0, 0);
+        for block in &blocks {
+            // Blocks only get compatible memory types
+            let memory_type_index = ctx
+                .memory_type_index_for(
+                    block.memory_type_bits,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+                .unwrap_or_default();
+            let memory_allocate_info = vk::MemoryAllocateInfo::default()
+                .allocation_size(block.size)
+                .memory_type_index(memory_type_index);
+            let memory = unsafe { ctx.device.allocate_memory(&memory_allocate_info, None) }
+                .map_err(|e| RendError::vulkan(e, "allocating aliased attachment memory"))?;
+            let members: Vec<_> = block.members.iter().map(|e| candidates[*e].0).collect();
+            made.block_memory.push((memory, members));
+            for (i, image, requirements, _) in block.members.iter().map(|e| &candidates[*e]) {
+                let f = targets[*i];
+                let texture = texture::bind_texture(
+                    ctx,
+                    f.name.clone(),
+                    *image,
+                    memory,
+                    f.level,
+                    f.layers as u32,
+                    f.format,
+                    f.kind(),
+                )?;
+                let attachment =
+                    Self::attachment_of(ctx, f, extents[*i], &texture).inspect_err(|_| unsafe {
+                        ctx.device.destroy_image_view(texture.view, None)
+                    })?;
+                // Block memory is released separately until everything is made
+                made.attachments[*i] = Some(Attachment {
+                    is_aliased: true,
+                    ..attachment
+                });
+                made.unbound_images.remove(i);
+                separate_size += requirements.size;
+            }
+            aliased_size += block.size;
+            if block.members.len() > 1 {
+                let names: Vec<_> = block
+                    .members
+                    .iter()
+                    .map(|e| targets[candidates[*e].0].name.as_str())
+                    .collect();
+                log::info!(
+                    "targets {} share {:.2} MiB of memory",
+                    names.join(", "),
+                    mib_of(block.size)
+                );
+            }
+        }
+        if !candidates.is_empty() {
+            log::info!(
+                "transient targets take {:.2} MiB instead of {:.2} MiB, saving {:.2} MiB",
+                mib_of(aliased_size),
+                mib_of(separate_size),
+                mib_of(separate_size - aliased_size)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Everything made so far while making attachments
+struct MadeAttachments {
+    attachments: Vec<Option<Attachment>>,
+    /// Images of the candidates for aliasing by target index, until they're bound into one
+    unbound_images: HashMap<usize, vk::Image>,
+    /// Memory of each block with the target indices placed in it
+    block_memory: Vec<(vk::DeviceMemory, Vec<usize>)>,
+}
+
+impl MadeAttachments {
+    fn destroy(&self, device: &ash::Device) {
+        for attachment in self.attachments.iter().flatten() {
+            attachment.destroy(device);
+        }
+        unsafe {
+            for image in self.unbound_images.values() {
+                device.destroy_image(*image, None);
+            }
+            for (memory, _) in &self.block_memory {
+                device.free_memory(*memory, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use ash::vk;
+    use serde_json::json;
+
+    use super::{super::file::Pipeline, pack_blocks};
+
+    fn candidate(
+        size: vk::DeviceSize,
+        lifetime: RangeInclusive<usize>,
+    ) -> (vk::MemoryRequirements, RangeInclusive<usize>) {
+        let requirements = vk::MemoryRequirements {
+            size,
+            alignment: 1,
+            memory_type_bits: 1,
+        };
+        (requirements, lifetime)
+    }
+
+    #[test]
+    fn transient_lifetimes_span_first_to_last_use() {
+        let targets: Vec<_> = ["src", "a", "b", "accumulated"]
+            .iter()
+            .map(|e| json!({ "name": e, "format": "R8G8B8A8_UNORM", "width": 1.0, "height": 1.0 }))
+            .collect();
+        let rect = json!({ "x": 0, "y": 0, "width": 1.0, "height": 1.0 });
+        let blit = |name: &str, input: &str, output: &str| {
+            json!({
+                "type": "blit",
+                "name": name,
+                "input": input,
+                "inputRect": rect,
+                "output": output,
+                "outputRect": rect,
+                "filter": "LINEAR",
+                "attributes": ["COLOR"],
+            })
+        };
+        let pip: Pipeline = serde_json::from_value(json!({
+            "targets": targets,
+            "programs": [{ "name": "copy", "vertex": "fullscreen.vert", "fragment": "copy.frag" }],
+            "sharedState": {
+                "accumulate": {
+                    "writing": "COLOR",
+                    "depth": "NO",
+                    "scissor": "DEFAULT",
+                    "viewport": "DEFAULT",
+                    "stencil": "NO",
+                    "triangle": "DEFAULT",
+                    "blending": "NO",
+                    "clearing": "NO",
+                },
+            },
+            "passes": [
+                blit("first", "src", "a"),
+                blit("second", "a", "b"),
+                blit("present", "b", "default"),
+                {
+                    "type": "render",
+                    "name": "accumulate",
+                    "program": "copy",
+                    "batch": "FULLSCREEN",
+                    "outputs": ["accumulated"],
+                    "inputs": [{ "name": "a", "sampler": "DEFAULT" }],
+                    "perPassUpdaters": [],
+                    "perInstanceUpdaters": [],
+                    "state": { "type": "reference", "name": "accumulate" },
+                },
+            ],
+        }))
+        .unwrap();
+        let lifetimes = pip.transient_lifetimes().unwrap();
+        let mut names: Vec<_> = lifetimes.keys().map(|e| e.as_str()).collect();
+        names.sort();
+        // Read before written and not cleared before writing respectively
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(lifetimes["a"], 0..=3);
+        assert_eq!(lifetimes["b"], 1..=2);
+    }
+
+    #[test]
+    fn packs_smaller_candidates_into_gaps() {
+        let candidates = [
+            candidate(100, 0..=1),
+            candidate(50, 2..=3),
+            candidate(80, 1..=2),
+        ];
+        let blocks = pack_blocks(&candidates, &|bits| bits != 0);
+        let members: Vec<_> = blocks.iter().map(|e| e.members.clone()).collect();
+        assert_eq!(members, [vec![0, 1], vec![2]]);
+        let sizes: Vec<_> = blocks.iter().map(|e| e.size).collect();
+        assert_eq!(sizes, [100, 80]);
+    }
+
+    #[test]
+    fn never_packs_overlapping_lifetimes_together() {
+        let candidates: Vec<_> = (0..32)
+            .map(|i| candidate(1 + (i * 7 % 11) as u64, i % 5..=i % 5 + i % 3))
+            .collect();
+        let blocks = pack_blocks(&candidates, &|bits| bits != 0);
+        let mut placed: Vec<_> = blocks.iter().flat_map(|e| e.members.clone()).collect();
+        placed.sort();
+        assert_eq!(placed, (0..candidates.len()).collect::<Vec<_>>());
+        for block in &blocks {
+            for a in &block.members {
+                for b in block.members.iter().filter(|e| *e != a) {
+                    let (a, b) = (&candidates[*a].1, &candidates[*b].1);
+                    assert!(a.end() < b.start() || b.end() < a.start());
+                }
+            }
+            let biggest = block.members.iter().map(|e| candidates[*e].0.size).max();
+            assert_eq!(Some(block.size), biggest);
+        }
+    }
+
+    #[test]
+    fn never_packs_incompatible_memory_types_together() {
+        let mut candidates = [candidate(100, 0..=0), candidate(100, 1..=1)];
+        candidates[1].0.memory_type_bits = 2;
+        let blocks = pack_blocks(&candidates, &|bits| bits != 0);
+        assert_eq!(blocks.len(), 2);
+    }
+}
@@ -1,6 +1,13 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
-use crate::{context::VulkanContext, format::Format};
+use crate::{
+    context::VulkanContext,
+    error::{RendError, RendResult},
+    format::Format,
+    texture::TextureKind,
+};
 
 #[derive(Clone)]
 pub struct Attachment {
@@ -12,9 +19,22 @@ pub struct Attachment {
     pub image: vk::Image,
     pub view: vk::ImageView,
     pub per_level_views: Vec<vk::ImageView>,
+    /// Every level of a single layer, only present for layered attachments
+    pub per_layer_views: Vec<vk::ImageView>,
+    /// Single level of a single layer by `level * layers + layer`, only for layered attachments
+    pub per_level_layer_views: Vec<vk::ImageView>,
+    /// Every layer of a single level as a plain array, only for cube attachments
+    /// since cube views can't be rendered into
+    pub per_level_array_views: Vec<vk::ImageView>,
     pub level_usage: u8,
+    /// Layer the pass uses, all of them if none
+    pub layer_usage: Option<u8>,
+    pub layers: u8,
     pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
     pub descriptor_index: u32,
+    /// Memory is shared with other attachments and owned by the pipeline instead
+    pub is_aliased: bool,
 }
 
 impl Attachment {
@@ -32,9 +52,34 @@ impl Attachment {
     }
 
     pub fn usage_view(&self) -> vk::ImageView {
-        match crate::texture::MipMap::is_all_levels_value(self.level_usage) {
-            true => self.view,                              // all levels view
-            false => self.per_level_view(self.level_usage), // specific level view
+        let is_all_levels = crate::texture::MipMap::is_all_levels_value(self.level_usage);
+        // Non layered attachments have a single layer, same as using all of them
+        match (is_all_levels, self.layer_usage.filter(|_| self.layers > 1)) {
+            (true, None) => self.view,                              // all levels view
+            (false, None) => self.per_level_view(self.level_usage), // specific level view
+            (true, Some(layer)) => self.per_layer_views[layer as usize],
+            (false, Some(layer)) => {
+                let index = self.level_usage as usize * self.layers as usize + layer as usize;
+                self.per_level_layer_views[index]
+            }
+        }
+    }
+
+    /// View to render or store into, same as the usage view except for whole cubes
+    pub fn render_view(&self) -> vk::ImageView {
+        match self.layer_usage {
+            None if !self.per_level_array_views.is_empty() => {
+                self.per_level_array_views[self.level_usage as usize]
+            }
+            _ => self.usage_view(),
+        }
+    }
+
+    /// First layer and layer count the pass uses
+    pub fn usage_layers(&self) -> (u32, u32) {
+        match self.layer_usage {
+            Some(layer) => (layer as u32, 1),
+            None => (0, self.layers as u32),
         }
     }
 
@@ -50,14 +95,38 @@ impl Attachment {
         }
     }
 
+    /// Same usage of the attachment named like this one in the map, if it's there
+    pub fn resized(&self, attachments_by_name: &HashMap<String, Attachment>) -> Attachment {
+        match attachments_by_name.get(&self.name) {
+            Some(e) => Attachment {
+                name: self.name.clone(),
+                level_usage: self.level_usage,
+                layer_usage: self.layer_usage,
+                descriptor_index: self.descriptor_index,
+                ..e.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
     pub fn destroy(&self, device: &ash::Device) {
-        if self.is_default() {
-            // Default attachments are owned by the swapchain
+        if self.is_default() && self.memory == vk::DeviceMemory::null() {
+            // Default attachments are owned by the swapchain, unless rendering headless
             return;
         }
         unsafe {
-            device.free_memory(self.memory, None);
+            if !self.is_aliased {
+                device.free_memory(self.memory, None);
+            }
             device.destroy_image_view(self.view, None);
+            for view in self
+                .per_layer_views
+                .iter()
+                .chain(&self.per_level_layer_views)
+                .chain(&self.per_level_array_views)
+            {
+                device.destroy_image_view(*view, None);
+            }
             for view in &self.per_level_views {
                 let view = *view;
                 /*
@@ -77,28 +146,97 @@ impl Attachment {
         image: vk::Image,
         format: Format,
         levels: u8,
-    ) -> Vec<vk::ImageView> {
-        let vk_format = format.to_vk();
-        let infos = (0..levels).map(|l| {
-            vk::ImageViewCreateInfo::default()
-                .subresource_range(
-                    vk::ImageSubresourceRange::default()
-                        .aspect_mask(format.aspect())
-                        .base_mip_level(l as u32)
-                        .level_count(1)
-                        .layer_count(1),
-                )
-                .image(image)
-                .format(vk_format)
-                .view_type(vk::ImageViewType::TYPE_2D)
-        });
-        infos
-            .map(|info| unsafe {
-                ctx.device
-                    .create_image_view(&info, None)
-                    .expect("failed creating image view")
-            })
-            .collect()
+        layers: u8,
+        kind: TextureKind,
+    ) -> RendResult<Vec<vk::ImageView>> {
+        let view_type = kind.view_type_of(layers as u32);
+        Self::views_of(
+            ctx,
+            image,
+            format,
+            (0..levels).map(|l| (view_type, (l as u32, 1), (0, layers as u32))),
+        )
+    }
+
+    /// Views of every level of every layer, then of every single level of every layer.
+    /// Both empty if there is only one layer, the regular views work for it.
+    pub fn per_layer_views_of(
+        ctx: &VulkanContext,
+        image: vk::Image,
+        format: Format,
+        levels: u8,
+        layers: u8,
+    ) -> RendResult<(Vec<vk::ImageView>, Vec<vk::ImageView>)> {
+        if layers < 2 {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let view_type = vk::ImageViewType::TYPE_2D;
+        let per_layer = Self::views_of(
+            ctx,
+            image,
+            format,
+            (0..layers).map(|e| (view_type, (0, levels as u32), (e as u32, 1))),
+        )?;
+        let per_level_layer = Self::views_of(
+            ctx,
+            image,
+            format,
+            (0..levels)
+                .flat_map(|l| (0..layers).map(move |e| (l, e)))
+                .map(|(l, e)| (view_type, (l as u32, 1), (e as u32, 1))),
+        )
+        .inspect_err(|_| Self::destroy_views(&ctx.device, &per_layer))?;
+        Ok((per_layer, per_level_layer))
+    }
+
+    /// Creates a view per (type, levels, layers) range, none are left if any fails
+    fn views_of(
+        ctx: &VulkanContext,
+        image: vk::Image,
+        format: Format,
+        ranges: impl Iterator<Item = (vk::ImageViewType, (u32, u32), (u32, u32))>,
+    ) -> RendResult<Vec<vk::ImageView>> {
+        let mut views = Vec::new();
+        for (view_type, levels, layers) in ranges {
+            match Self::view_of(ctx, image, format, view_type, levels, layers) {
+                Ok(view) => views.push(view),
+                Err(err) => {
+                    Self::destroy_views(&ctx.device, &views);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(views)
+    }
+
+    pub fn destroy_views(device: &ash::Device, views: &[vk::ImageView]) {
+        for view in views {
+            unsafe { device.destroy_image_view(*view, None) };
+        }
+    }
+
+    fn view_of(
+        ctx: &VulkanContext,
+        image: vk::Image,
+        format: Format,
+        view_type: vk::ImageViewType,
+        (base_mip_level, level_count): (u32, u32),
+        (base_array_layer, layer_count): (u32, u32),
+    ) -> RendResult<vk::ImageView> {
+        let info = vk::ImageViewCreateInfo::default()
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(format.aspect())
+                    .base_mip_level(base_mip_level)
+                    .level_count(level_count)
+                    .base_array_layer(base_array_layer)
+                    .layer_count(layer_count),
+            )
+            .image(image)
+            .format(format.to_vk())
+            .view_type(view_type);
+        unsafe { ctx.device.create_image_view(&info, None) }
+            .map_err(|e| RendError::vulkan(e, "creating an attachment view"))
     }
 
     pub fn default_attachment_of(
@@ -115,9 +253,16 @@ impl Attachment {
             name: Attachment::DEFAULT_NAME.to_string(),
             view: image_view,
             per_level_views: [image_view].into(),
+            per_layer_views: Vec::new(),
+            per_level_layer_views: Vec::new(),
+            per_level_array_views: Vec::new(),
             level_usage: 0,
+            layer_usage: None,
+            layers: 1,
             extent,
+            samples: vk::SampleCountFlags::TYPE_1,
             descriptor_index: 0,
+            is_aliased: false,
         }
     }
 
@@ -144,14 +289,17 @@ impl Attachment {
             .subresource_range(Self::color_subresource_range())
     }
 
-    pub fn default_attachment_present_barrier<'a>(image: vk::Image) -> vk::ImageMemoryBarrier2<'a> {
+    pub fn default_attachment_present_barrier<'a>(
+        image: vk::Image,
+        final_layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier2<'a> {
         vk::ImageMemoryBarrier2::default()
             .image(image)
             .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
             // None is the expected access mask for presenting
             .dst_access_mask(vk::AccessFlags2::NONE)
             .old_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
-            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(final_layout)
             .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
             .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
             .subresource_range(Self::color_subresource_range())
@@ -173,6 +321,7 @@ impl Attachment {
 
     pub fn default_attachment_blit_present_barrier<'a>(
         image: vk::Image,
+        final_layout: vk::ImageLayout,
     ) -> vk::ImageMemoryBarrier2<'a> {
         vk::ImageMemoryBarrier2::default()
             .image(image)
@@ -180,7 +329,7 @@ impl Attachment {
             // None is the expected access mask for presenting
             .dst_access_mask(vk::AccessFlags2::NONE)
             .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(final_layout)
             .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
             .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
             .subresource_range(Self::color_subresource_range())
@@ -210,12 +359,22 @@ impl Attachment {
         aspect: vk::ImageAspectFlags,
         base: u32,
         count: u32,
+    ) -> vk::ImageSubresourceRange {
+        Self::subresource_range_wlayers(aspect, base, count, 0, 1)
+    }
+
+    pub fn subresource_range_wlayers(
+        aspect: vk::ImageAspectFlags,
+        base: u32,
+        count: u32,
+        base_layer: u32,
+        layer_count: u32,
     ) -> vk::ImageSubresourceRange {
         vk::ImageSubresourceRange::default()
             .aspect_mask(aspect)
             .base_mip_level(base)
             .level_count(count)
-            .base_array_layer(0)
-            .layer_count(1)
+            .base_array_layer(base_layer)
+            .layer_count(layer_count)
     }
 }
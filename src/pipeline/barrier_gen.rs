@@ -2,44 +2,159 @@ use std::{collections::HashMap, ops::Range};
 
 use ash::vk;
 
-use crate::texture::MipMap;
+use crate::{error::RendResult, texture::MipMap};
 
 use super::{
     attachment::Attachment,
+    buffer::PipelineBuffer,
     file::{AttachmentFile, BaseState, DescHandler, Pipeline, PipelineStep, State, Target},
 };
 
 #[derive(Clone)]
-struct Image {
-    name: String,
-    level: u8,
+pub(super) struct Image {
+    pub name: String,
+    pub level: u8,
+    pub layer: u8,
 }
 
 impl Image {
-    pub fn of_attachment(f: &impl super::file::AttachmentFile) -> Self {
-        Self {
-            level: f.level().get(),
-            name: f.name().to_string(),
+    pub fn key(&self) -> (&str, u8, u8) {
+        (&self.name, self.level, self.layer)
+    }
+
+    /// One image per level and layer the attachment refers to
+    fn all_of(name: &str, levels: Range<u8>, layers: Range<u8>) -> Vec<Self> {
+        levels
+            .flat_map(|level| {
+                layers.clone().map(move |layer| Self {
+                    name: name.to_string(),
+                    level,
+                    layer,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub(super) enum PassKind {
+    #[default]
+    Render,
+    Blit,
+    Compute,
+}
+
+impl PassKind {
+    fn layout_of(self, is_output: bool) -> vk::ImageLayout {
+        match (self, is_output) {
+            // Blitting outputs require "transfer dst", inputs "transfer src"
+            (PassKind::Blit, true) => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            (PassKind::Blit, false) => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            // Render outputs require "attachment", inputs "read only"
+            (PassKind::Render, true) => vk::ImageLayout::ATTACHMENT_OPTIMAL,
+            // Compute outputs are storage images, these can only be in "general"
+            (PassKind::Compute, true) => vk::ImageLayout::GENERAL,
+            // Both render and compute inputs are sampled
+            (_, false) => vk::ImageLayout::READ_ONLY_OPTIMAL,
+        }
+    }
+
+    fn src_stage_of_input(self, has_depth_or_stencil: bool) -> vk::PipelineStageFlags2 {
+        match self {
+            PassKind::Blit => vk::PipelineStageFlags2::TRANSFER,
+            PassKind::Compute => vk::PipelineStageFlags2::COMPUTE_SHADER,
+            PassKind::Render if has_depth_or_stencil => {
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+            }
+            PassKind::Render => vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        }
+    }
+
+    fn dst_stage_of_input(self, has_depth_or_stencil: bool) -> vk::PipelineStageFlags2 {
+        match self {
+            PassKind::Blit => vk::PipelineStageFlags2::TRANSFER,
+            PassKind::Compute => vk::PipelineStageFlags2::COMPUTE_SHADER,
+            PassKind::Render if has_depth_or_stencil => {
+                vk::PipelineStageFlags2::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+            }
+            PassKind::Render => vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        }
+    }
+
+    fn src_stage_of_output(self, has_depth_or_stencil: bool) -> vk::PipelineStageFlags2 {
+        match self {
+            PassKind::Blit => vk::PipelineStageFlags2::TRANSFER,
+            PassKind::Compute => vk::PipelineStageFlags2::COMPUTE_SHADER,
+            PassKind::Render if has_depth_or_stencil => {
+                vk::PipelineStageFlags2::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+            }
+            PassKind::Render => vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        }
+    }
+
+    fn dst_stage_of_output(self, has_depth_or_stencil: bool) -> vk::PipelineStageFlags2 {
+        match self {
+            PassKind::Blit => vk::PipelineStageFlags2::TRANSFER,
+            PassKind::Compute => vk::PipelineStageFlags2::COMPUTE_SHADER,
+            PassKind::Render if has_depth_or_stencil => {
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+            }
+            PassKind::Render => vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        }
+    }
+
+    fn buffer_stage(self) -> vk::PipelineStageFlags2 {
+        match self {
+            PassKind::Blit => vk::PipelineStageFlags2::TRANSFER,
+            PassKind::Compute => vk::PipelineStageFlags2::COMPUTE_SHADER,
+            // Any shader of the program may access it, draws may take their arguments from it
+            PassKind::Render => {
+                vk::PipelineStageFlags2::DRAW_INDIRECT
+                    | vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS
+                    | vk::PipelineStageFlags2::FRAGMENT_SHADER
+            }
         }
     }
 }
 
 #[derive(Clone)]
-struct Pass {
-    name: String,
-    inputs: Vec<Image>,
-    outputs: Vec<Image>,
-    is_blitting: bool,
+pub(super) struct Pass {
+    pub name: String,
+    pub inputs: Vec<Image>,
+    pub outputs: Vec<Image>,
+    pub input_buffers: Vec<String>,
+    pub output_buffers: Vec<String>,
+    pub kind: PassKind,
+}
+
+/// Device independent description of a barrier the generator emits
+pub(super) struct BarrierDesc {
+    pub image: Image,
+    pub is_output: bool,
+    /// Index of the pass the image was last used in
+    pub src_pass: usize,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
 }
 
 #[derive(Clone)]
 pub struct BarrierGen {
     passes: Vec<Pass>,
     levels_by_owner: HashMap<String, u8>,
+    layers_by_owner: HashMap<String, u8>,
+    /// History targets and the target they get swapped with every frame
+    history_partners: HashMap<String, String>,
 }
 
 #[derive(Default)]
 struct BarrierEval {
+    src_kind: PassKind,
     src_access: vk::AccessFlags2,
     old_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
@@ -49,11 +164,13 @@ struct BarrierEval {
 
 impl BarrierEval {
     fn of(
+        src_kind: PassKind,
         src_access: vk::AccessFlags2,
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
     ) -> Self {
         Self {
+            src_kind,
             src_access,
             old_layout,
             new_layout,
@@ -74,86 +191,127 @@ impl BarrierEval {
             ..Default::default()
         }
     }
-
-    fn was_blitting(&self) -> bool {
-        self.old_layout == vk::ImageLayout::TRANSFER_SRC_OPTIMAL
-            || self.old_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-    }
 }
 
 impl BarrierGen {
     pub fn new(
         targets: &[Target],
-        passes: &[PipelineStep],
-        resolve_state: &dyn Fn(&BaseState) -> State,
-    ) -> Self {
+        passes: &[&PipelineStep],
+        resolve_state: &dyn Fn(&BaseState) -> RendResult<State>,
+    ) -> RendResult<Self> {
         let levels_by_owner = targets
             .iter()
             .map(|t| (t.name.clone(), t.level))
             .collect::<HashMap<_, _>>();
+        let layers_by_owner = targets
+            .iter()
+            .map(|t| (t.name.clone(), t.image_layers()))
+            .collect::<HashMap<_, _>>();
+        let images_of = |f: &dyn AttachmentFile| {
+            Image::all_of(
+                f.name(),
+                Self::level_range_for(f.name(), f.level().get(), &levels_by_owner),
+                Self::layer_range_for(f.name(), f.layer(), &layers_by_owner),
+            )
+        };
         let tmp = passes
             .iter()
-            .map(|p| match p {
-                PipelineStep::Render(p) => {
-                    let mut outputs: Vec<_> = p
-                        .outputs
-                        .iter()
-                        .map(|e| e.get())
-                        .map(|i| Image::of_attachment(&i))
-                        .collect();
-                    let mut inputs = Vec::with_capacity(p.inputs.len());
-                    for input in p.inputs.iter().map(|i| i.get()) {
-                        for lvl in
-                            Self::level_range_for(&input.name, input.level.get(), &levels_by_owner)
-                        {
-                            inputs.push(Image {
-                                level: lvl,
-                                name: input.name().to_string(),
-                            });
+            .map(|p| -> RendResult<Pass> {
+                Ok(match p {
+                    PipelineStep::Render(p) => {
+                        let mut outputs = Vec::with_capacity(p.outputs.len());
+                        for output in p.outputs.iter().map(|e| e.get()) {
+                            outputs.extend(images_of(&output));
+                            // Resolve targets get written at the end of the pass, same as outputs
+                            if let Some(resolve) = &output.resolve {
+                                let layers =
+                                    Self::layer_range_for(resolve, output.layer, &layers_by_owner);
+                                outputs.extend(Image::all_of(resolve, 0..1, layers));
+                            }
                         }
-                    }
-                    // Depth stencil attachment requires some special checks
-                    if let Some(d) = &p.depth_stencil {
-                        let state = resolve_state(&p.state);
-                        let writing = Pipeline::handle_option(state.writing);
-                        let depth_img = Image {
-                            name: d.clone(),
-                            level: 0,
-                        };
-                        if writing.depth {
-                            // Writes depth, interpret it as an output from the pass
-                            outputs.push(depth_img);
-                        } else {
-                            /*
-                             * Assume it's only depth testing, interpret it as an input,
-                             * checking if it isn't already being sampled in the same pass.
-                             */
-                            if !inputs.iter().any(|e| d == &e.name) {
-                                inputs.push(depth_img);
+                        let mut inputs = Vec::with_capacity(p.inputs.len());
+                        for input in p.inputs.iter().map(|i| i.get()) {
+                            inputs.extend(images_of(&input));
+                        }
+                        // Depth stencil attachment requires some special checks
+                        if let Some(d) = &p.depth_stencil {
+                            let d = d.get();
+                            let state = resolve_state(&p.state)?;
+                            let writing = Pipeline::handle_option(state.writing)?;
+                            let depth_imgs = images_of(&d);
+                            if writing.depth {
+                                // Writes depth, interpret it as an output from the pass
+                                outputs.extend(depth_imgs);
+                            } else {
+                                /*
+                                 * Assume it's only depth testing, interpret it as an input,
+                                 * checking if it isn't already being sampled in the same pass.
+                                 */
+                                if !inputs.iter().any(|e| d.name == e.name) {
+                                    inputs.extend(depth_imgs);
+                                }
                             }
                         }
+                        Pass {
+                            name: p.name.clone(),
+                            kind: PassKind::Render,
+                            inputs,
+                            outputs,
+                            input_buffers: p.input_buffers.clone(),
+                            output_buffers: p.output_buffers.clone(),
+                        }
                     }
-                    Pass {
+                    // Blit pass has only one input/output, re-represent as single item vecs
+                    PipelineStep::Blit(p) => Pass {
                         name: p.name.clone(),
-                        is_blitting: false,
-                        inputs,
-                        outputs,
+                        kind: PassKind::Blit,
+                        inputs: images_of(&p.input.get()),
+                        outputs: images_of(&p.output.get()),
+                        input_buffers: Vec::new(),
+                        output_buffers: Vec::new(),
+                    },
+                    /*
+                     * Every level but the last is blitted from, every level but the first is blitted
+                     * into. The stage leaves the whole chain as transfer src, so as a previous pass
+                     * every level is an input, and as the current one only the first level is.
+                     */
+                    PipelineStep::GenerateMips(p) => {
+                        let levels = *levels_by_owner.get(&p.target).unwrap_or_else(|| {
+                            panic!("levels for attachment '{}' not found!", p.target)
+                        });
+                        // Mips get generated for every layer at once
+                        let layers = Self::layer_range_for(&p.target, None, &layers_by_owner);
+                        Pass {
+                            name: p.name.clone(),
+                            kind: PassKind::Blit,
+                            inputs: Image::all_of(&p.target, 0..levels, layers.clone()),
+                            outputs: Image::all_of(&p.target, 1..levels, layers),
+                            input_buffers: Vec::new(),
+                            output_buffers: Vec::new(),
+                        }
                     }
-                }
-                // Blit pass has only one input/output, re-represent as single item vecs
-                PipelineStep::Blit(p) => Pass {
-                    name: p.name.clone(),
-                    is_blitting: true,
-                    inputs: [Image::of_attachment(&p.input.get())].into(),
-                    outputs: [Image::of_attachment(&p.output.get())].into(),
-                },
-                _ => panic!("unsupported pipeline step!"),
+                    PipelineStep::Compute(p) => Pass {
+                        name: p.name.clone(),
+                        kind: PassKind::Compute,
+                        inputs: p.inputs.iter().flat_map(|e| images_of(&e.get())).collect(),
+                        outputs: p.outputs.iter().flat_map(|e| images_of(&e.get())).collect(),
+                        input_buffers: p.input_buffers.clone(),
+                        output_buffers: p.output_buffers.clone(),
+                    },
+                    _ => panic!("unsupported pipeline step!"),
+                })
             })
+            .collect::<RendResult<_>>()?;
+        let history_partners = targets
+            .iter()
+            .filter_map(|t| Some((t.name.clone(), t.history_partner()?)))
             .collect();
-        BarrierGen {
+        Ok(BarrierGen {
             passes: tmp,
             levels_by_owner,
-        }
+            layers_by_owner,
+            history_partners,
+        })
     }
 
     fn level_range_for(
@@ -161,14 +319,24 @@ impl BarrierGen {
         level_usage: u8,
         levels_by_owner: &HashMap<String, u8>,
     ) -> Range<u8> {
-        let owner_levels = match levels_by_owner.get(name) {
-            Some(r) => *r,
+        if !MipMap::is_all_levels_value(level_usage) {
+            return level_usage..level_usage + 1;
+        }
+        match levels_by_owner.get(name) {
+            Some(r) => 0..*r,
             None => panic!("levels for attachment '{}' not found!", name),
-        };
-        if MipMap::is_all_levels_value(level_usage) {
-            0..owner_levels
-        } else {
-            level_usage..level_usage + 1
+        }
+    }
+
+    fn layer_range_for(
+        name: &str,
+        layer_usage: Option<u8>,
+        layers_by_owner: &HashMap<String, u8>,
+    ) -> Range<u8> {
+        match layer_usage {
+            Some(layer) => layer..layer + 1,
+            // The default attachment isn't a target, it only has the one layer
+            None => 0..*layers_by_owner.get(name).unwrap_or(&1),
         }
     }
 
@@ -176,77 +344,54 @@ impl BarrierGen {
         prev_pass: &Pass,
         name: &str,
         level: u8,
-        current_is_blitting: bool,
+        layer: u8,
+        current_kind: PassKind,
         is_output: bool,
     ) -> BarrierEval {
-        let new_layout = if current_is_blitting {
-            // Blitting outputs require "transfer dst", inputs "transfer src"
-            if is_output {
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL
-            } else {
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
-            }
-        } else {
-            // Non blitting outputs require "attachment", inputs "read only"
-            if is_output {
-                vk::ImageLayout::ATTACHMENT_OPTIMAL
-            } else {
-                vk::ImageLayout::READ_ONLY_OPTIMAL
-            }
-        };
+        let new_layout = current_kind.layout_of(is_output);
         if prev_pass
             .inputs
             .iter()
-            .any(|e| e.name.eq(name) && e.level == level)
+            .any(|e| e.name.eq(name) && e.level == level && e.layer == layer)
         {
             // Previous pass had this attachment as an input
             if !is_output {
                 // Only check for same barriers if it's evaluating an input against inputs
-                if prev_pass.is_blitting == current_is_blitting {
+                if prev_pass.kind == current_kind {
                     // Already issued this same barrier before
                     return BarrierEval::already_issued();
                 }
             }
-            if prev_pass.is_blitting {
-                // Prev was blitting, curr isnt. Issue a transition from transfer src
-                return BarrierEval::of(
-                    vk::AccessFlags2::MEMORY_READ,
-                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                    new_layout,
-                );
-            }
-            // Curr is blitting, prev wasn't. Issue a transition from read only
+            // Different kind of pass or now writing to it, transition from whatever it read with
             return BarrierEval::of(
+                prev_pass.kind,
                 vk::AccessFlags2::MEMORY_READ,
-                vk::ImageLayout::READ_ONLY_OPTIMAL,
+                prev_pass.kind.layout_of(false),
                 new_layout,
             );
         }
         if prev_pass
             .outputs
             .iter()
-            .any(|e| e.name.eq(name) && e.level == level)
+            .any(|e| e.name.eq(name) && e.level == level && e.layer == layer)
         {
             // Previous pass had this attachment as an output
             if is_output {
-                // Only check for same barriers if it's evaluating an output against outputs
-                if prev_pass.is_blitting == current_is_blitting {
+                /*
+                 * Only check for same barriers if it's evaluating an output against outputs.
+                 * Compute writes aren't ordered between dispatches like attachment writes are,
+                 * so those always need a barrier.
+                 */
+                if prev_pass.kind == current_kind && current_kind != PassKind::Compute {
                     // Already issued this same barrier before
                     return BarrierEval::already_issued();
                 }
             }
-            if prev_pass.is_blitting {
-                // Prev was blitting, curr isnt. Issue a transition from transfer dst
-                return BarrierEval::of(
-                    vk::AccessFlags2::MEMORY_WRITE,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    new_layout,
-                );
-            }
-            // Prev wasn't blitting. Issue a transition from output attachment
+            // Transition from whatever it wrote with
             return BarrierEval::of(
+                prev_pass.kind,
                 vk::AccessFlags2::MEMORY_WRITE,
-                vk::ImageLayout::ATTACHMENT_OPTIMAL,
+                prev_pass.kind.layout_of(true),
                 new_layout,
             );
         }
@@ -254,6 +399,176 @@ impl BarrierGen {
         BarrierEval::next_pass()
     }
 
+    pub(super) fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub(super) fn history_partner(&self, name: &str) -> Option<&str> {
+        self.history_partners.get(name).map(|e| e.as_str())
+    }
+
+    /// If the pass at the index uses any history target, or the previous frame's contents
+    pub(super) fn is_using_history(&self, index: usize) -> bool {
+        let pass = &self.passes[index];
+        let mut images = pass.inputs.iter().chain(&pass.outputs);
+        images.any(|e| self.history_partners.contains_key(&e.name))
+    }
+
+    /// Searches back from the current pass for the last one that used the image, returning
+    /// its index and how to transition from it, if a barrier is needed at all.
+    fn search_back(
+        &self,
+        currenti: usize,
+        name: &str,
+        level: u8,
+        layer: u8,
+        is_output: bool,
+    ) -> Option<(usize, BarrierEval)> {
+        let curr_kind = self.passes[currenti].kind;
+        let len = self.passes.len();
+        /*
+         * History images were used under their partner's name in the previous frame, so these
+         * keep searching through all of it, down to the passes before the current one.
+         */
+        let partner = self.history_partner(name);
+        let last_offset = if partner.is_some() {
+            len + currenti
+        } else {
+            len - 1
+        };
+        // Search back starting from current passs
+        for offset in 1..=last_offset {
+            let i = (currenti + 2 * len - offset) % len;
+            let name = match partner {
+                Some(partner) if offset > currenti => partner,
+                _ => name,
+            };
+            let prev = &self.passes[i];
+            let ev_barrier = Self::eval_barrier_for(prev, name, level, layer, curr_kind, is_output);
+            if ev_barrier.already_issued {
+                return None;
+            }
+            if !ev_barrier.keep_searching {
+                return Some((i, ev_barrier));
+            }
+        }
+        // Looped back to current pass, nothing to check
+        None
+    }
+
+    /// If no pass before the current one in the frame uses the image
+    fn is_first_use(&self, currenti: usize, name: &str, level: u8, layer: u8) -> bool {
+        !self.passes[..currenti].iter().any(|p| {
+            let mut images = p.inputs.iter().chain(&p.outputs);
+            images.any(|e| e.key() == (name, level, layer))
+        })
+    }
+
+    /// Describes the barriers the pass at the index would emit for every image it uses
+    pub(super) fn describe_barriers_for(&self, currenti: usize) -> Vec<BarrierDesc> {
+        let pass = &self.passes[currenti];
+        let inputs = pass.inputs.iter().map(|e| (e, false));
+        let outputs = pass.outputs.iter().map(|e| (e, true));
+        inputs
+            .chain(outputs)
+            // Default attachment barriers are handled in the rendering loop
+            .filter(|(image, _)| image.name != Attachment::DEFAULT_NAME)
+            .filter_map(|(image, is_output)| {
+                let (src_pass, ev_barrier) =
+                    self.search_back(currenti, &image.name, image.level, image.layer, is_output)?;
+                Some(BarrierDesc {
+                    image: image.clone(),
+                    is_output,
+                    src_pass,
+                    old_layout: ev_barrier.old_layout,
+                    new_layout: ev_barrier.new_layout,
+                })
+            })
+            .collect()
+    }
+
+    /// Searches back from the current pass for the passes the buffer access has to wait for,
+    /// returning the stages and accesses to wait on, if a barrier is needed at all.
+    fn search_back_buffer(
+        &self,
+        currenti: usize,
+        name: &str,
+        is_output: bool,
+    ) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2)> {
+        let curr_kind = self.passes[currenti].kind;
+        let len = self.passes.len();
+        let mut src_stage = vk::PipelineStageFlags2::NONE;
+        let mut src_access = vk::AccessFlags2::NONE;
+        /*
+         * Buffers have no layouts, reads only need to wait for the last write, and writes for
+         * the last write and every read since then. Wraps around to the previous frame, back
+         * to the current pass itself, since it may be the only one writing it.
+         */
+        for offset in 1..=len {
+            let prev = &self.passes[(currenti + len - offset) % len];
+            if prev.output_buffers.iter().any(|e| e == name) {
+                src_stage |= prev.kind.buffer_stage();
+                src_access |= vk::AccessFlags2::MEMORY_WRITE;
+                break;
+            }
+            if prev.input_buffers.iter().any(|e| e == name) {
+                if is_output {
+                    // Only an execution dependency, reads leave nothing to make available
+                    src_stage |= prev.kind.buffer_stage();
+                } else if prev.kind == curr_kind {
+                    // Already waited for the same write from the same stages
+                    return None;
+                }
+            }
+        }
+        (src_stage != vk::PipelineStageFlags2::NONE).then_some((src_stage, src_access))
+    }
+
+    pub fn gen_buffer_barriers_for<'a>(
+        &self,
+        currenti: usize,
+        inputs: &[PipelineBuffer],
+        outputs: &[PipelineBuffer],
+    ) -> Vec<vk::BufferMemoryBarrier2<'a>> {
+        let curr_kind = self.passes[currenti].kind;
+        // Buffers both read and written in the pass get a single barrier, as outputs
+        let inputs = inputs
+            .iter()
+            .filter(|e| !outputs.iter().any(|o| o.name == e.name))
+            .map(|e| (e, false));
+        let mut barriers = Vec::new();
+        for (buffer, is_output) in inputs.chain(outputs.iter().map(|e| (e, true))) {
+            let Some((src_stage, src_access)) =
+                self.search_back_buffer(currenti, &buffer.name, is_output)
+            else {
+                continue;
+            };
+            let dst_access = if is_output {
+                vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE
+            } else {
+                vk::AccessFlags2::MEMORY_READ
+            };
+            log::trace!(
+                "pass {} at {}, buffer barrier {}: {}",
+                self.passes[currenti].name,
+                currenti,
+                if is_output { "output" } else { "input" },
+                buffer.name,
+            );
+            barriers.push(
+                vk::BufferMemoryBarrier2::default()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .src_stage_mask(src_stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(curr_kind.buffer_stage())
+                    .dst_access_mask(dst_access),
+            );
+        }
+        barriers
+    }
+
     pub fn gen_image_barriers_for<'a>(
         &self,
         currenti: usize,
@@ -261,44 +576,21 @@ impl BarrierGen {
         outputs: &[Attachment],
     ) -> Vec<vk::ImageMemoryBarrier2<'a>> {
         let mut barriers: Vec<(&str, bool, vk::ImageMemoryBarrier2)> = Vec::new();
-        let curr_is_blitting = self.passes[currenti].is_blitting;
-        fn wrap_around(index: usize, length: usize) -> usize {
-            if index == 0 {
-                length - 1
-            } else {
-                index - 1
-            }
-        }
+        let curr_kind = self.passes[currenti].kind;
         for input in inputs {
             if input.is_default() {
                 panic!("Can't read from the default attachment!")
             }
-            let level_range =
-                Self::level_range_for(&input.name, input.level_usage, &self.levels_by_owner);
-            // We may need to emit one barrier per mip map level if the input reads several of them
-            for level_usage in level_range {
-                // Search back starting from current passs
-                let mut i = currenti;
-                loop {
-                    i = wrap_around(i, self.passes.len());
-                    if i == currenti {
-                        // Looped back to current pass, nothing to check
-                        break;
-                    }
-                    let prev = &self.passes[i];
-                    let ev_barrier = Self::eval_barrier_for(
-                        prev,
-                        &input.name,
-                        level_usage,
-                        curr_is_blitting,
-                        false,
-                    );
-                    if ev_barrier.already_issued {
-                        break;
-                    }
-                    if ev_barrier.keep_searching {
-                        continue;
-                    }
+            let images = Image::all_of(
+                &input.name,
+                Self::level_range_for(&input.name, input.level_usage, &self.levels_by_owner),
+                Self::layer_range_for(&input.name, input.layer_usage, &self.layers_by_owner),
+            );
+            // We may need to emit one barrier per mip map level and layer the input reads
+            for Image { level, layer, .. } in images {
+                if let Some((_, ev_barrier)) =
+                    self.search_back(currenti, &input.name, level, layer, false)
+                {
                     // Image was written to before, barrier for reading
                     let barrier = vk::ImageMemoryBarrier2::default()
                         .image(input.image)
@@ -306,30 +598,22 @@ impl BarrierGen {
                         .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
                         .old_layout(ev_barrier.old_layout)
                         .new_layout(ev_barrier.new_layout)
-                        .src_stage_mask(if ev_barrier.was_blitting() {
-                            vk::PipelineStageFlags2::TRANSFER
-                        } else if input.format.has_depth_or_stencil() {
-                            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
-                                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
-                        } else {
-                            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
-                        })
-                        .dst_stage_mask(if curr_is_blitting {
-                            vk::PipelineStageFlags2::TRANSFER
-                        } else if input.format.has_depth_or_stencil() {
-                            vk::PipelineStageFlags2::FRAGMENT_SHADER
-                                | vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
-                                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
-                        } else {
-                            vk::PipelineStageFlags2::FRAGMENT_SHADER
-                        })
-                        .subresource_range(Attachment::subresource_range_wlevels(
+                        .src_stage_mask(
+                            ev_barrier
+                                .src_kind
+                                .src_stage_of_input(input.format.has_depth_or_stencil()),
+                        )
+                        .dst_stage_mask(
+                            curr_kind.dst_stage_of_input(input.format.has_depth_or_stencil()),
+                        )
+                        .subresource_range(Attachment::subresource_range_wlayers(
                             input.format.aspect(),
-                            level_usage as u32,
+                            level as u32,
+                            1,
+                            layer as u32,
                             1,
                         ));
                     barriers.push((&input.name, false, barrier));
-                    break;
                 }
             }
         }
@@ -341,58 +625,65 @@ impl BarrierGen {
                  */
                 continue;
             }
-            // Search back starting from current passs
-            let mut i = currenti;
-            loop {
-                i = wrap_around(i, self.passes.len());
-                if i == currenti {
-                    // Looped back to current pass, nothing to check
-                    break;
-                }
-                let prev = &self.passes[i];
-                let ev_barrier = Self::eval_barrier_for(
-                    prev,
-                    &output.name,
-                    output.level_usage, // always a specific mip
-                    curr_is_blitting,
-                    true,
-                );
-                if ev_barrier.already_issued {
-                    break;
-                }
-                if ev_barrier.keep_searching {
+            let layer_range =
+                Self::layer_range_for(&output.name, output.layer_usage, &self.layers_by_owner);
+            // Outputs are always a specific mip, but may write several layers
+            for layer in layer_range {
+                let level = output.level_usage;
+                if output.is_aliased && self.is_first_use(currenti, &output.name, level, layer) {
+                    /*
+                     * Memory was used by other attachments since the last frame, wait for
+                     * everything before and discard whatever is there.
+                     */
+                    let barrier = vk::ImageMemoryBarrier2::default()
+                        .image(output.image)
+                        .src_access_mask(
+                            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+                        )
+                        .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(curr_kind.layout_of(true))
+                        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                        .dst_stage_mask(
+                            curr_kind.dst_stage_of_output(output.format.has_depth_or_stencil()),
+                        )
+                        .subresource_range(Attachment::subresource_range_wlayers(
+                            output.format.aspect(),
+                            level as u32,
+                            1,
+                            layer as u32,
+                            1,
+                        ));
+                    barriers.push((&output.name, true, barrier));
                     continue;
                 }
-                let barrier = vk::ImageMemoryBarrier2::default()
-                    .image(output.image)
-                    .src_access_mask(ev_barrier.src_access)
-                    .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE)
-                    .old_layout(ev_barrier.old_layout)
-                    .new_layout(ev_barrier.new_layout)
-                    .src_stage_mask(if ev_barrier.was_blitting() {
-                        vk::PipelineStageFlags2::TRANSFER
-                    } else if output.format.has_depth_or_stencil() {
-                        vk::PipelineStageFlags2::FRAGMENT_SHADER
-                            | vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
-                            | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
-                    } else {
-                        vk::PipelineStageFlags2::FRAGMENT_SHADER
-                    })
-                    .dst_stage_mask(if curr_is_blitting {
-                        vk::PipelineStageFlags2::TRANSFER
-                    } else if output.format.has_depth_or_stencil() {
-                        vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
-                            | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
-                    } else {
-                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
-                    })
-                    .subresource_range(Attachment::subresource_range_wlevels(
-                        output.format.aspect(),
-                        output.level_usage as u32,
-                        1,
-                    ));
-                barriers.push((&output.name, true, barrier));
-                break;
+                // Always a specific mip
+                if let Some((_, ev_barrier)) =
+                    self.search_back(currenti, &output.name, output.level_usage, layer, true)
+                {
+                    let barrier = vk::ImageMemoryBarrier2::default()
+                        .image(output.image)
+                        .src_access_mask(ev_barrier.src_access)
+                        .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                        .old_layout(ev_barrier.old_layout)
+                        .new_layout(ev_barrier.new_layout)
+                        .src_stage_mask(
+                            ev_barrier
+                                .src_kind
+                                .src_stage_of_output(output.format.has_depth_or_stencil()),
+                        )
+                        .dst_stage_mask(
+                            curr_kind.dst_stage_of_output(output.format.has_depth_or_stencil()),
+                        )
+                        .subresource_range(Attachment::subresource_range_wlayers(
+                            output.format.aspect(),
+                            output.level_usage as u32,
+                            1,
+                            layer as u32,
+                            1,
+                        ));
+                    barriers.push((&output.name, true, barrier));
+                }
             }
         }
         if log::log_enabled!(log::Level::Trace) {
@@ -422,3 +713,62 @@ impl BarrierGen {
         barriers.iter().map(|e| e.2).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+    use serde_json::json;
+
+    use super::{super::file::Pipeline, BarrierGen};
+
+    #[test]
+    fn history_waits_on_previous_frame_write_before_current_pass() {
+        let rect = json!({ "x": 0, "y": 0, "width": 1.0, "height": 1.0 });
+        let blit = |name: &str, input: &str, output: &str| {
+            json!({
+                "type": "blit",
+                "name": name,
+                "input": input,
+                "inputRect": rect,
+                "output": output,
+                "outputRect": rect,
+                "filter": "LINEAR",
+                "attributes": ["COLOR"],
+            })
+        };
+        let target = |name: &str, history: bool| {
+            json!({
+                "name": name,
+                "format": "R8G8B8A8_UNORM",
+                "width": 1.0,
+                "height": 1.0,
+                "history": history,
+            })
+        };
+        let mut pip: Pipeline = serde_json::from_value(json!({
+            "targets": [target("src", false), target("velocity", true), target("out", false)],
+            "programs": [],
+            "sharedState": {},
+            "passes": [
+                blit("gbuffer", "src", "velocity"),
+                blit("blur", "velocity@prev", "out"),
+            ],
+        }))
+        .unwrap();
+        // Same as when reading the pipeline
+        let history = pip.targets[1].history_target();
+        pip.targets.push(history);
+        let passes: Vec<_> = pip.passes.iter().collect();
+        let barrier_gen = BarrierGen::new(&pip.targets, &passes, &|e| pip.state_of(e)).unwrap();
+        let barriers = barrier_gen.describe_barriers_for(1);
+        // Written by "gbuffer" as "velocity" in the previous frame, before "blur"
+        let barrier = barriers
+            .iter()
+            .find(|e| e.image.name == "velocity@prev")
+            .expect("missing barrier for velocity@prev!");
+        assert!(!barrier.is_output);
+        assert_eq!(barrier.src_pass, 0);
+        assert_eq!(barrier.old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    }
+}
@@ -1,6 +1,14 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
-use super::{attachment::Attachment, stage::Stage};
+use crate::context::VulkanContext;
+
+use super::{
+    attachment::Attachment,
+    file::BlitRect,
+    stage::{replace_barrier_images, Stage},
+};
 
 pub struct BlitStage<'a> {
     pub name: String,
@@ -8,6 +16,9 @@ pub struct BlitStage<'a> {
     /// Wont be present if the blit stage writes the current swapchain image
     pub output: Option<Attachment>,
     pub filter: vk::Filter,
+    /// Regions of both attachments the blit offsets get computed out of
+    pub input_rect: BlitRect,
+    pub output_rect: BlitRect,
     pub region: vk::ImageBlit,
     pub index: u32,
     pub image_barriers: Vec<vk::ImageMemoryBarrier2<'a>>,
@@ -36,6 +47,28 @@ impl<'a> Stage for BlitStage<'a> {
         // Nothing to do
     }
 
+    fn resize(
+        &mut self,
+        _ctx: &VulkanContext,
+        attachments_by_name: &HashMap<String, Attachment>,
+        images: &HashMap<vk::Image, vk::Image>,
+    ) {
+        self.input = self.input.resized(attachments_by_name);
+        self.output = self.output.as_ref().map(|e| e.resized(attachments_by_name));
+        // Final blits write into whatever the default attachment is
+        let output_extent = match &self.output {
+            Some(output) => Some(output.extent),
+            None => attachments_by_name
+                .get(Attachment::DEFAULT_NAME)
+                .map(|e| e.extent),
+        };
+        self.region.src_offsets = self.input_rect.to_vk(self.input.extent);
+        if let Some(extent) = output_extent {
+            self.region.dst_offsets = self.output_rect.to_vk(extent);
+        }
+        replace_barrier_images(&mut self.image_barriers, images);
+    }
+
     fn work(&mut self, ctx: super::RenderContext) {
         let mut image_barriers = self.image_barriers.clone();
         if self.is_final {
@@ -72,6 +105,7 @@ impl<'a> Stage for BlitStage<'a> {
             // Need to transition for presenting
             let present_image_barriers = vec![Attachment::default_attachment_blit_present_barrier(
                 ctx.default_attachment.image,
+                ctx.default_attachment_final_layout,
             )];
             let barrier_dep_info =
                 vk::DependencyInfo::default().image_memory_barriers(&present_image_barriers);
@@ -0,0 +1,96 @@
+use ash::vk;
+
+use crate::{
+    context::VulkanContext,
+    error::{RendError, RendResult},
+};
+
+/// Device local buffer declared in the pipeline, passes access it through its device address
+#[derive(Clone)]
+pub struct PipelineBuffer {
+    pub name: String,
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: u64,
+    pub device_addr: u64,
+}
+
+impl PipelineBuffer {
+    pub fn new(ctx: &VulkanContext, name: &str, size: u64) -> RendResult<Self> {
+        use vk::BufferUsageFlags as Buf;
+        // May hold indirect arguments, transfers let it be filled or copied around
+        let usage = Buf::SHADER_DEVICE_ADDRESS
+            | Buf::STORAGE_BUFFER
+            | Buf::INDIRECT_BUFFER
+            | Buf::TRANSFER_SRC
+            | Buf::TRANSFER_DST;
+        let buffer_info = vk::BufferCreateInfo {
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = unsafe { ctx.device.create_buffer(&buffer_info, None) }
+            .map_err(|e| RendError::vulkan(e, &format!("creating pipeline buffer {}", name)))?;
+        let mem_reqs = unsafe { ctx.device.get_buffer_memory_requirements(buffer) };
+        let Some(memi) = ctx.memory_type_index_for(
+            mem_reqs.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ) else {
+            unsafe { ctx.device.destroy_buffer(buffer, None) };
+            return Err(RendError::OutOfMemory(format!(
+                "no device local memory type for pipeline buffer {}",
+                name
+            )));
+        };
+        let mut mem_flags = vk::MemoryAllocateFlagsInfo {
+            flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+            ..Default::default()
+        };
+        let mem_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(mem_reqs.size)
+            .memory_type_index(memi)
+            .push_next(&mut mem_flags);
+        let device_addr_info = vk::BufferDeviceAddressInfo {
+            buffer,
+            ..Default::default()
+        };
+        let memory = match unsafe { ctx.device.allocate_memory(&mem_info, None) } {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { ctx.device.destroy_buffer(buffer, None) };
+                return Err(RendError::vulkan(
+                    err,
+                    &format!("allocating the memory of pipeline buffer {}", name),
+                ));
+            }
+        };
+        if let Err(err) = unsafe { ctx.device.bind_buffer_memory(buffer, memory, 0) } {
+            unsafe {
+                ctx.device.destroy_buffer(buffer, None);
+                ctx.device.free_memory(memory, None);
+            }
+            return Err(RendError::vulkan(
+                err,
+                &format!("binding the memory of pipeline buffer {}", name),
+            ));
+        }
+        let device_addr = unsafe { ctx.device.get_buffer_device_address(&device_addr_info) };
+        ctx.try_set_debug_name(&format!("{}_pip_buffer", name), buffer);
+        ctx.try_set_debug_name(&format!("{}_pip_buffer_memory", name), memory);
+        Ok(Self {
+            name: name.to_string(),
+            buffer,
+            memory,
+            size,
+            device_addr,
+        })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
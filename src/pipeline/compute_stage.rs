@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use crate::{
+    context::VulkanContext,
+    pipeline::{descriptor::DescriptorGroup, file::Dispatch},
+    render_task::RenderTask,
+    shader_resource::{ResourceKind, SingleResource},
+};
+
+use super::{
+    attachment::Attachment,
+    render_stage::{missing_resource_of, reserve_pass_buffers},
+    stage::{replace_barrier_images, Stage},
+};
+
+pub struct ComputeStage<'a> {
+    pub name: String,
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub inputs: Vec<Attachment>,
+    pub outputs: Vec<Attachment>,
+    /// Sampler of every input, in the same order
+    pub samplers: Vec<vk::Sampler>,
+    pub per_pass_updaters: Vec<ResourceKind>,
+    pub per_pass_constant: Vec<f32>,
+    /// Addresses of the pipeline buffers it reads and writes, pushed after the per pass data
+    pub buffer_addresses: Vec<u64>,
+    /// Sampled inputs, same as render stages
+    pub attachment_descriptors: Option<Box<DescriptorGroup>>,
+    /// Outputs, written as storage images
+    pub storage_descriptors: Box<DescriptorGroup>,
+    /// Placeholder for the attachment set when the stage has no inputs
+    pub empty_set_layout: Option<vk::DescriptorSetLayout>,
+    pub dispatch: Dispatch,
+    pub dispatch_target: Attachment,
+    /// Out of the dispatch relative to its target
+    pub group_count: [u32; 2],
+    pub index: u32,
+    pub image_barriers: Vec<vk::ImageMemoryBarrier2<'a>>,
+    pub buffer_barriers: Vec<vk::BufferMemoryBarrier2<'a>>,
+    pub is_validation_layer_enabled: bool,
+}
+
+impl<'a> Stage for ComputeStage<'a> {
+    fn work(&mut self, ctx: super::RenderContext) {
+        if !self.image_barriers.is_empty() || !self.buffer_barriers.is_empty() {
+            let barrier_dep_info = vk::DependencyInfo::default()
+                .image_memory_barriers(&self.image_barriers)
+                .buffer_memory_barriers(&self.buffer_barriers);
+            unsafe {
+                ctx.vulkan
+                    .device
+                    .cmd_pipeline_barrier2(ctx.command_buffer, &barrier_dep_info);
+            }
+        }
+        // Compose the descriptor set array to bind for this stage
+        let mut descriptor_sets = vec![ctx.sampler_descriptors.set, ctx.image_descriptors.set];
+        if let Some(desc) = &self.attachment_descriptors {
+            descriptor_sets.push(desc.set)
+        }
+        unsafe {
+            ctx.vulkan.device.cmd_bind_descriptor_sets(
+                ctx.command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &descriptor_sets,
+                &[],
+            );
+            // Storage images have a fixed set index regardless of having inputs or not
+            ctx.vulkan.device.cmd_bind_descriptor_sets(
+                ctx.command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                super::DESCRIPTOR_SET_STORAGE_IMAGE,
+                &[self.storage_descriptors.set],
+                &[],
+            );
+            ctx.vulkan.device.cmd_bind_pipeline(
+                ctx.command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+        }
+        let Some(mut push_constants) = reserve_pass_buffers(
+            &self.per_pass_updaters,
+            &self.per_pass_constant,
+            ctx.frame_allocator,
+            ctx.shader_resources_by_kind,
+        ) else {
+            // Rendering checks these beforehand, shouldn't get here
+            log::warn!(
+                "skipping stage {}, per pass resources are missing",
+                self.name
+            );
+            return;
+        };
+        push_constants.extend(&self.buffer_addresses);
+        unsafe {
+            if !push_constants.is_empty() {
+                let push_constants = push_constants.align_to::<u8>().1;
+                ctx.vulkan.device.cmd_push_constants(
+                    ctx.command_buffer,
+                    self.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0u32,
+                    push_constants,
+                );
+            }
+            ctx.vulkan.device.cmd_dispatch(
+                ctx.command_buffer,
+                self.group_count[0],
+                self.group_count[1],
+                1,
+            );
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    fn is_validation_layer_enabled(&self) -> bool {
+        self.is_validation_layer_enabled
+    }
+
+    fn image_barriers(&'_ self) -> Vec<vk::ImageMemoryBarrier2<'_>> {
+        self.image_barriers.clone()
+    }
+
+    fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            if let Some(desc) = &self.attachment_descriptors {
+                desc.destroy(device)
+            }
+            self.storage_descriptors.destroy(device);
+            if let Some(layout) = self.empty_set_layout {
+                device.destroy_descriptor_set_layout(layout, None);
+            }
+        }
+    }
+
+    fn resize(
+        &mut self,
+        ctx: &VulkanContext,
+        attachments_by_name: &HashMap<String, Attachment>,
+        images: &HashMap<vk::Image, vk::Image>,
+    ) {
+        let resized_all = |e: &[Attachment]| -> Vec<_> {
+            e.iter().map(|e| e.resized(attachments_by_name)).collect()
+        };
+        self.inputs = resized_all(&self.inputs);
+        self.outputs = resized_all(&self.outputs);
+        if let Some(desc) = &mut self.attachment_descriptors {
+            for (input, sampler) in self.inputs.iter().zip(&self.samplers) {
+                desc.place_image_sampler_at(
+                    ctx,
+                    input.descriptor_index,
+                    input.usage_view(),
+                    vk::ImageLayout::READ_ONLY_OPTIMAL,
+                    *sampler,
+                );
+            }
+        }
+        for output in &self.outputs {
+            self.storage_descriptors.place_image_at(
+                ctx,
+                output.descriptor_index,
+                output.render_view(),
+                vk::ImageLayout::GENERAL,
+            );
+        }
+        self.dispatch_target = self.dispatch_target.resized(attachments_by_name);
+        self.group_count = self
+            .dispatch
+            .group_count(self.dispatch_target.usage_extent());
+        replace_barrier_images(&mut self.image_barriers, images);
+    }
+
+    fn missing_pass_resource(
+        &self,
+        _batches_by_task_type: &HashMap<u64, Vec<RenderTask>>,
+        shader_resources_by_kind: &HashMap<ResourceKind, SingleResource>,
+    ) -> Option<ResourceKind> {
+        // Dispatches every frame regardless of the batches
+        missing_resource_of(&self.per_pass_updaters, shader_resources_by_kind)
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use super::{attachment::Attachment, barrier_gen::BarrierGen, file::*};
+use crate::error::RendResult;
+
+impl Pipeline {
+    /// Drops the passes whose outputs never reach the default attachment, along with the
+    /// targets and buffers no remaining pass uses, so these aren't allocated at all.
+    pub(super) fn cull(&mut self) -> RendResult<()> {
+        let enabled: Vec<usize> = (0..self.passes.len())
+            .filter(|i| !self.passes[*i].is_disabled())
+            .collect();
+        let enabled_passes: Vec<_> = enabled.iter().map(|i| &self.passes[*i]).collect();
+        let barrier_gen = BarrierGen::new(&self.targets, &enabled_passes, &|e| self.state_of(e))?;
+        let passes = barrier_gen.passes();
+        let mut is_live: Vec<_> = passes
+            .iter()
+            .map(|p| p.outputs.iter().any(|e| e.name == Attachment::DEFAULT_NAME))
+            .collect();
+        if !is_live.contains(&true) {
+            log::warn!("no pass writes into the default attachment, not culling any pass");
+            return Ok(());
+        }
+        /*
+         * A pass is live if a live pass reads any image or buffer it writes. Reads may refer to
+         * what was written in the previous frame, so pass order doesn't matter, iterate until no
+         * more passes, images or buffers get marked.
+         */
+        let mut needed = HashSet::new();
+        let mut needed_buffers = HashSet::new();
+        loop {
+            let mut changed = false;
+            for (i, pass) in passes.iter().enumerate() {
+                let is_needed = pass.outputs.iter().any(|e| needed.contains(&e.key()))
+                    || pass
+                        .output_buffers
+                        .iter()
+                        .any(|e| needed_buffers.contains(e));
+                if !is_live[i] && is_needed {
+                    is_live[i] = true;
+                    changed = true;
+                }
+                if is_live[i] {
+                    for buffer in &pass.input_buffers {
+                        changed |= needed_buffers.insert(buffer);
+                    }
+                    for input in &pass.inputs {
+                        changed |= needed.insert(input.key());
+                        // Reading a history target reads what its partner had last frame
+                        if let Some(partner) = barrier_gen.history_partner(&input.name) {
+                            changed |= needed.insert((partner, input.level, input.layer));
+                        }
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut used_targets = HashSet::new();
+        let mut used_buffers = HashSet::new();
+        for (i, pass) in passes.iter().enumerate() {
+            if !is_live[i] {
+                continue;
+            }
+            used_buffers.extend(
+                pass.input_buffers
+                    .iter()
+                    .chain(&pass.output_buffers)
+                    .cloned(),
+            );
+            for image in pass.inputs.iter().chain(&pass.outputs) {
+                used_targets.insert(image.name.clone());
+                // Both of them get swapped every frame, so both are needed
+                if let Some(partner) = barrier_gen.history_partner(&image.name) {
+                    used_targets.insert(partner.to_string());
+                }
+            }
+            // Dispatches may be sized after a target the pass doesn't use otherwise
+            if let PipelineStep::Compute(p) = enabled_passes[i] {
+                used_targets.insert(p.dispatch.target.get().name);
+            }
+        }
+        let dead: HashSet<_> = (0..passes.len())
+            .filter(|i| !is_live[*i])
+            .map(|i| enabled[i])
+            .collect();
+        let mut index = 0;
+        self.passes.retain(|p| {
+            let is_dead = dead.contains(&index);
+            index += 1;
+            if is_dead {
+                log::info!(
+                    "culling pass {}, nothing reaching the default attachment uses its outputs",
+                    p.name()
+                );
+            }
+            !is_dead
+        });
+        self.targets.retain(|t| {
+            let is_used = used_targets.contains(&t.name);
+            if !is_used {
+                log::info!("culling target {}, no remaining pass uses it", t.name);
+            }
+            is_used
+        });
+        self.buffers.retain(|b| {
+            let is_used = used_buffers.contains(&b.name);
+            if !is_used {
+                log::info!("culling buffer {}, no remaining pass uses it", b.name);
+            }
+            is_used
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::super::file::Pipeline;
+
+    /// Color targets and the passes blitting from one into the other
+    fn pipeline_of(targets: &[&str], blits: &[(&str, &str, &str)]) -> Pipeline {
+        let targets: Vec<_> = targets
+            .iter()
+            .map(|e| json!({ "name": e, "format": "R8G8B8A8_UNORM", "width": 1.0, "height": 1.0 }))
+            .collect();
+        let rect = json!({ "x": 0, "y": 0, "width": 1.0, "height": 1.0 });
+        let passes: Vec<Value> = blits
+            .iter()
+            .map(|(name, input, output)| {
+                json!({
+                    "type": "blit",
+                    "name": name,
+                    "input": input,
+                    "inputRect": rect,
+                    "output": output,
+                    "outputRect": rect,
+                    "filter": "LINEAR",
+                    "attributes": ["COLOR"],
+                })
+            })
+            .collect();
+        let description = json!({
+            "targets": targets,
+            "programs": [],
+            "sharedState": {},
+            "passes": passes,
+        });
+        serde_json::from_value(description).unwrap()
+    }
+
+    fn names_of(pip: &Pipeline) -> (Vec<&str>, Vec<&str>) {
+        let passes = pip.passes.iter().map(|e| e.name()).collect();
+        let targets = pip.targets.iter().map(|e| e.name.as_str()).collect();
+        (passes, targets)
+    }
+
+    #[test]
+    fn culls_passes_and_targets_never_read() {
+        let mut pip = pipeline_of(
+            &["src", "albedo", "velocity", "unused"],
+            &[
+                ("gbuffer", "src", "albedo"),
+                // Written but never read, nothing reaching the default attachment needs it
+                ("motion", "src", "velocity"),
+                ("present", "albedo", "default"),
+            ],
+        );
+        pip.cull().unwrap();
+        let (passes, targets) = names_of(&pip);
+        assert_eq!(passes, ["gbuffer", "present"]);
+        assert_eq!(targets, ["src", "albedo"]);
+    }
+
+    #[test]
+    fn culls_nothing_without_default_attachment_writes() {
+        let mut pip = pipeline_of(
+            &["src", "albedo", "velocity"],
+            &[("gbuffer", "src", "albedo"), ("motion", "src", "velocity")],
+        );
+        pip.cull().unwrap();
+        let (passes, targets) = names_of(&pip);
+        assert_eq!(passes, ["gbuffer", "motion"]);
+        assert_eq!(targets, ["src", "albedo", "velocity"]);
+    }
+}
@@ -14,10 +14,16 @@ pub struct DescriptorGroup {
     occupancy: BitVec,
 }
 
-pub const MAX_DESCRIPTOR_SETS: u32 = 16;
+pub const MAX_DESCRIPTOR_SETS: u32 = 32;
 pub const MAX_DESCRIPTOR_IMAGE: u32 = 2048;
 pub const MAX_DESCRIPTOR_IMAGE_SAMPLER: u32 = 64;
 pub const MAX_DESCRIPTOR_SAMPLER: u32 = 32;
+pub const MAX_DESCRIPTOR_STORAGE_IMAGE: u32 = 32;
+
+// Same descriptors are bound for both render and compute stages
+const STAGE_FLAGS: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+    vk::ShaderStageFlags::ALL_GRAPHICS.as_raw() | vk::ShaderStageFlags::COMPUTE.as_raw(),
+);
 
 pub fn make_pool(ctx: &VulkanContext, is_dynamic: bool) -> vk::DescriptorPool {
     let image_sampler_size = vk::DescriptorPoolSize {
@@ -32,7 +38,16 @@ pub fn make_pool(ctx: &VulkanContext, is_dynamic: bool) -> vk::DescriptorPool {
         descriptor_count: MAX_DESCRIPTOR_SAMPLER,
         ty: vk::DescriptorType::SAMPLER,
     };
-    let pool_sizes = [image_sampler_size, image_size, sampler_size];
+    let storage_image_size = vk::DescriptorPoolSize {
+        descriptor_count: MAX_DESCRIPTOR_STORAGE_IMAGE,
+        ty: vk::DescriptorType::STORAGE_IMAGE,
+    };
+    let pool_sizes = [
+        image_sampler_size,
+        image_size,
+        sampler_size,
+        storage_image_size,
+    ];
     let info = vk::DescriptorPoolCreateInfo::default()
         .flags(if is_dynamic {
             vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
@@ -61,7 +76,7 @@ impl DescriptorGroup {
                 .binding(0)
                 .descriptor_type(descriptor_type)
                 .descriptor_count(capacity)
-                .stage_flags(vk::ShaderStageFlags::ALL_GRAPHICS)]
+                .stage_flags(STAGE_FLAGS)]
         } else {
             (0..capacity)
                 .map(|e| {
@@ -69,7 +84,7 @@ impl DescriptorGroup {
                         .binding(e)
                         .descriptor_type(descriptor_type)
                         .descriptor_count(1)
-                        .stage_flags(vk::ShaderStageFlags::ALL_GRAPHICS)
+                        .stage_flags(STAGE_FLAGS)
                 })
                 .collect()
         };
@@ -137,6 +152,11 @@ impl DescriptorGroup {
         self.occupancy.first_zero().unwrap()
     }
 
+    /// None if every slot is occupied
+    pub fn try_next_free(&self) -> Option<usize> {
+        self.occupancy.first_zero()
+    }
+
     pub fn remove_at(&mut self, index: u32) {
         self.occupancy.set(index as usize, false);
     }
@@ -5,18 +5,26 @@ use indexmap::IndexMap;
 use serde::Deserialize;
 
 use super::state::*;
-use crate::{format, shader_resource::ResourceKind, texture::MipMap, UsedAsIndex};
+use crate::{
+    error::{RendError, RendResult},
+    format,
+    shader_resource::ResourceKind,
+    texture::{MipMap, TextureKind},
+    UsedAsIndex,
+};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Pipeline {
     pub targets: Vec<Target>,
+    #[serde(default)]
+    pub buffers: Vec<Buffer>,
     pub programs: Vec<Program>,
     pub passes: Vec<PipelineStep>,
     pub shared_state: HashMap<String, State>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Target {
     pub name: String,
@@ -25,12 +33,83 @@ pub struct Target {
     pub height: U32OrF32,
     #[serde(default = "Target::default_level")]
     pub level: u8,
+    /// Array layers, more than one makes it an array. Counts whole cubes for cube targets
+    #[serde(default = "Target::default_layers")]
+    pub layers: u8,
+    /// Six layers per cube, sampled as a cube map
+    #[serde(default)]
+    pub cube: bool,
+    /// Samples per pixel, multisampled targets can't have more than one level
+    #[serde(default = "Target::default_samples")]
+    pub samples: u8,
+    /// Keeps the previous frame's contents around, readable as `name@prev`
+    #[serde(default)]
+    pub history: bool,
+    /// Set when a compute pass writes into it, requires storage image usage
+    #[serde(skip)]
+    pub is_storage: bool,
 }
 
 impl Target {
+    /// Suffix of the targets holding the previous frame's contents of history targets
+    pub const HISTORY_SUFFIX: &'static str = "@prev";
+
     pub const fn default_level() -> u8 {
         1
     }
+
+    pub const fn default_layers() -> u8 {
+        1
+    }
+
+    pub const fn default_samples() -> u8 {
+        1
+    }
+
+    pub fn sample_count(&self) -> vk::SampleCountFlags {
+        vk::SampleCountFlags::from_raw(self.samples as u32)
+    }
+
+    pub fn kind(&self) -> TextureKind {
+        if self.cube {
+            TextureKind::CUBEMAP
+        } else {
+            TextureKind::T2D
+        }
+    }
+
+    /// Layers the image actually has, counting every face of a cube
+    pub fn image_layers(&self) -> u8 {
+        self.layers.saturating_mul(self.kind().layer_count() as u8)
+    }
+
+    /// Same target holding the previous frame's contents, swapped with this one every frame
+    pub fn history_target(&self) -> Target {
+        Target {
+            name: format!("{}{}", self.name, Self::HISTORY_SUFFIX),
+            ..self.clone()
+        }
+    }
+
+    /// Name of the target this one gets swapped with every frame, if it's a history target
+    pub fn history_partner(&self) -> Option<String> {
+        if !self.history {
+            return None;
+        }
+        match self.name.strip_suffix(Self::HISTORY_SUFFIX) {
+            Some(name) => Some(name.to_string()),
+            None => Some(format!("{}{}", self.name, Self::HISTORY_SUFFIX)),
+        }
+    }
+}
+
+/// GPU buffer passes read and write through its device address
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Buffer {
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
 }
 
 #[derive(Clone, Deserialize)]
@@ -43,6 +122,8 @@ pub enum StrOrObj<T> {
 pub trait AttachmentFile {
     fn name(&self) -> &str;
     fn level(&self) -> StrOrObj<u8>;
+    /// None if it refers to all of the layers
+    fn layer(&self) -> Option<u8>;
 }
 
 #[derive(Deserialize, Clone)]
@@ -51,6 +132,13 @@ pub struct AttachmentOutput {
     pub name: String,
     #[serde(default = "AttachmentOutput::default_level")]
     pub level: u8,
+    /// Writes into a single layer, otherwise renders into all of them.
+    /// For cube targets it's the cube, and needs a face to address a single layer
+    pub layer: Option<u8>,
+    /// Face of a cube target to write into, otherwise renders into all of them
+    pub face: Option<u8>,
+    /// Single sampled target the output gets resolved into at the end of the pass
+    pub resolve: Option<String>,
 }
 
 impl AttachmentOutput {
@@ -67,6 +155,18 @@ impl AttachmentFile for AttachmentOutput {
     fn level(&self) -> StrOrObj<u8> {
         StrOrObj::Obj(self.level)
     }
+
+    fn layer(&self) -> Option<u8> {
+        match self.face {
+            Some(face) => Some(
+                self.layer
+                    .unwrap_or(0)
+                    .saturating_mul(6)
+                    .saturating_add(face),
+            ),
+            None => self.layer,
+        }
+    }
 }
 
 impl StrOrObj<AttachmentOutput> {
@@ -75,6 +175,9 @@ impl StrOrObj<AttachmentOutput> {
             StrOrObj::Str(s) => AttachmentOutput {
                 name: s.to_string(),
                 level: AttachmentOutput::default_level(),
+                layer: None,
+                face: None,
+                resolve: None,
             },
             StrOrObj::Obj(s) => s.clone(),
         }
@@ -100,6 +203,8 @@ pub struct AttachmentInput {
     pub sampler: DescOption<Sampler>,
     #[serde(default = "AttachmentInput::default_level")]
     pub level: StrOrObj<u8>,
+    /// Samples a single layer, otherwise the whole array
+    pub layer: Option<u8>,
 }
 
 impl AttachmentInput {
@@ -116,6 +221,10 @@ impl AttachmentFile for AttachmentInput {
     fn level(&self) -> StrOrObj<u8> {
         self.level.clone()
     }
+
+    fn layer(&self) -> Option<u8> {
+        self.layer
+    }
 }
 
 impl StrOrObj<AttachmentInput> {
@@ -124,6 +233,7 @@ impl StrOrObj<AttachmentInput> {
             StrOrObj::Str(s) => AttachmentInput {
                 name: s.to_string(),
                 level: AttachmentInput::default_level(),
+                layer: None,
                 sampler: DescOption::Predefined(OptionPredefined::Default),
             },
             StrOrObj::Obj(s) => s.clone(),
@@ -153,7 +263,10 @@ impl Sampler {
 pub enum PipelineStep {
     Render(RenderPass),
     Blit(BlitPass),
+    Compute(ComputePass),
+    GenerateMips(GenerateMipsPass),
     Include(IncludePass),
+    Repeat(RepeatPass),
 }
 
 impl PipelineStep {
@@ -161,14 +274,36 @@ impl PipelineStep {
         match self {
             PipelineStep::Render(p) => p.is_disabled,
             PipelineStep::Blit(p) => p.is_disabled,
+            PipelineStep::Compute(p) => p.is_disabled,
+            PipelineStep::GenerateMips(p) => p.is_disabled,
             PipelineStep::Include(p) => p.is_disabled,
+            PipelineStep::Repeat(p) => p.is_disabled,
         }
     }
     pub fn name(&self) -> &str {
         match self {
             PipelineStep::Render(p) => &p.name,
             PipelineStep::Blit(p) => &p.name,
+            PipelineStep::Compute(p) => &p.name,
+            PipelineStep::GenerateMips(p) => &p.name,
             PipelineStep::Include(p) => &p.name,
+            PipelineStep::Repeat(p) => &p.name,
+        }
+    }
+    /// Buffers the pass reads, only render and compute passes use them
+    pub fn input_buffers(&self) -> &[String] {
+        match self {
+            PipelineStep::Render(p) => &p.input_buffers,
+            PipelineStep::Compute(p) => &p.input_buffers,
+            _ => &[],
+        }
+    }
+    /// Buffers the pass writes, only render and compute passes use them
+    pub fn output_buffers(&self) -> &[String] {
+        match self {
+            PipelineStep::Render(p) => &p.output_buffers,
+            PipelineStep::Compute(p) => &p.output_buffers,
+            _ => &[],
         }
     }
 }
@@ -181,6 +316,29 @@ pub struct IncludePass {
     pub is_disabled: bool,
 }
 
+/// Pass template instantiated once per value, replacing `${variable}` in every string of it
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepeatPass {
+    pub name: String,
+    #[serde(default = "RepeatPass::default_variable")]
+    pub variable: String,
+    /// Integers from the start up to the end, not including it
+    pub range: Option<[i64; 2]>,
+    /// Strings or numbers to use instead of a range
+    pub values: Option<Vec<serde_json::Value>>,
+    /// Any kind of pass but includes, strings that are only the variable take its value as is
+    pub template: serde_json::Value,
+    #[serde(default)]
+    pub is_disabled: bool,
+}
+
+impl RepeatPass {
+    fn default_variable() -> String {
+        "i".to_string()
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RenderPass {
@@ -188,14 +346,23 @@ pub struct RenderPass {
     pub program: String,
     #[serde(default)]
     pub batch_parent_id: u32,
-    pub depth_stencil: Option<String>,
+    pub depth_stencil: Option<StrOrObj<AttachmentOutput>>,
     pub batch: crate::render_task::TaskKind,
     pub outputs: Vec<StrOrObj<AttachmentOutput>>,
     pub inputs: Vec<StrOrObj<AttachmentInput>>,
     pub per_pass_updaters: Vec<UpdaterKind>,
     pub per_instance_updaters: Vec<UpdaterKind>,
     pub per_pass_constant: Option<IndexMap<String, f32>>,
+    /// Buffers read, their addresses get pushed after the per instance data
+    #[serde(default)]
+    pub input_buffers: Vec<String>,
+    /// Buffers written, their addresses get pushed after the input ones
+    #[serde(default)]
+    pub output_buffers: Vec<String>,
     pub state: BaseState,
+    /// Clips every task to its own scissor rect, if it has one
+    #[serde(default)]
+    pub scissor_per_task: bool,
     #[serde(default)]
     pub is_disabled: bool,
 }
@@ -250,7 +417,12 @@ pub struct BlitPass {
 }
 
 impl BlitPass {
-    pub fn to_vk(&self, src_extent: Extent2D, dst_extent: Extent2D) -> vk::ImageBlit {
+    /// Takes the extent and the (first layer, layer count) of both attachments
+    pub fn to_vk(
+        &self,
+        (src_extent, src_layers): (Extent2D, (u32, u32)),
+        (dst_extent, dst_layers): (Extent2D, (u32, u32)),
+    ) -> vk::ImageBlit {
         let aspect_flags = self
             .attributes
             .iter()
@@ -261,21 +433,88 @@ impl BlitPass {
             })
             .reduce(|acc, e| acc | e)
             .expect("missing attributes to blit!");
-        let layer = vk::ImageSubresourceLayers {
+        let layers_of = |(base_array_layer, layer_count)| vk::ImageSubresourceLayers {
             mip_level: 0,
-            base_array_layer: 0,
-            layer_count: 1,
+            base_array_layer,
+            layer_count,
             aspect_mask: aspect_flags,
         };
         vk::ImageBlit {
-            dst_subresource: layer,
-            src_subresource: layer,
+            dst_subresource: layers_of(dst_layers),
+            src_subresource: layers_of(src_layers),
             src_offsets: self.input_rect.to_vk(src_extent),
             dst_offsets: self.output_rect.to_vk(dst_extent),
         }
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateMipsPass {
+    pub name: String,
+    /// Target whose first level gets downsampled into the rest of its levels
+    pub target: String,
+    #[serde(default)]
+    pub is_disabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComputePass {
+    pub name: String,
+    pub program: String,
+    /// Sampled same as the inputs of a render pass
+    #[serde(default)]
+    pub inputs: Vec<StrOrObj<AttachmentInput>>,
+    /// Written as storage images
+    pub outputs: Vec<StrOrObj<AttachmentOutput>>,
+    pub dispatch: Dispatch,
+    #[serde(default)]
+    pub per_pass_updaters: Vec<UpdaterKind>,
+    pub per_pass_constant: Option<IndexMap<String, f32>>,
+    /// Buffers read, their addresses get pushed after the per pass data
+    #[serde(default)]
+    pub input_buffers: Vec<String>,
+    /// Buffers written, their addresses get pushed after the input ones
+    #[serde(default)]
+    pub output_buffers: Vec<String>,
+    #[serde(default)]
+    pub is_disabled: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Dispatch {
+    /// Attachment (and mip level) the invocation count is relative to
+    pub target: StrOrObj<AttachmentOutput>,
+    #[serde(default = "Dispatch::default_size")]
+    pub width: U32OrF32,
+    #[serde(default = "Dispatch::default_size")]
+    pub height: U32OrF32,
+    /// Has to match the local size declared in the compute shader
+    #[serde(default = "Dispatch::default_group_size")]
+    pub group_size: [u32; 2],
+}
+
+impl Dispatch {
+    fn default_size() -> U32OrF32 {
+        U32OrF32::F32(1.0)
+    }
+
+    pub const fn default_group_size() -> [u32; 2] {
+        [8, 8]
+    }
+
+    /// Work group count to cover the invocations relative to the target extent
+    pub fn group_count(&self, target_extent: Extent2D) -> [u32; 2] {
+        let invocations = Pipeline::extent_of(self.width, self.height, target_extent);
+        [
+            invocations.width.div_ceil(self.group_size[0].max(1)),
+            invocations.height.div_ceil(self.group_size[1].max(1)),
+        ]
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[derive(Copy, Clone)]
@@ -330,6 +569,9 @@ pub struct Program {
     pub fragment: String,
     #[serde(default)]
     pub geometry: String,
+    /// Compute programs have only this shader
+    #[serde(default)]
+    pub compute: String,
 }
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -441,6 +683,14 @@ impl Filtering {
         }
     }
 
+    pub fn try_of_u8(v: u8) -> RendResult<Self> {
+        if v > Self::MAX_VALUE {
+            Err(RendError::InvalidKind(format!("filtering {}", v)))
+        } else {
+            Ok(Self::of_u8(v))
+        }
+    }
+
     pub const fn of_u8(v: u8) -> Self {
         if v > Self::MAX_VALUE {
             panic!()
@@ -484,6 +734,14 @@ impl WrapMode {
         }
     }
 
+    pub fn try_of_u8(v: u8) -> RendResult<Self> {
+        if v > Self::MAX_VALUE {
+            Err(RendError::InvalidKind(format!("wrap mode {}", v)))
+        } else {
+            Ok(Self::of_u8(v))
+        }
+    }
+
     pub const fn of_u8(v: u8) -> Self {
         if v > Self::MAX_VALUE {
             panic!()
@@ -539,6 +797,14 @@ impl CompareFunc {
         }
     }
 
+    pub fn try_of_u8(v: u8) -> RendResult<Self> {
+        if v > Self::MAX_VALUE {
+            Err(RendError::InvalidKind(format!("compare func {}", v)))
+        } else {
+            Ok(Self::of_u8(v))
+        }
+    }
+
     pub const fn of_u8(v: u8) -> Self {
         if v > Self::MAX_VALUE {
             panic!()
@@ -791,26 +1057,41 @@ pub enum OptionPredefined {
     Yes,
 }
 
+impl OptionPredefined {
+    /// Name as written in the pipeline file
+    pub const fn name(&self) -> &'static str {
+        match self {
+            OptionPredefined::Default => "DEFAULT",
+            OptionPredefined::No => "NO",
+            OptionPredefined::Yes => "YES",
+        }
+    }
+}
+
+fn invalid_option<T>(desc: &str) -> RendError {
+    RendError::Pipeline(format!(
+        "invalid {} option {}",
+        std::any::type_name::<T>(),
+        desc
+    ))
+}
+
 pub trait DescHandler<T>
 where
     T: Predefined<T>,
 {
-    fn handle_specific(desc: &String) -> T {
-        panic!(
-            "{}::handle_specific::invalid({})",
-            std::any::type_name::<T>(),
-            desc
-        )
-    }
-    fn handle_option(desc: DescOption<T>) -> T {
+    fn handle_specific(desc: &str) -> RendResult<T> {
+        Err(invalid_option::<T>(desc))
+    }
+    fn handle_option(desc: DescOption<T>) -> RendResult<T> {
         match desc {
             DescOption::Predefined(v) => match v {
-                OptionPredefined::Default => T::def(),
-                OptionPredefined::No => T::no(),
-                OptionPredefined::Yes => T::yes(),
+                OptionPredefined::Default => Ok(T::def()),
+                OptionPredefined::No => Ok(T::no()),
+                OptionPredefined::Yes => Ok(T::yes()),
             },
             DescOption::Specific(v) => Self::handle_specific(&v),
-            DescOption::Configured(v) => v,
+            DescOption::Configured(v) => Ok(v),
         }
     }
 }
@@ -824,8 +1105,8 @@ impl DescHandler<StencilDesc> for Pipeline {
 }
 
 impl DescHandler<WriteDesc> for Pipeline {
-    fn handle_specific(desc: &String) -> WriteDesc {
-        match desc.as_str() {
+    fn handle_specific(desc: &str) -> RendResult<WriteDesc> {
+        let v = match desc {
             "COLOR" => WriteDesc {
                 color_mask: 0xFFFFFFFF,
                 depth: false,
@@ -841,8 +1122,9 @@ impl DescHandler<WriteDesc> for Pipeline {
                 depth: false,
                 stencil: true,
             },
-            _ => panic!("invalid {desc}"),
-        }
+            _ => return Err(invalid_option::<WriteDesc>(desc)),
+        };
+        Ok(v)
     }
 }
 impl DescHandler<TriangleDesc> for Pipeline {
@@ -854,14 +1136,15 @@ impl DescHandler<BlendDesc> for Pipeline {
 }
 
 impl DescHandler<DepthDesc> for Pipeline {
-    fn handle_specific(desc: &String) -> DepthDesc {
-        match desc.as_str() {
+    fn handle_specific(desc: &str) -> RendResult<DepthDesc> {
+        let v = match desc {
             "CLAMP" => DepthDesc {
                 clamping: true,
                 ..DepthDesc::def()
             },
-            _ => panic!("invalid {desc}"),
-        }
+            _ => return Err(invalid_option::<DepthDesc>(desc)),
+        };
+        Ok(v)
     }
 }
 
@@ -874,8 +1157,8 @@ impl DescHandler<ViewportDesc> for Pipeline {
 }
 
 impl DescHandler<ClearDesc> for Pipeline {
-    fn handle_specific(desc: &String) -> ClearDesc {
-        match desc.as_str() {
+    fn handle_specific(desc: &str) -> RendResult<ClearDesc> {
+        let v = match desc {
             "COLOR" => ClearDesc {
                 color: Some(DEFAULT_COLOR_CLEAR_VALUE),
                 depth: None,
@@ -891,8 +1174,9 @@ impl DescHandler<ClearDesc> for Pipeline {
                 depth: None,
                 stencil: Some(DEFAULT_STENCIL_CLEAR_VALUE),
             },
-            _ => panic!("invalid {desc}"),
-        }
+            _ => return Err(invalid_option::<ClearDesc>(desc)),
+        };
+        Ok(v)
     }
 }
 
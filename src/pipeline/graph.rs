@@ -0,0 +1,466 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+
+use crate::error::RendResult;
+
+use super::{
+    attachment::Attachment,
+    barrier_gen::{BarrierGen, Image},
+    file::*,
+};
+
+/// Levels and layers of a single target, collected from its per level and layer images
+#[derive(Default)]
+struct Subresources {
+    levels: BTreeSet<u8>,
+    layers: BTreeSet<u8>,
+}
+
+impl Subresources {
+    fn add(&mut self, image: &Image) {
+        self.levels.insert(image.level);
+        self.layers.insert(image.layer);
+    }
+
+    /// Compacts contiguous values, ie, `0-3,5`
+    fn ranges_of(values: &BTreeSet<u8>) -> String {
+        let mut ranges: Vec<(u8, u8)> = Vec::new();
+        for v in values.iter().copied() {
+            match ranges.last_mut() {
+                Some((_, end)) if *end as u16 + 1 == v as u16 => *end = v,
+                _ => ranges.push((v, v)),
+            }
+        }
+        ranges
+            .iter()
+            .map(|(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{}-{}", start, end)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Target name plus the levels and layers, only if the target has more than one of them
+    fn describe(&self, name: &str, graph: &Graph) -> String {
+        let mut desc = name.to_string();
+        if graph.levels_by_target.get(name).copied().unwrap_or(1) > 1 {
+            write!(desc, " mip {}", Self::ranges_of(&self.levels)).unwrap();
+        }
+        if graph.layers_by_target.get(name).copied().unwrap_or(1) > 1 {
+            write!(desc, " layer {}", Self::ranges_of(&self.layers)).unwrap();
+        }
+        desc
+    }
+
+    fn to_json(&self, name: &str) -> Value {
+        json!({
+            "name": name,
+            "levels": self.levels,
+            "layers": self.layers,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct BarrierKey {
+    name: String,
+    is_output: bool,
+    src_pass: usize,
+    old_layout: String,
+    new_layout: String,
+}
+
+struct PassNode {
+    name: String,
+    kind: &'static str,
+    is_disabled: bool,
+    inputs: IndexMap<String, Subresources>,
+    outputs: IndexMap<String, Subresources>,
+    blit: Option<String>,
+    barriers: IndexMap<BarrierKey, Subresources>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct EdgeKey {
+    /// None if no enabled pass writes the target
+    from: Option<usize>,
+    to: usize,
+    name: String,
+    is_previous_frame: bool,
+    is_buffer: bool,
+}
+
+struct TargetNode {
+    name: String,
+    is_read: bool,
+    is_written: bool,
+}
+
+struct Graph {
+    passes: Vec<PassNode>,
+    edges: IndexMap<EdgeKey, Subresources>,
+    targets: Vec<TargetNode>,
+    levels_by_target: HashMap<String, u8>,
+    layers_by_target: HashMap<String, u8>,
+}
+
+impl Pipeline {
+    /// Graphviz DOT graph of the pipeline, with passes as nodes and the targets flowing
+    /// between them as edges. Expects an already validated pipeline.
+    pub fn to_dot(&self) -> RendResult<String> {
+        Ok(Graph::of(self)?.to_dot())
+    }
+
+    /// Same graph as [`Pipeline::to_dot`], as JSON.
+    pub fn to_graph_json(&self) -> RendResult<Value> {
+        Ok(Graph::of(self)?.to_json())
+    }
+}
+
+impl Graph {
+    fn of(pip: &Pipeline) -> RendResult<Self> {
+        let resolve_state = |e: &BaseState| pip.state_of(e);
+        let all_passes: Vec<_> = pip
+            .passes
+            .iter()
+            .filter(|e| !matches!(e, PipelineStep::Include(_)))
+            .collect();
+        // Disabled passes don't take part in the barriers, but are still drawn with their targets
+        let images_gen = BarrierGen::new(&pip.targets, &all_passes, &resolve_state)?;
+        let enabled: Vec<usize> = (0..all_passes.len())
+            .filter(|i| !all_passes[*i].is_disabled())
+            .collect();
+        let enabled_passes: Vec<_> = enabled.iter().map(|i| all_passes[*i]).collect();
+        let barrier_gen = BarrierGen::new(&pip.targets, &enabled_passes, &resolve_state)?;
+
+        let group = |images: &[Image]| {
+            let mut grouped = IndexMap::<String, Subresources>::new();
+            for image in images {
+                grouped.entry(image.name.clone()).or_default().add(image);
+            }
+            grouped
+        };
+        let mut passes: Vec<PassNode> = all_passes
+            .iter()
+            .zip(images_gen.passes())
+            .map(|(step, pass)| PassNode {
+                name: pass.name.clone(),
+                kind: match step {
+                    PipelineStep::Render(_) => "render",
+                    PipelineStep::Blit(_) => "blit",
+                    PipelineStep::Compute(_) => "compute",
+                    PipelineStep::GenerateMips(_) => "generateMips",
+                    PipelineStep::Include(_) | PipelineStep::Repeat(_) => unreachable!(),
+                },
+                is_disabled: step.is_disabled(),
+                inputs: group(&pass.inputs),
+                outputs: group(&pass.outputs),
+                blit: match step {
+                    PipelineStep::Blit(p) => Some(format!(
+                        "{} -> {}, {}",
+                        Self::rect_of(&p.input_rect),
+                        Self::rect_of(&p.output_rect),
+                        p.filter
+                    )),
+                    _ => None,
+                },
+                barriers: IndexMap::new(),
+            })
+            .collect();
+
+        for (enabled_index, pass_index) in enabled.iter().enumerate() {
+            for barrier in barrier_gen.describe_barriers_for(enabled_index) {
+                let key = BarrierKey {
+                    name: barrier.image.name.clone(),
+                    is_output: barrier.is_output,
+                    src_pass: enabled[barrier.src_pass],
+                    old_layout: format!("{:?}", barrier.old_layout),
+                    new_layout: format!("{:?}", barrier.new_layout),
+                };
+                passes[*pass_index]
+                    .barriers
+                    .entry(key)
+                    .or_default()
+                    .add(&barrier.image);
+            }
+        }
+
+        /*
+         * Link every image a pass reads to the last enabled pass that wrote it, wrapping around
+         * to the previous frame. Images the pass writes itself, like generateMips levels, are
+         * internal to the pass and aren't linked. History images were written under their
+         * partner's name in the previous frame, by any pass of it.
+         */
+        let written = |i: usize, name: &str, image: &Image| {
+            barrier_gen.passes()[i]
+                .outputs
+                .iter()
+                .any(|e| e.name == name && e.level == image.level && e.layer == image.layer)
+        };
+        let mut edges = IndexMap::<EdgeKey, Subresources>::new();
+        for (current, pass) in barrier_gen.passes().iter().enumerate() {
+            for image in &pass.inputs {
+                if written(current, &image.name, image) {
+                    continue;
+                }
+                let count = enabled.len();
+                let partner = barrier_gen.history_partner(&image.name);
+                let last_offset = if partner.is_some() {
+                    count + current
+                } else {
+                    count - 1
+                };
+                // Offsets past the current pass are in the previous frame
+                let writer = (1..=last_offset)
+                    .map(|offset| (offset, (current + 2 * count - offset) % count))
+                    .find(|(offset, i)| match partner {
+                        Some(partner) if *offset > current => written(*i, partner, image),
+                        _ => written(*i, &image.name, image),
+                    });
+                let key = EdgeKey {
+                    from: writer.map(|(_, i)| enabled[i]),
+                    to: enabled[current],
+                    name: image.name.clone(),
+                    is_previous_frame: writer.is_some_and(|(offset, _)| offset > current),
+                    is_buffer: false,
+                };
+                edges.entry(key).or_default().add(image);
+            }
+            // Buffers have no levels nor layers, same linking otherwise
+            for buffer in &pass.input_buffers {
+                if pass.output_buffers.contains(buffer) {
+                    continue;
+                }
+                let count = enabled.len();
+                let writer = (1..count)
+                    .map(|offset| (current + count - offset) % count)
+                    .find(|i| barrier_gen.passes()[*i].output_buffers.contains(buffer));
+                let key = EdgeKey {
+                    from: writer.map(|i| enabled[i]),
+                    to: enabled[current],
+                    name: buffer.clone(),
+                    is_previous_frame: writer.is_some_and(|i| i >= current),
+                    is_buffer: true,
+                };
+                edges.entry(key).or_default();
+            }
+        }
+
+        let targets = pip
+            .targets
+            .iter()
+            .map(|t| {
+                // History targets get swapped every frame, using one uses both
+                let partner = t.history_partner();
+                let is_named = |e: &Image| e.name == t.name || partner.as_ref() == Some(&e.name);
+                let passes = barrier_gen.passes();
+                TargetNode {
+                    name: t.name.clone(),
+                    is_read: passes.iter().any(|p| p.inputs.iter().any(is_named)),
+                    is_written: passes.iter().any(|p| p.outputs.iter().any(is_named)),
+                }
+            })
+            .collect();
+
+        Ok(Graph {
+            passes,
+            edges,
+            targets,
+            levels_by_target: pip
+                .targets
+                .iter()
+                .map(|t| (t.name.clone(), t.level))
+                .collect(),
+            layers_by_target: pip
+                .targets
+                .iter()
+                .map(|t| (t.name.clone(), t.image_layers()))
+                .collect(),
+        })
+    }
+
+    fn rect_of(rect: &BlitRect) -> String {
+        fn value_of(v: U32OrF32) -> String {
+            match v {
+                U32OrF32::U32(v) => v.to_string(),
+                U32OrF32::F32(v) => format!("{}x", v),
+            }
+        }
+        format!(
+            "({}, {}) {}*{}",
+            value_of(rect.x),
+            value_of(rect.y),
+            value_of(rect.width),
+            value_of(rect.height)
+        )
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph pipeline {{").unwrap();
+        writeln!(dot, "  rankdir=LR;").unwrap();
+        writeln!(dot, "  node [shape=box, fontname=monospace];").unwrap();
+        writeln!(dot, "  edge [fontname=monospace];").unwrap();
+        for (i, pass) in self.passes.iter().enumerate() {
+            // Left justified lines, DOT's "\l" line ending
+            let mut label = format!("{} ({})\\l", Self::escape(&pass.name), pass.kind);
+            if let Some(blit) = &pass.blit {
+                write!(label, "{}\\l", Self::escape(blit)).unwrap();
+            }
+            for (key, subresources) in &pass.barriers {
+                write!(
+                    label,
+                    "{} {}: {} -> {} after {}\\l",
+                    if key.is_output { "out" } else { "in" },
+                    Self::escape(&subresources.describe(&key.name, self)),
+                    key.old_layout,
+                    key.new_layout,
+                    Self::escape(&self.passes[key.src_pass].name),
+                )
+                .unwrap();
+            }
+            if pass.is_disabled {
+                // Disabled passes don't run, list what they would use instead of linking it
+                for (prefix, images) in [("reads", &pass.inputs), ("writes", &pass.outputs)] {
+                    for (name, subresources) in images {
+                        let desc = subresources.describe(name, self);
+                        write!(label, "{} {}\\l", prefix, Self::escape(&desc)).unwrap();
+                    }
+                }
+            }
+            let style = if pass.is_disabled {
+                ", style=dashed, color=gray50, fontcolor=gray50"
+            } else {
+                ""
+            };
+            writeln!(dot, "  p{} [label=\"{}\"{}];", i, label, style).unwrap();
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            let problem = match (target.is_read, target.is_written) {
+                (true, true) => continue,
+                (false, false) => "unused",
+                (true, false) => "never written",
+                (false, true) => "never read",
+            };
+            writeln!(
+                dot,
+                "  t{} [label=\"{}\\n{}\", shape=note, color=red, fontcolor=red];",
+                i,
+                Self::escape(&target.name),
+                problem
+            )
+            .unwrap();
+        }
+        for (key, subresources) in &self.edges {
+            let from = match key.from {
+                Some(from) => format!("p{}", from),
+                None => match self.targets.iter().position(|e| e.name == key.name) {
+                    Some(target) => format!("t{}", target),
+                    // Not a target nor written by anything, validation would have caught it
+                    None => continue,
+                },
+            };
+            let mut label = Self::escape(&subresources.describe(&key.name, self));
+            let mut style = String::new();
+            if key.is_previous_frame {
+                label.push_str("\\n(previous frame)");
+                style.push_str(", style=dashed, constraint=false");
+            }
+            if key.is_buffer {
+                style.push_str(", color=blue, fontcolor=blue");
+            }
+            writeln!(
+                dot,
+                "  {} -> p{} [label=\"{}\"{}];",
+                from, key.to, label, style
+            )
+            .unwrap();
+        }
+        // Passes write into the default attachment last, show where it ends up
+        if let Some(last) = self
+            .passes
+            .iter()
+            .rposition(|p| !p.is_disabled && p.outputs.contains_key(Attachment::DEFAULT_NAME))
+        {
+            writeln!(dot, "  {} [shape=doublecircle];", Attachment::DEFAULT_NAME).unwrap();
+            writeln!(dot, "  p{} -> {};", last, Attachment::DEFAULT_NAME).unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    fn to_json(&self) -> Value {
+        let images_of = |images: &IndexMap<String, Subresources>| {
+            images
+                .iter()
+                .map(|(name, subresources)| subresources.to_json(name))
+                .collect::<Vec<_>>()
+        };
+        let passes: Vec<_> = self
+            .passes
+            .iter()
+            .map(|pass| {
+                let barriers: Vec<_> = pass
+                    .barriers
+                    .iter()
+                    .map(|(key, subresources)| {
+                        let mut barrier = subresources.to_json(&key.name);
+                        barrier["isOutput"] = json!(key.is_output);
+                        barrier["after"] = json!(self.passes[key.src_pass].name);
+                        barrier["oldLayout"] = json!(key.old_layout);
+                        barrier["newLayout"] = json!(key.new_layout);
+                        barrier
+                    })
+                    .collect();
+                json!({
+                    "name": pass.name,
+                    "type": pass.kind,
+                    "isDisabled": pass.is_disabled,
+                    "inputs": images_of(&pass.inputs),
+                    "outputs": images_of(&pass.outputs),
+                    "blit": pass.blit,
+                    "barriers": barriers,
+                })
+            })
+            .collect();
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|(key, subresources)| {
+                let mut edge = subresources.to_json(&key.name);
+                edge["from"] = json!(key.from.map(|i| &self.passes[i].name));
+                edge["to"] = json!(self.passes[key.to].name);
+                edge["isPreviousFrame"] = json!(key.is_previous_frame);
+                edge["isBuffer"] = json!(key.is_buffer);
+                edge
+            })
+            .collect();
+        let targets: Vec<_> = self
+            .targets
+            .iter()
+            .map(|t| {
+                json!({
+                    "name": t.name,
+                    "isRead": t.is_read,
+                    "isWritten": t.is_written,
+                })
+            })
+            .collect();
+        json!({
+            "passes": passes,
+            "edges": edges,
+            "targets": targets,
+        })
+    }
+}
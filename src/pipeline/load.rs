@@ -2,11 +2,12 @@ use ash::vk::{self, DescriptorType, Extent2D, ShaderStageFlags};
 
 use std::{
     collections::{HashMap, HashSet},
-    process::Command,
+    process::{Command, Stdio},
 };
 
 use super::{
     barrier_gen::BarrierGen,
+    buffer::PipelineBuffer,
     descriptor::DescriptorGroup,
     file::*,
     sampler::{Sampler, SamplerKey},
@@ -15,76 +16,126 @@ use super::{
 use crate::pipeline::attachment::Attachment;
 use crate::{
     context::VulkanContext,
+    error::{RendError, RendResult},
     pipeline::descriptor,
     texture::{self, TextureKind},
 };
 use crate::{shader, shader_resource::ResourceKind};
 
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Stable across runs and toolchains, unlike the std hasher
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
+
 impl Pipeline {
-    pub fn read(name: Option<&str>) -> Self {
+    pub fn read(name: Option<&str>) -> RendResult<Self> {
         let name = name.unwrap_or("pipeline.json");
-        let file = std::fs::File::open(name)
-            .expect(format!("failed opening the pipeline at {}", name).as_str());
-        let pipeline: Pipeline = serde_json::from_reader(file)
-            .expect(format!("couldn't parse the pipeline at {}", name).as_str());
+        let file = std::fs::File::open(name).map_err(|e| {
+            RendError::Pipeline(format!("failed opening the pipeline at {}: {}", name, e))
+        })?;
+        let pipeline: Pipeline = serde_json::from_reader(file).map_err(|e| {
+            RendError::Pipeline(format!("couldn't parse the pipeline at {}: {}", name, e))
+        })?;
         let mut passes = Vec::new();
         let mut programs = pipeline.programs;
         let mut targets = pipeline.targets;
+        let mut buffers = pipeline.buffers;
         let mut shared_state = pipeline.shared_state;
         for p in pipeline.passes {
             match p {
                 PipelineStep::Include(pass) => {
-                    let pip = Self::read(Some(&pass.name));
+                    let pip = Self::read(Some(&pass.name))?;
                     programs.extend(pip.programs);
                     targets.extend(pip.targets);
+                    buffers.extend(pip.buffers);
                     shared_state.extend(pip.shared_state);
                     if !pass.is_disabled {
                         passes.extend(pip.passes)
                     }
                 }
+                PipelineStep::Repeat(pass) => {
+                    let steps = pass.expand().map_err(|e| {
+                        RendError::Pipeline(format!(
+                            "failed repeating pass {} of the pipeline at {}: {}",
+                            pass.name, name, e
+                        ))
+                    })?;
+                    passes.extend(steps);
+                }
                 _ => {
                     passes.push(p);
                 }
             };
         }
-        Pipeline {
+        // Targets written by compute passes need to be usable as storage images
+        for pass in &passes {
+            let PipelineStep::Compute(pass) = pass else {
+                continue;
+            };
+            for output in pass.outputs.iter().map(|e| e.get()) {
+                if let Some(target) = targets.iter_mut().find(|e| e.name == output.name) {
+                    target.is_storage = true;
+                }
+            }
+        }
+        // History targets get a second target with the previous frame's contents
+        let history_targets: Vec<_> = targets
+            .iter()
+            .filter(|e| e.history)
+            .map(|e| e.history_target())
+            .collect();
+        targets.extend(history_targets);
+        Ok(Pipeline {
             passes,
             programs,
             targets,
+            buffers,
             shared_state,
-        }
+        })
     }
 
     fn spirv_path_of(shader: &str) -> String {
         format!("shader/vk/{}.spv", shader)
     }
 
-    fn source_path_of(shader: &str) -> String {
+    pub(super) fn source_path_of(shader: &str) -> String {
         format!("shader/{}", shader)
     }
 
-    fn compile_shader_programs<'a>(
-        ctx: &'a VulkanContext,
-        programs: &'a [Program],
-    ) -> HashMap<String, shader::ShaderProgram<'a>> {
+    /// Compiles every shader the programs use, returning the SPIR-V binaries by shader name.
+    pub(super) fn compile_shaders(programs: &[Program]) -> RendResult<HashMap<String, Vec<u32>>> {
         // Create dest folder for all of the SPIR-V binaries
         let base_path = Self::spirv_path_of("tmp");
         let base_path = std::path::Path::new(&base_path).parent().unwrap();
         std::fs::DirBuilder::new()
             .recursive(true)
             .create(base_path)
-            .expect(&format!(
-                "failed creating the SPIR-V folder at {}!",
-                base_path.to_str().unwrap()
-            ));
+            .map_err(|e| {
+                RendError::Shader(format!(
+                    "failed creating the SPIR-V folder at {}: {}",
+                    base_path.to_str().unwrap(),
+                    e
+                ))
+            })?;
         // Same shader could be used in multiple programs, flatten and de-duplicate
         let shaders = programs
             .iter()
-            .flat_map(|p| vec![&p.fragment, &p.vertex, &p.geometry])
+            .flat_map(|p| vec![&p.fragment, &p.vertex, &p.geometry, &p.compute])
             .filter(|f| !f.is_empty())
             .collect::<HashSet<_>>();
-        // Invoke glslang and compile each shader into SPIR-V
-        for shader in shaders {
+        /*
+         * Launch glslang for every shader whose source, includes or flags changed since it was
+         * last compiled, all at once, then wait for them all. Unchanged ones are already in
+         * the SPIR-V folder from a previous run.
+         */
+        let mut compiling = Vec::new();
+        let mut errors = Vec::new();
+        for shader in &shaders {
             let spirv_path = Self::spirv_path_of(shader);
             let source_path = Self::source_path_of(shader);
             // Some flags so the various macros work
@@ -99,494 +150,955 @@ impl Pipeline {
                 "-o",
                 &spirv_path,
             ];
-            // TODO: Could launch all of these these concurrently and wait for them all.
+            let hash = format!("{:016x}", Self::shader_hash_of(&source_path, &args));
+            let hash_path = Self::hash_path_of(shader);
+            let is_cached = std::path::Path::new(&spirv_path).is_file()
+                && std::fs::read_to_string(&hash_path).is_ok_and(|e| e == hash);
+            if is_cached {
+                continue;
+            }
             log::info!("compiling shader {} with args {:?}...", shader, args);
-            let output = Command::new("glslangValidator")
+            let child = Command::new("glslangValidator")
                 .args(args)
-                .output()
-                .expect(format!("failed to start compiler for {}!", &shader).as_str());
-
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn();
+            match child {
+                Ok(child) => compiling.push((shader, hash_path, hash, child)),
+                Err(e) => {
+                    errors.push(format!("failed to start compiler for {}: {}", shader, e));
+                    // Compiler is missing most likely, no point in trying the rest
+                    break;
+                }
+            }
+        }
+        let mut rebuilt = Vec::new();
+        for (shader, hash_path, hash, child) in compiling {
+            let output = match child.wait_with_output() {
+                Ok(output) => output,
+                Err(e) => {
+                    errors.push(format!("failed waiting the compiler for {}: {}", shader, e));
+                    continue;
+                }
+            };
             if !output.status.success() {
                 let msg = String::from_utf8_lossy(if !output.stdout.is_empty() {
                     &output.stdout
                 } else {
                     &output.stderr
                 });
-                panic!(
+                errors.push(format!(
                     "error compiling! shader: {}, status: {}, error: {}",
                     shader, output.status, msg
-                )
+                ));
+                // Whatever SPIR-V was there no longer matches the source
+                let _ = std::fs::remove_file(&hash_path);
+                continue;
+            }
+            // Only cache it once it compiled, otherwise it'd be skipped next time
+            if let Err(e) = std::fs::write(&hash_path, hash) {
+                log::warn!("failed writing the shader hash at {}: {}", hash_path, e);
             }
             log::info!("shader {} compiled!", shader);
+            rebuilt.push(shader.as_str());
         }
+        if !errors.is_empty() {
+            return Err(RendError::Shader(errors.join("\n")));
+        }
+        rebuilt.sort();
+        log::info!(
+            "{} of {} shaders rebuilt: {:?}",
+            rebuilt.len(),
+            shaders.len(),
+            rebuilt
+        );
+
+        let load_spirv = |name: &String| {
+            let path = Self::spirv_path_of(name);
+            let mut file = std::fs::File::open(&path)
+                .map_err(|e| RendError::Shader(format!("spirv {path} failed to open: {e}")))?;
+            let bin = ash::util::read_spv(&mut file)
+                .map_err(|e| RendError::Shader(format!("spirv {path} failed to load: {e}")))?;
+            Ok((name.to_string(), bin))
+        };
+
+        shaders.into_iter().map(load_spirv).collect()
+    }
+
+    fn hash_path_of(shader: &str) -> String {
+        format!("shader/vk/{}.hash", shader)
+    }
+
+    /// Hashes the shader source along everything it includes and the compiler flags.
+    fn shader_hash_of(source_path: &str, args: &[&str]) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        for arg in args {
+            hash = fnv1a(hash, arg.as_bytes());
+        }
+        let mut visited = HashSet::new();
+        Self::hash_source(source_path, hash, &mut visited)
+    }
+
+    fn hash_source(path: &str, hash: u64, visited: &mut HashSet<String>) -> u64 {
+        // Include guards may make files include each other
+        if !visited.insert(path.to_string()) {
+            return hash;
+        }
+        let hash = fnv1a(hash, path.as_bytes());
+        let Ok(source) = std::fs::read_to_string(path) else {
+            // Compiler will report it, hashing the path is enough
+            return hash;
+        };
+        let mut hash = fnv1a(hash, source.as_bytes());
+        /*
+         * Follow every include regardless of the preprocessor conditionals around it,
+         * at worst a shader gets recompiled when it didn't need to.
+         */
+        let dir = std::path::Path::new(path)
+            .parent()
+            .unwrap_or(std::path::Path::new(""));
+        for line in source.lines() {
+            let Some(include) = line.trim_start().strip_prefix("#include") else {
+                continue;
+            };
+            let Some(name) = include
+                .trim()
+                .strip_prefix('"')
+                .and_then(|e| e.strip_suffix('"'))
+            else {
+                continue;
+            };
+            let include_path = dir.join(name);
+            hash = Self::hash_source(include_path.to_str().unwrap(), hash, visited);
+        }
+        hash
+    }
 
+    fn shader_programs_of<'a>(
+        ctx: &'a VulkanContext,
+        programs: &'a [Program],
+        spirv_by_name: &HashMap<String, Vec<u32>>,
+    ) -> RendResult<HashMap<String, shader::ShaderProgram<'a>>> {
         let load_spirv = |name: &str| match name.is_empty() {
-            true => None,
-            false => {
-                let path = Self::spirv_path_of(name);
-                let mut file =
-                    std::fs::File::open(&path).expect(&format!("spirv {path} failed to open!"));
-                let bin = ash::util::read_spv(&mut file)
-                    .expect(&format!("spirv {} failed to load!", path));
-                Some((name.to_string(), bin))
-            }
+            true => Ok(None),
+            false => spirv_by_name
+                .get(name)
+                .map(|bin| Some((name.to_string(), bin.clone())))
+                .ok_or_else(|| RendError::Pipeline(format!("spirv {} missing!", name))),
         };
 
-        let programs_by_name: HashMap<_, _> = programs
-            .iter()
-            .map(|p| {
-                (
+        let mut programs_by_name = HashMap::with_capacity(programs.len());
+        for p in programs {
+            let program = load_spirv(&p.vertex).and_then(|vertex| {
+                shader::ShaderProgram::new(
+                    ctx,
                     p.name.clone(),
-                    shader::ShaderProgram::new(
-                        ctx,
-                        p.name.clone(),
-                        load_spirv(&p.vertex),
-                        load_spirv(&p.fragment),
-                        load_spirv(&p.geometry),
-                    ),
+                    vertex,
+                    load_spirv(&p.fragment)?,
+                    load_spirv(&p.geometry)?,
+                    load_spirv(&p.compute)?,
                 )
-            })
-            .collect();
+            });
+            match program {
+                Ok(program) => {
+                    programs_by_name.insert(p.name.clone(), program);
+                }
+                Err(err) => {
+                    for program in programs_by_name.values() {
+                        program.destroy(&ctx.device);
+                    }
+                    return Err(err);
+                }
+            }
+        }
 
-        return programs_by_name;
+        Ok(programs_by_name)
     }
 
     pub fn load(
         ctx: &VulkanContext,
         internal_extent: Extent2D,
         external_extent: Extent2D,
-        default_attachment: Attachment,
+        default_attachment: Option<Attachment>,
         is_validation_layer_enabled: bool,
         name: Option<&str>,
-    ) -> crate::pipeline::Pipeline {
-        let pip = Self::read(name);
-        let shader_programs_by_name = Self::compile_shader_programs(ctx, &pip.programs);
-        // Filter out disabled passes
-        let enabled_passes: Vec<_> = pip
-            .passes
-            .into_iter()
-            .filter(|e| !e.is_disabled())
-            .collect();
-        let shared_state = pip.shared_state;
-        let resolve_state = |e: &BaseState| match e {
-            BaseState::State(s) => s.clone(),
-            BaseState::Reference(r) => {
-                let tmp = shared_state
-                    .get(&r.name)
-                    .expect(&format!("missing state with name {}", &r.name));
-                tmp.clone()
+    ) -> RendResult<crate::pipeline::Pipeline> {
+        let path = name.unwrap_or("pipeline.json").to_string();
+        let mut pip = Self::read(Some(&path))?;
+        pip.cull()?;
+        let lifetimes = pip.transient_lifetimes()?;
+        let spirv_by_name = Self::compile_shaders(&pip.programs)?;
+        let targets: Vec<_> = pip.targets.iter().collect();
+        let (mut attachments, aliased_memory) =
+            Self::make_attachments(ctx, &targets, &lifetimes, internal_extent, external_extent)?;
+        /*
+         * Default attachment is provided by the caller since it depends on the swapchain.
+         * Without one we're rendering headless, so the pipeline owns it instead.
+         */
+        let buffers = match default_attachment {
+            Some(attachment) => Ok(attachment),
+            None => Self::headless_default_attachment(ctx, external_extent),
+        }
+        .and_then(|default_attachment| {
+            attachments.push(default_attachment);
+            Self::make_buffers(ctx, &pip.buffers)
+        });
+        let buffers = match buffers {
+            Ok(buffers) => buffers,
+            Err(err) => {
+                for attachment in &attachments {
+                    attachment.destroy(&ctx.device);
+                }
+                for memory in &aliased_memory {
+                    unsafe { ctx.device.free_memory(*memory, None) };
+                }
+                return Err(err);
             }
         };
-
-        let barrier_gen = BarrierGen::new(&pip.targets, &enabled_passes, &resolve_state);
-
-        let mut attachments_by_name: HashMap<_, _> = pip
-            .targets
-            .iter()
-            .map(|f| {
-                let extent =
-                    Self::attachment_extent_of(f.width, f.height, internal_extent, external_extent);
-                let texture = texture::make(
-                    ctx,
-                    f.name.clone(),
-                    extent.width,
-                    extent.height,
-                    f.level,
-                    f.format,
-                    TextureKind::T2D,
-                    true,
-                );
-                let per_level_views =
-                    Attachment::per_level_views_of(ctx, texture.image, f.format, f.level);
-                ctx.try_set_debug_name(&format!("{}_att_image", f.name), texture.image);
-                ctx.try_set_debug_name(&format!("{}_att_image_memory", f.name), texture.memory);
-                ctx.try_set_debug_name(&format!("{}_att_image_view", f.name), texture.view);
-                for (i, view) in per_level_views.iter().enumerate() {
-                    ctx.try_set_debug_name(&format!("{}_att_image_view{}", f.name, i), *view);
-                }
-                // key -> name, value -> attachment
-                (
-                    f.name.clone(),
-                    Attachment {
-                        name: f.name.clone(),
-                        format: f.format,
-                        vk_format: f.format.to_vk(),
-                        image: texture.image,
-                        memory: texture.memory,
-                        view: texture.view,
-                        extent,
-                        per_level_views,
-                        level_usage: 0,
-                        descriptor_index: 0,
-                    },
-                )
-            })
-            .collect();
-        // Default attachment is provided by the caller since it depends on the swapchain.
-        attachments_by_name.insert(default_attachment.name.clone(), default_attachment);
         // Descriptor pool to use across all descriptor sets
         let descriptor_pool = super::descriptor::make_pool(ctx, true);
         ctx.try_set_debug_name("main_descriptor_pool", descriptor_pool);
+        // Per pass descriptor sets get their own pool, so they can be reset when rebuilding stages
+        let stage_descriptor_pool = super::descriptor::make_pool(ctx, false);
+        ctx.try_set_debug_name("stage_descriptor_pool", stage_descriptor_pool);
         let image_descriptors = Self::image_descriptors(ctx, descriptor_pool);
-        let mut sampler_descriptors = Self::sampler_descriptors(ctx, descriptor_pool);
+        let sampler_descriptors = Self::sampler_descriptors(ctx, descriptor_pool);
+
+        let mut pipeline = crate::pipeline::Pipeline {
+            stages: Vec::new(),
+            swapped_stages: Vec::new(),
+            is_history_swapped: false,
+            attachments,
+            aliased_memory,
+            buffers,
+            descriptor_pool,
+            stage_descriptor_pool,
+            image_descriptors,
+            sampler_descriptors,
+            samplers_by_key: HashMap::new(),
+            file: pip,
+            path,
+            spirv_by_name,
+            internal_extent,
+            external_extent,
+            is_validation_layer_enabled,
+        };
+        if let Err(err) = pipeline.build_stages(ctx) {
+            pipeline.destroy(&ctx.device);
+            return Err(err);
+        }
+        Ok(pipeline)
+    }
+
+    /// The state itself, or the shared state it refers to.
+    pub(super) fn state_of(&self, state: &BaseState) -> RendResult<State> {
+        match state {
+            BaseState::State(s) => Ok(s.clone()),
+            BaseState::Reference(r) => {
+                self.shared_state.get(&r.name).cloned().ok_or_else(|| {
+                    RendError::Pipeline(format!("missing state with name {}", &r.name))
+                })
+            }
+        }
+    }
+
+    /// Builds every stage of the pipeline out of its description and current attachments.
+    /// With the history swapped, only builds the stages using history targets, with each
+    /// of them using the attachment of its partner instead. Nothing built is kept on failure.
+    pub(super) fn make_stages(
+        ctx: &VulkanContext,
+        pipeline: &mut crate::pipeline::Pipeline,
+        is_history_swapped: bool,
+    ) -> RendResult<Vec<Box<dyn Stage>>> {
+        let pip = &pipeline.file;
+        let stage_descriptor_pool = pipeline.stage_descriptor_pool;
+        let image_descriptors = &pipeline.image_descriptors;
+        let sampler_descriptors = &mut pipeline.sampler_descriptors;
+        let samplers_by_key = &mut pipeline.samplers_by_key;
+        let is_validation_layer_enabled = pipeline.is_validation_layer_enabled;
+        // Filter out disabled passes
+        let enabled_passes: Vec<_> = pip.passes.iter().filter(|e| !e.is_disabled()).collect();
+        let resolve_state = |e: &BaseState| pip.state_of(e);
 
-        let mut samplers_by_key: HashMap<SamplerKey, Sampler> = HashMap::new();
+        let barrier_gen = BarrierGen::new(&pip.targets, &enabled_passes, &resolve_state)?;
 
+        let attachments_by_name =
+            pip.attachments_by_name_of(&pipeline.attachments, is_history_swapped)?;
+
+        let buffers_by_name: HashMap<_, _> = pipeline
+            .buffers
+            .iter()
+            .map(|e| (e.name.as_str(), e))
+            .collect();
+
+        let shader_programs_by_name =
+            Self::shader_programs_of(ctx, &pip.programs, &pipeline.spirv_by_name)?;
+        let shared_set_layouts = [sampler_descriptors.layout, image_descriptors.layout];
         let mut stages = Vec::<Box<dyn Stage>>::with_capacity(enabled_passes.len());
+        let mut result = Ok(());
         for (pass_index, pass) in enabled_passes.into_iter().enumerate() {
-            let render_pass = match pass {
-                PipelineStep::Blit(blit) => {
-                    let blit_stage = Self::build_blit_stage(
-                        &blit,
-                        &barrier_gen,
-                        pass_index,
-                        is_validation_layer_enabled,
-                        &attachments_by_name,
-                    );
-                    stages.push(Box::new(blit_stage));
-                    // Nothing else to do for blit stages
-                    continue;
+            if is_history_swapped && !barrier_gen.is_using_history(pass_index) {
+                // Same stage for both frames, no need to build it again
+                continue;
+            }
+            let stage: RendResult<Box<dyn Stage>> = match pass {
+                PipelineStep::Blit(blit) => Self::build_blit_stage(
+                    blit,
+                    &barrier_gen,
+                    pass_index,
+                    is_validation_layer_enabled,
+                    &attachments_by_name,
+                )
+                .map(|e| Box::new(e) as Box<dyn Stage>),
+                PipelineStep::GenerateMips(mips) => Self::build_mips_stage(
+                    mips,
+                    &barrier_gen,
+                    pass_index,
+                    is_validation_layer_enabled,
+                    &attachments_by_name,
+                )
+                .map(|e| Box::new(e) as Box<dyn Stage>),
+                PipelineStep::Compute(compute) => Self::build_compute_stage(
+                    ctx,
+                    compute,
+                    &barrier_gen,
+                    pass_index,
+                    is_validation_layer_enabled,
+                    &attachments_by_name,
+                    &buffers_by_name,
+                    &shader_programs_by_name,
+                    stage_descriptor_pool,
+                    &shared_set_layouts,
+                    samplers_by_key,
+                )
+                .map(|e| Box::new(e) as Box<dyn Stage>),
+                PipelineStep::Render(render) => Self::build_render_stage(
+                    ctx,
+                    render,
+                    &barrier_gen,
+                    pass_index,
+                    is_validation_layer_enabled,
+                    &attachments_by_name,
+                    &buffers_by_name,
+                    &shader_programs_by_name,
+                    stage_descriptor_pool,
+                    &shared_set_layouts,
+                    samplers_by_key,
+                    &resolve_state,
+                )
+                .map(|e| Box::new(e) as Box<dyn Stage>),
+                // Reading the pipeline expands these into regular passes
+                PipelineStep::Include(_) | PipelineStep::Repeat(_) => Err(RendError::Pipeline(
+                    format!("unsupported pipeline step {}!", pass.name()),
+                )),
+            };
+            match stage {
+                Ok(stage) => stages.push(stage),
+                Err(err) => {
+                    result = Err(err);
+                    break;
                 }
-                // Render pass requires the longer setup below
-                PipelineStep::Render(render) => render,
-                _ => panic!("unsupported pipeline step!"),
+            }
+        }
+        for shader in shader_programs_by_name
+            .into_values()
+            .flat_map(|e| e.shaders)
+        {
+            // No longer need them.
+            unsafe { ctx.device.destroy_shader_module(shader.info.module, None) };
+        }
+        if let Err(err) = result {
+            // Stages built so far are no use without the rest
+            for stage in stages {
+                stage.destroy(&ctx.device);
+            }
+            return Err(err);
+        }
+
+        //  Place all sampler descriptors into the descriptor buffer and write to the GPU
+        let mut positioned_samplers = samplers_by_key.values().collect::<Vec<_>>();
+        positioned_samplers.sort_by(|a, b| a.position.cmp(&b.position));
+        for sampler in positioned_samplers {
+            sampler_descriptors.place_sampler_at(ctx, sampler.position as u32, sampler.sampler);
+        }
+        // TODO: Deferred descriptor writes
+        // sampler_descriptors.into_device();
+        // image_descriptors.into_device();
+        Ok(stages)
+    }
+
+    /// Attachments keyed by their name. With the history swapped, history targets get the
+    /// attachment of their partner instead.
+    pub(super) fn attachments_by_name_of(
+        &self,
+        attachments: &[Attachment],
+        is_history_swapped: bool,
+    ) -> RendResult<HashMap<String, Attachment>> {
+        let mut attachments_by_name: HashMap<_, _> = attachments
+            .iter()
+            .map(|e| (e.name.clone(), e.clone()))
+            .collect();
+        if !is_history_swapped {
+            return Ok(attachments_by_name);
+        }
+        for target in &self.targets {
+            let Some(partner) = target.history_partner() else {
+                continue;
             };
-            let render_pass_state = resolve_state(&render_pass.state);
-            let writing = Self::handle_option(render_pass_state.writing.clone());
-            let depth = Self::handle_option(render_pass_state.depth.clone());
-            let blending = Self::handle_option(render_pass_state.blending.clone());
-            let stencil = Self::handle_option(render_pass_state.stencil.clone());
-            let viewport = Self::handle_option(render_pass_state.viewport.clone());
-            let scissor = Self::handle_option(render_pass_state.scissor.clone());
-            let triangle = Self::handle_option(render_pass_state.triangle.clone());
-            let clearing = Self::handle_option(render_pass_state.clearing.clone());
-            let stencil_op_state = stencil.to_vk();
-            let depth_stencil_state = depth.to_vk(stencil_op_state, &writing);
-            let rasterization_state = triangle.to_vk(depth);
-            let depth_stencil_attachment = render_pass.depth_stencil.as_ref().map(|name| {
-                attachments_by_name.get(&name.to_string()).expect(&format!(
-                    "depth stencil attachment {} missing for pass {}!",
-                    name, render_pass.name
-                ))
-            });
-            let binding_descs = [];
-            let attrib_descs = [];
-            let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default()
-                .vertex_binding_descriptions(&binding_descs)
-                .vertex_attribute_descriptions(&attrib_descs);
-            let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-                ..Default::default()
+            let attachment = attachments
+                .iter()
+                .find(|e| e.name == partner)
+                .ok_or_else(|| RendError::Pipeline(format!("attachment {} missing!", partner)))?;
+            // Partner's images under this name, barriers refer to attachments by name
+            let swapped = Attachment {
+                name: target.name.clone(),
+                ..attachment.clone()
             };
+            attachments_by_name.insert(target.name.clone(), swapped);
+        }
+        Ok(attachments_by_name)
+    }
 
-            let dynamic_state_info =
-                vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&[]);
-            let render_pass_inputs = render_pass
-                .inputs
-                .iter()
-                .map(|e| e.get())
-                .collect::<Vec<_>>();
-            let render_pass_outputs = render_pass
-                .outputs
-                .iter()
-                .map(|e| e.get())
-                .collect::<Vec<_>>();
-            // TODO: Check why if depth output isn't placed last, VVL errors get reported
-            let attachment_outputs =
-                Self::find_attachments(&render_pass_outputs, &attachments_by_name);
-            let attachment_inputs =
-                Self::find_attachments(&render_pass_inputs, &attachments_by_name);
-            let attachment_samplers: Vec<_> = render_pass_inputs
-                .iter()
-                .map(|i| {
-                    let sampler = Self::handle_option(i.sampler.clone());
-                    let key = SamplerKey {
-                        filter: sampler.filter,
-                        wrap_mode: sampler.wrap_mode,
-                        compare_func: sampler.compare_func,
-                        anisotropy: sampler.anisotropy,
-                    };
-                    match samplers_by_key.get(&key) {
-                        Some(s) => s.clone(),
-                        None => {
-                            let smp = Sampler::of_key(ctx, key, samplers_by_key.len() as u8);
-                            samplers_by_key.insert(key, smp.clone());
-                            smp
-                        }
-                    }
-                })
-                .collect();
-            let attachment_output_formats: Vec<_> =
-                attachment_outputs.iter().map(|e| e.vk_format).collect();
-            // We only need blend state for color attachments, ignoring depth/stencil
-            let _attachments =
-                blending.to_attachment_states(attachment_output_formats.len() as u32);
-            let blend_state = blending.to_vk().attachments(&_attachments);
-
-            let mut rendering_pipeline_info = {
-                let mut b = vk::PipelineRenderingCreateInfo::default()
-                    .color_attachment_formats(&attachment_output_formats);
-                if writing.stencil || !stencil.disabled {
-                    let att = depth_stencil_attachment.expect(&format!(
+    #[allow(clippy::too_many_arguments)]
+    fn build_render_stage<'a>(
+        ctx: &VulkanContext,
+        render_pass: &RenderPass,
+        barrier_gen: &BarrierGen,
+        pass_index: usize,
+        is_validation_layer_enabled: bool,
+        attachments_by_name: &HashMap<String, Attachment>,
+        buffers_by_name: &HashMap<&str, &PipelineBuffer>,
+        shader_programs_by_name: &HashMap<String, shader::ShaderProgram>,
+        stage_descriptor_pool: vk::DescriptorPool,
+        shared_set_layouts: &[vk::DescriptorSetLayout],
+        samplers_by_key: &mut HashMap<SamplerKey, Sampler>,
+        resolve_state: &dyn Fn(&BaseState) -> RendResult<State>,
+    ) -> RendResult<crate::pipeline::render_stage::RenderStage<'a>> {
+        let render_pass_state = resolve_state(&render_pass.state)?;
+        let writing = Self::handle_option(render_pass_state.writing.clone())?;
+        let depth = Self::handle_option(render_pass_state.depth.clone())?;
+        let blending = Self::handle_option(render_pass_state.blending.clone())?;
+        let stencil = Self::handle_option(render_pass_state.stencil.clone())?;
+        let viewport = Self::handle_option(render_pass_state.viewport.clone())?;
+        let scissor = Self::handle_option(render_pass_state.scissor.clone())?;
+        let triangle = Self::handle_option(render_pass_state.triangle.clone())?;
+        let clearing = Self::handle_option(render_pass_state.clearing.clone())?;
+        let stencil_op_state = stencil.to_vk();
+        let depth_stencil_state = depth.to_vk(stencil_op_state, &writing);
+        let rasterization_state = triangle.to_vk(depth);
+        let depth_stencil_attachment = match &render_pass.depth_stencil {
+            Some(e) => Some(Self::find_attachments(&[e.get()], attachments_by_name)?.remove(0)),
+            None => None,
+        };
+        let depth_stencil_attachment = depth_stencil_attachment.as_ref();
+        let binding_descs = [];
+        let attrib_descs = [];
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&binding_descs)
+            .vertex_attribute_descriptions(&attrib_descs);
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+
+        // Set by the stage when rendering, so they can change without rebuilding the pipeline
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        let render_pass_inputs = render_pass
+            .inputs
+            .iter()
+            .map(|e| e.get())
+            .collect::<Vec<_>>();
+        let render_pass_outputs = render_pass
+            .outputs
+            .iter()
+            .map(|e| e.get())
+            .collect::<Vec<_>>();
+        // TODO: Check why if depth output isn't placed last, VVL errors get reported
+        let attachment_outputs = Self::find_attachments(&render_pass_outputs, attachments_by_name)?;
+        let attachment_inputs = Self::find_attachments(&render_pass_inputs, attachments_by_name)?;
+        let attachment_samplers = render_pass_inputs
+            .iter()
+            .map(|i| Self::sampler_of(ctx, samplers_by_key, i))
+            .collect::<RendResult<Vec<_>>>()?;
+        let attachment_output_formats: Vec<_> =
+            attachment_outputs.iter().map(|e| e.vk_format).collect();
+        // We only need blend state for color attachments, ignoring depth/stencil
+        let _attachments = blending.to_attachment_states(attachment_output_formats.len() as u32);
+        let blend_state = blending.to_vk().attachments(&_attachments);
+
+        let mut rendering_pipeline_info = {
+            let mut b = vk::PipelineRenderingCreateInfo::default()
+                .color_attachment_formats(&attachment_output_formats);
+            if writing.stencil || !stencil.disabled {
+                let att = depth_stencil_attachment.ok_or_else(|| {
+                    RendError::Pipeline(format!(
                         "stencil attachment for writing/testing not set for pass {}!",
                         render_pass.name
-                    ));
-                    b = b.stencil_attachment_format(att.vk_format);
-                }
-                if writing.depth || depth.testing {
-                    let att = depth_stencil_attachment.expect(&format!(
+                    ))
+                })?;
+                b = b.stencil_attachment_format(att.vk_format);
+            }
+            if writing.depth || depth.testing {
+                let att = depth_stencil_attachment.ok_or_else(|| {
+                    RendError::Pipeline(format!(
                         "depth attachment for writing/testing not set for pass {}!",
                         render_pass.name
-                    ));
-                    b = b.depth_attachment_format(att.vk_format);
+                    ))
+                })?;
+                b = b.depth_attachment_format(att.vk_format);
+            }
+            b
+        };
+
+        // All outputs have the same sample count, a pass can't mix them
+        let rasterization_samples = attachment_outputs
+            .first()
+            .or(depth_stencil_attachment)
+            .map_or(vk::SampleCountFlags::TYPE_1, |e| e.samples);
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples,
+            ..Default::default()
+        };
+        let shader_stages = shader_programs_by_name
+            .get(&render_pass.program)
+            .ok_or_else(|| {
+                RendError::Pipeline(format!("program {} missing!", render_pass.program))
+            })?
+            .shaders
+            .iter()
+            .map(|e| e.info)
+            .collect::<Vec<_>>();
+
+        // Resolve targets are always written whole, through their first level
+        let attachment_resolves = render_pass_outputs
+            .iter()
+            .map(|e| match e.resolve.as_ref() {
+                Some(name) => {
+                    let att = attachments_by_name.get(name).ok_or_else(|| {
+                        RendError::Pipeline(format!(
+                            "resolve attachment {} missing for pass {}!",
+                            name, render_pass.name
+                        ))
+                    })?;
+                    Ok(Some(Attachment {
+                        level_usage: 0,
+                        layer_usage: e.layer,
+                        ..att.clone()
+                    }))
                 }
-                b
-            };
+                None => Ok(None),
+            })
+            .collect::<RendResult<Vec<_>>>()?;
+        let input_buffers = Self::find_buffers(&render_pass.input_buffers, buffers_by_name)?;
+        let output_buffers = Self::find_buffers(&render_pass.output_buffers, buffers_by_name)?;
 
-            let multisample_state = vk::PipelineMultisampleStateCreateInfo {
-                rasterization_samples: vk::SampleCountFlags::TYPE_1,
-                ..Default::default()
-            };
-            let shader_stages = shader_programs_by_name
-                .get(&render_pass.program)
-                .expect(&format!("program {} missing!", render_pass.program))
-                .shaders
-                .iter()
-                .map(|e| e.info)
-                .collect::<Vec<_>>();
+        let per_pass_updaters: Vec<_> = render_pass
+            .per_pass_updaters
+            .iter()
+            .map(|e| e.to_resource_kind())
+            .collect();
+        if !Self::is_pass_updater_order_valid(&per_pass_updaters) {
+            return Err(RendError::Pipeline(format!(
+                "per pass updaters in pass '{}' are not sorted correctly by resource size!",
+                render_pass.name
+            )));
+        }
 
-            let mut attachment_descriptors = (!render_pass.inputs.is_empty()).then(|| {
-                Box::new(Self::attachment_image_descriptors(
-                    ctx,
-                    descriptor_pool,
-                    &render_pass.name,
-                    render_pass.inputs.len() as u32,
-                ))
-            });
+        let mut attachment_descriptors = (!render_pass.inputs.is_empty()).then(|| {
+            Box::new(Self::attachment_image_descriptors(
+                ctx,
+                stage_descriptor_pool,
+                &render_pass.name,
+                render_pass.inputs.len() as u32,
+            ))
+        });
 
-            let clear_color_value = clearing.to_vk_color();
-            let clear_depth_stencil_value = clearing.to_vk_depth_stencil();
-            let make_attachment_descriptor = |e: (&Attachment, &Sampler)| -> Attachment {
-                let descriptor_index = attachment_descriptors
-                    .as_mut()
-                    .unwrap()
-                    .place_image_sampler(
-                        ctx,
-                        /*
-                         * Have to use only the specific mip usage view, otherwise VVL
-                         * complains about mip layout transitions if the view encompasses all of them
-                         * and we're still reading and writing through the mip chain
-                         */
-                        e.0.usage_view(),
-                        vk::ImageLayout::READ_ONLY_OPTIMAL,
-                        e.1.sampler,
-                    );
-                Attachment {
-                    descriptor_index,
-                    ..e.0.clone()
-                }
-            };
-            // Final passes have special rendering attachment info hanlding on render.
-            let default_attachment_index = render_pass_outputs
-                .iter()
-                .position(|e| Attachment::DEFAULT_NAME == e.name);
+        let clear_color_value = clearing.to_vk_color();
+        let clear_depth_stencil_value = clearing.to_vk_depth_stencil();
+        let make_attachment_descriptor = |e: (&Attachment, &Sampler)| -> Attachment {
+            let descriptor_index = attachment_descriptors
+                .as_mut()
+                .unwrap()
+                .place_image_sampler(
+                    ctx,
+                    /*
+                     * Have to use only the specific mip usage view, otherwise VVL
+                     * complains about mip layout transitions if the view encompasses all of them
+                     * and we're still reading and writing through the mip chain
+                     */
+                    e.0.usage_view(),
+                    vk::ImageLayout::READ_ONLY_OPTIMAL,
+                    e.1.sampler,
+                );
+            Attachment {
+                descriptor_index,
+                ..e.0.clone()
+            }
+        };
+        // Final passes have special rendering attachment info hanlding on render.
+        let default_attachment_index = render_pass_outputs
+            .iter()
+            .position(|e| Attachment::DEFAULT_NAME == e.name);
 
-            // Generate attachment structs with the proper descriptor index/offset
-            let inputs: Vec<_> = attachment_inputs
-                .iter()
-                .zip(attachment_samplers.iter())
-                .map(make_attachment_descriptor)
-                .collect();
-
-            let make_rendering_attachment_info = |e: &Attachment| vk::RenderingAttachmentInfo {
-                image_view: e.usage_view(),
-                image_layout: vk::ImageLayout::ATTACHMENT_OPTIMAL,
-                load_op: if e.format.has_depth_or_stencil() {
-                    clear_depth_stencil_value
-                        .map_or(vk::AttachmentLoadOp::LOAD, |_| vk::AttachmentLoadOp::CLEAR)
-                } else {
-                    clear_color_value
-                        .map_or(vk::AttachmentLoadOp::LOAD, |_| vk::AttachmentLoadOp::CLEAR)
-                },
-                clear_value: if e.format.has_depth_or_stencil() {
-                    clear_depth_stencil_value.unwrap_or_default()
-                } else {
-                    clear_color_value.unwrap_or_default()
-                },
-                /*
-                 * When using a COLOR attachment, we're storing unconditionally.
-                 * Same case when using a DEPTH_STENCIL attachment and depth-stencil writing is enabled.
-                 */
-                store_op: if writing.depth_or_stencil() || !e.format.has_depth_or_stencil() {
-                    vk::AttachmentStoreOp::STORE
-                } else {
-                    // Don't store if using a DEPTH_STENCIL attachment with writes disabled
-                    vk::AttachmentStoreOp::NONE
-                },
-                ..Default::default()
-            };
+        // Generate attachment structs with the proper descriptor index/offset
+        let inputs: Vec<_> = attachment_inputs
+            .iter()
+            .zip(attachment_samplers.iter())
+            .map(make_attachment_descriptor)
+            .collect();
 
-            let attachment_rendering: Vec<_> = attachment_outputs
-                .iter()
-                .map(make_rendering_attachment_info)
-                .collect();
-            let depth_stencil_rendering =
-                depth_stencil_attachment.map(make_rendering_attachment_info);
+        let make_rendering_attachment_info = |e: &Attachment| vk::RenderingAttachmentInfo {
+            image_view: e.render_view(),
+            image_layout: vk::ImageLayout::ATTACHMENT_OPTIMAL,
+            load_op: if e.format.has_depth_or_stencil() {
+                clear_depth_stencil_value
+                    .map_or(vk::AttachmentLoadOp::LOAD, |_| vk::AttachmentLoadOp::CLEAR)
+            } else {
+                clear_color_value
+                    .map_or(vk::AttachmentLoadOp::LOAD, |_| vk::AttachmentLoadOp::CLEAR)
+            },
+            clear_value: if e.format.has_depth_or_stencil() {
+                clear_depth_stencil_value.unwrap_or_default()
+            } else {
+                clear_color_value.unwrap_or_default()
+            },
             /*
-             * Add the depth-stencil attachment to the output list if present,
-             * this way proper barriers for writing/testing will be generated if
-             * the attachment is read from in a previous pass as an input.
+             * When using a COLOR attachment, we're storing unconditionally.
+             * Same case when using a DEPTH_STENCIL attachment and depth-stencil writing is enabled.
              */
-            let mut outputs_for_barriers = attachment_outputs.clone();
-            if writing.depth || writing.stencil {
-                if let Some(att) = depth_stencil_attachment {
-                    outputs_for_barriers.push(att.clone())
-                };
-            }
-            let image_barriers =
-                barrier_gen.gen_image_barriers_for(pass_index, &inputs, &outputs_for_barriers);
+            store_op: if writing.depth_or_stencil() || !e.format.has_depth_or_stencil() {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                // Don't store if using a DEPTH_STENCIL attachment with writes disabled
+                vk::AttachmentStoreOp::NONE
+            },
+            ..Default::default()
+        };
 
-            let mut set_layouts = vec![sampler_descriptors.layout, image_descriptors.layout];
+        let default_resolve_index = attachment_resolves
+            .iter()
+            .position(|e| e.as_ref().is_some_and(|e| e.is_default()));
+        let attachment_rendering: Vec<_> = attachment_outputs
+            .iter()
+            .zip(attachment_resolves.iter())
+            .map(|(e, resolve)| {
+                let info = make_rendering_attachment_info(e);
+                match resolve {
+                    Some(resolve) => vk::RenderingAttachmentInfo {
+                        // Integer formats can't be averaged
+                        resolve_mode: if e.format.is_integer() {
+                            vk::ResolveModeFlags::SAMPLE_ZERO
+                        } else {
+                            vk::ResolveModeFlags::AVERAGE
+                        },
+                        resolve_image_view: resolve.render_view(),
+                        resolve_image_layout: vk::ImageLayout::ATTACHMENT_OPTIMAL,
+                        ..info
+                    },
+                    None => info,
+                }
+            })
+            .collect();
+        let depth_stencil_rendering = depth_stencil_attachment.map(make_rendering_attachment_info);
+        // Validated to be the same for all of them, more than one renders into every layer
+        let layer_count = attachment_outputs
+            .iter()
+            .chain(depth_stencil_attachment)
+            .map(|e| e.usage_layers().1)
+            .max()
+            .unwrap_or(1);
+        /*
+         * Add the depth-stencil attachment to the output list if present,
+         * this way proper barriers for writing/testing will be generated if
+         * the attachment is read from in a previous pass as an input.
+         */
+        let mut outputs_for_barriers = attachment_outputs.clone();
+        // Resolving writes into them as if they were outputs too
+        outputs_for_barriers.extend(attachment_resolves.iter().flatten().cloned());
+        if writing.depth || writing.stencil {
+            if let Some(att) = depth_stencil_attachment {
+                outputs_for_barriers.push(att.clone())
+            };
+        }
+        let image_barriers =
+            barrier_gen.gen_image_barriers_for(pass_index, &inputs, &outputs_for_barriers);
+        let buffer_barriers =
+            barrier_gen.gen_buffer_barriers_for(pass_index, &input_buffers, &output_buffers);
+
+        let destroy_descriptors = || {
             if let Some(d) = &attachment_descriptors {
-                set_layouts.push(d.layout)
-            }
-            let pipeline_layout = unsafe {
-                let push_constant_ranges = [vk::PushConstantRange::default()
-                    .offset(0)
-                    .size(128)
-                    .stage_flags(ShaderStageFlags::ALL_GRAPHICS)];
-                let info = vk::PipelineLayoutCreateInfo::default()
-                    .set_layouts(&set_layouts)
-                    .push_constant_ranges(&push_constant_ranges);
-                ctx.device.create_pipeline_layout(&info, None)
+                d.destroy(&ctx.device)
             }
-            .unwrap();
-            ctx.try_set_debug_name(
-                &format!("{}_pipeline_layout", render_pass.name),
-                pipeline_layout,
-            );
+        };
+        let mut set_layouts = shared_set_layouts.to_vec();
+        if let Some(d) = &attachment_descriptors {
+            set_layouts.push(d.layout)
+        }
+        let pipeline_layout = unsafe {
+            let push_constant_ranges = [vk::PushConstantRange::default()
+                .offset(0)
+                .size(128)
+                .stage_flags(ShaderStageFlags::ALL_GRAPHICS)];
+            let info = vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constant_ranges);
+            ctx.device.create_pipeline_layout(&info, None)
+        }
+        .map_err(|e| {
+            destroy_descriptors();
+            RendError::vulkan(
+                e,
+                &format!("creating the pipeline layout of pass {}", render_pass.name),
+            )
+        })?;
+        ctx.try_set_debug_name(
+            &format!("{}_pipeline_layout", render_pass.name),
+            pipeline_layout,
+        );
 
-            // OpenGL NDC from -1 to 1 on depth, instead of 0 to 1
-            // let mut depth_clip_control = vk::PipelineViewportDepthClipControlCreateInfoEXT {
-            //     negative_one_to_one: 0,
-            //     ..Default::default()
-            // };
-            // Out of all outputs, find the minimum extent among them
-            let min_extent = attachment_outputs
-                .iter()
-                .map(|e| e.usage_extent())
-                .reduce(|acc, e| vk::Extent2D {
-                    width: e.width.min(acc.width),
-                    height: e.height.min(acc.height),
-                })
-                .unwrap_or(external_extent);
-            // After having the concrete destination view, we can set up viewport/scissor with proper dimensions
-            let viewports = [viewport.to_vk(&depth, min_extent)];
-            let scissors = [scissor.to_vk(min_extent)];
-            let viewport_scissor_state = vk::PipelineViewportStateCreateInfo::default()
-                .scissors(&scissors)
-                .viewports(&viewports)
-                // .push_next(&mut depth_clip_control)
-                ;
-
-            let graphic_pipeline_info = vk::GraphicsPipelineCreateInfo::default()
-                .stages(&shader_stages)
-                .vertex_input_state(&vertex_input_state_info)
-                .input_assembly_state(&vertex_input_assembly_state_info)
-                .viewport_state(&viewport_scissor_state)
-                .rasterization_state(&rasterization_state)
-                .multisample_state(&multisample_state)
-                .depth_stencil_state(&depth_stencil_state)
-                .color_blend_state(&blend_state)
-                .dynamic_state(&dynamic_state_info)
-                .layout(pipeline_layout)
-                .push_next(&mut rendering_pipeline_info);
-
-            let graphics_pipelines = unsafe {
-                ctx.device.create_graphics_pipelines(
-                    vk::PipelineCache::null(),
-                    &[graphic_pipeline_info],
-                    None,
-                )
-            }
-            .expect("Unable to create graphics pipeline");
-            let graphics_pipeline = graphics_pipelines[0];
+        // OpenGL NDC from -1 to 1 on depth, instead of 0 to 1
+        // let mut depth_clip_control = vk::PipelineViewportDepthClipControlCreateInfoEXT {
+        //     negative_one_to_one: 0,
+        //     ..Default::default()
+        // };
+        // Only the counts are needed, the values are dynamic
+        let viewport_scissor_state = vk::PipelineViewportStateCreateInfo::default()
+            .scissor_count(1)
+            .viewport_count(1)
+            // .push_next(&mut depth_clip_control)
+            ;
+
+        let graphic_pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input_state_info)
+            .input_assembly_state(&vertex_input_assembly_state_info)
+            .viewport_state(&viewport_scissor_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&blend_state)
+            .dynamic_state(&dynamic_state_info)
+            .layout(pipeline_layout)
+            .push_next(&mut rendering_pipeline_info);
+
+        let graphics_pipelines = unsafe {
+            ctx.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[graphic_pipeline_info],
+                None,
+            )
+        }
+        .map_err(|(_, e)| {
+            unsafe { ctx.device.destroy_pipeline_layout(pipeline_layout, None) };
+            destroy_descriptors();
+            RendError::vulkan(
+                e,
+                &format!(
+                    "creating the graphics pipeline of pass {}",
+                    render_pass.name
+                ),
+            )
+        })?;
+        let graphics_pipeline = graphics_pipelines[0];
 
-            ctx.try_set_debug_name(&format!("{}_pipeline", render_pass.name), graphics_pipeline);
+        ctx.try_set_debug_name(&format!("{}_pipeline", render_pass.name), graphics_pipeline);
 
-            let per_pass_updaters: Vec<_> = render_pass
-                .per_pass_updaters
+        Ok(crate::pipeline::render_stage::RenderStage {
+            name: render_pass.name.clone(),
+            is_validation_layer_enabled,
+            rendering: super::render_stage::Rendering {
+                attachments: attachment_rendering,
+                depth_stencil: depth_stencil_rendering,
+                resolves: attachment_resolves,
+                depth_stencil_attachment: depth_stencil_attachment.cloned(),
+                default_attachment_index,
+                default_resolve_index,
+                layer_count,
+            },
+            batch_parent_id: render_pass.batch_parent_id,
+            viewport,
+            scissor,
+            depth,
+            scissor_per_task: render_pass.scissor_per_task,
+            task_kind: render_pass.batch,
+            pipeline: graphics_pipeline,
+            layout: pipeline_layout,
+            per_instance_updaters: render_pass
+                .per_instance_updaters
                 .iter()
                 .map(|e| e.to_resource_kind())
-                .collect();
+                .collect(),
+            per_pass_updaters,
+            per_pass_constant: match &render_pass.per_pass_constant {
+                Some(m) => m.iter().map(|p| *p.1).collect(),
+                None => Vec::new(),
+            },
+            buffer_addresses: Self::buffer_addresses_of(&input_buffers, &output_buffers),
+            inputs,
+            samplers: attachment_samplers.iter().map(|e| e.sampler).collect(),
+            outputs: attachment_outputs,
+            index: pass_index as u32,
+            is_final: default_attachment_index.is_some() || default_resolve_index.is_some(),
+            image_barriers,
+            buffer_barriers,
+            attachment_descriptors,
+        })
+    }
 
-            if !Self::is_pass_updater_order_valid(&per_pass_updaters) {
-                panic!(
-                    "per pass updaters in pass '{}' are not sorted correctly by resource size!",
-                    render_pass.name
-                );
-            }
+    pub(super) fn make_attachment(
+        ctx: &VulkanContext,
+        f: &Target,
+        extent: Extent2D,
+    ) -> RendResult<Attachment> {
+        let texture = texture::make_of_usage(
+            ctx,
+            f.name.clone(),
+            extent.into(),
+            f.level,
+            f.layers as u32,
+            f.format,
+            f.kind(),
+            Self::attachment_usage_of(f),
+            f.sample_count(),
+        )?;
+        Self::attachment_of(ctx, f, extent, &texture).inspect_err(|_| texture.destroy(&ctx.device))
+    }
 
-            stages.push(Box::new(crate::pipeline::render_stage::RenderStage {
-                name: render_pass.name.clone(),
-                is_validation_layer_enabled,
-                rendering: super::render_stage::Rendering {
-                    attachments: attachment_rendering,
-                    depth_stencil: depth_stencil_rendering,
-                    default_attachment_index,
-                },
-                batch_parent_id: render_pass.batch_parent_id,
-                render_area: scissors[0],
-                task_kind: render_pass.batch,
-                pipeline: graphics_pipeline,
-                layout: pipeline_layout,
-                per_instance_updaters: render_pass
-                    .per_instance_updaters
-                    .iter()
-                    .map(|e| e.to_resource_kind())
-                    .collect(),
-                per_pass_updaters,
-                per_pass_constant: match &render_pass.per_pass_constant {
-                    Some(m) => m.iter().map(|p| *p.1).collect(),
-                    None => Vec::new(),
-                },
-                inputs,
-                outputs: attachment_outputs,
-                index: pass_index as u32,
-                is_final: default_attachment_index.is_some(),
-                image_barriers,
-                attachment_descriptors,
-                reserved_buffers: Vec::new(),
-            }));
-        }
-        for shader in shader_programs_by_name
-            .into_values()
-            .flat_map(|e| e.shaders)
-        {
-            // No longer need them.
-            unsafe { ctx.device.destroy_shader_module(shader.info.module, None) };
+    pub(super) fn attachment_usage_of(f: &Target) -> vk::ImageUsageFlags {
+        let mut usage = texture::usage_flags_for(f.format, true);
+        if f.is_storage {
+            usage |= vk::ImageUsageFlags::STORAGE;
         }
+        usage
+    }
 
-        //  Place all sampler descriptors into the descriptor buffer and write to the GPU
-        let mut positioned_samplers = samplers_by_key.values().collect::<Vec<_>>();
-        positioned_samplers.sort_by(|a, b| a.position.cmp(&b.position));
-        for sampler in positioned_samplers {
-            sampler_descriptors.place_sampler_at(ctx, sampler.position as u32, sampler.sampler);
+    /// Attachment of the target using the texture's image and memory, with every view it needs.
+    /// On failure the texture is left for the caller to release.
+    pub(super) fn attachment_of(
+        ctx: &VulkanContext,
+        f: &Target,
+        extent: Extent2D,
+        texture: &texture::Texture,
+    ) -> RendResult<Attachment> {
+        let layers = f.image_layers();
+        let per_level_views = Attachment::per_level_views_of(
+            ctx,
+            texture.image,
+            f.format,
+            f.level,
+            layers,
+            f.kind(),
+        )?;
+        let (per_layer_views, per_level_layer_views) =
+            Attachment::per_layer_views_of(ctx, texture.image, f.format, f.level, layers)
+                .inspect_err(|_| Attachment::destroy_views(&ctx.device, &per_level_views))?;
+        // Cube views are only for sampling, these get rendered into instead
+        let per_level_array_views = match f.cube {
+            true => Attachment::per_level_views_of(
+                ctx,
+                texture.image,
+                f.format,
+                f.level,
+                layers,
+                TextureKind::T2D,
+            )
+            .inspect_err(|_| {
+                Attachment::destroy_views(&ctx.device, &per_level_views);
+                Attachment::destroy_views(&ctx.device, &per_layer_views);
+                Attachment::destroy_views(&ctx.device, &per_level_layer_views);
+            })?,
+            false => Vec::new(),
+        };
+        ctx.try_set_debug_name(&format!("{}_att_image", f.name), texture.image);
+        ctx.try_set_debug_name(&format!("{}_att_image_memory", f.name), texture.memory);
+        ctx.try_set_debug_name(&format!("{}_att_image_view", f.name), texture.view);
+        for (i, view) in per_level_views.iter().enumerate() {
+            ctx.try_set_debug_name(&format!("{}_att_image_view{}", f.name, i), *view);
         }
-        // TODO: Deferred descriptor writes
-        // sampler_descriptors.into_device();
-        // image_descriptors.into_device();
-        crate::pipeline::Pipeline {
-            stages,
-            attachments: attachments_by_name.into_values().collect(),
-            descriptor_pool,
-            image_descriptors,
-            sampler_descriptors,
-            samplers_by_key,
+        for (i, view) in per_layer_views.iter().enumerate() {
+            ctx.try_set_debug_name(&format!("{}_att_image_layer_view{}", f.name, i), *view);
+        }
+        for (i, view) in per_level_array_views.iter().enumerate() {
+            ctx.try_set_debug_name(&format!("{}_att_image_array_view{}", f.name, i), *view);
+        }
+        for (i, view) in per_level_layer_views.iter().enumerate() {
+            let (level, layer) = (i / layers as usize, i % layers as usize);
+            ctx.try_set_debug_name(
+                &format!("{}_att_image_view{}_layer{}", f.name, level, layer),
+                *view,
+            );
+        }
+        Ok(Attachment {
+            name: f.name.clone(),
+            format: f.format,
+            vk_format: f.format.to_vk(),
+            image: texture.image,
+            memory: texture.memory,
+            view: texture.view,
+            extent,
+            per_level_views,
+            per_layer_views,
+            per_level_layer_views,
+            per_level_array_views,
+            level_usage: 0,
+            layer_usage: None,
+            layers,
+            samples: f.sample_count(),
+            descriptor_index: 0,
+            is_aliased: false,
+        })
+    }
+
+    /// None are left if any of them fails
+    pub(super) fn make_buffers(
+        ctx: &VulkanContext,
+        buffers: &[Buffer],
+    ) -> RendResult<Vec<PipelineBuffer>> {
+        let mut made = Vec::with_capacity(buffers.len());
+        for f in buffers {
+            match PipelineBuffer::new(ctx, &f.name, f.size) {
+                Ok(buffer) => made.push(buffer),
+                Err(err) => {
+                    for buffer in made {
+                        buffer.destroy(&ctx.device);
+                    }
+                    return Err(err);
+                }
+            }
         }
+        Ok(made)
+    }
+
+    pub(super) fn headless_default_attachment(
+        ctx: &VulkanContext,
+        extent: Extent2D,
+    ) -> RendResult<Attachment> {
+        // Same kind of format a swapchain would prefer
+        let format = crate::format::Format::R8G8B8A8_SRGB;
+        // Frames are read back from it by copying to a buffer
+        assert!(
+            texture::usage_flags_for(format, true).contains(vk::ImageUsageFlags::TRANSFER_SRC),
+            "default attachment must be usable as a transfer source"
+        );
+        let texture = texture::make(
+            ctx,
+            Attachment::DEFAULT_NAME.to_string(),
+            extent.into(),
+            1,
+            1,
+            format,
+            TextureKind::T2D,
+            true,
+        )?;
+        Ok(Attachment {
+            memory: texture.memory,
+            format,
+            ..Attachment::default_attachment_of(format.to_vk(), texture.image, texture.view, extent)
+        })
     }
 
-    fn is_pass_updater_order_valid(per_pass_updaters: &[ResourceKind]) -> bool {
+    pub(super) fn is_pass_updater_order_valid(per_pass_updaters: &[ResourceKind]) -> bool {
         let mut sorted_per_pass_updaters = per_pass_updaters.to_vec();
         // Sort in reverse, from bigger to smaller resource size due alignment concerns
         sorted_per_pass_updaters.sort_by(|a, b| b.resource_size().cmp(&a.resource_size()));
@@ -601,41 +1113,68 @@ impl Pipeline {
     fn find_attachments(
         attachments: &[impl AttachmentFile],
         attachments_by_name: &HashMap<String, Attachment>,
-    ) -> Vec<Attachment> {
+    ) -> RendResult<Vec<Attachment>> {
         attachments
             .iter()
             .map(|e| {
-                let att = attachments_by_name
-                    .get(e.name())
-                    .expect(&format!("attachment {} missing!", e.name()))
-                    .clone();
-                Attachment {
+                let att = attachments_by_name.get(e.name()).ok_or_else(|| {
+                    RendError::Pipeline(format!("attachment {} missing!", e.name()))
+                })?;
+                Ok(Attachment {
                     level_usage: e.level().get(),
-                    ..att
-                }
+                    layer_usage: e.layer(),
+                    ..att.clone()
+                })
+            })
+            .collect()
+    }
+
+    fn find_buffers(
+        names: &[String],
+        buffers_by_name: &HashMap<&str, &PipelineBuffer>,
+    ) -> RendResult<Vec<PipelineBuffer>> {
+        names
+            .iter()
+            .map(|e| match buffers_by_name.get(e.as_str()) {
+                Some(buffer) => Ok((*buffer).clone()),
+                None => Err(RendError::Pipeline(format!("buffer {} missing!", e))),
             })
             .collect()
     }
 
+    /// Inputs first, then outputs, same order they're declared in the pass
+    fn buffer_addresses_of(inputs: &[PipelineBuffer], outputs: &[PipelineBuffer]) -> Vec<u64> {
+        inputs
+            .iter()
+            .chain(outputs)
+            .map(|e| e.device_addr)
+            .collect()
+    }
+
     fn build_blit_stage<'a>(
         blit: &BlitPass,
         barrier_gen: &BarrierGen,
         index: usize,
         is_validation_layer_enabled: bool,
         attachments_by_name: &HashMap<String, Attachment>,
-    ) -> crate::pipeline::blit_stage::BlitStage<'a> {
-        let mut outputs = Self::find_attachments(&[blit.output.get()], attachments_by_name);
-        let mut inputs = Self::find_attachments(&[blit.input.get()], attachments_by_name);
+    ) -> RendResult<crate::pipeline::blit_stage::BlitStage<'a>> {
+        let mut outputs = Self::find_attachments(&[blit.output.get()], attachments_by_name)?;
+        let mut inputs = Self::find_attachments(&[blit.input.get()], attachments_by_name)?;
         let image_barriers = barrier_gen.gen_image_barriers_for(index, &inputs, &outputs);
         let input = inputs.remove(0);
         let output = outputs.remove(0);
-        crate::pipeline::blit_stage::BlitStage {
+        Ok(crate::pipeline::blit_stage::BlitStage {
             name: blit.name.clone(),
             index: index.try_into().unwrap(),
             is_validation_layer_enabled,
             image_barriers,
             filter: blit.filter.to_vk(),
-            region: blit.to_vk(input.extent, output.extent),
+            input_rect: blit.input_rect,
+            output_rect: blit.output_rect,
+            region: blit.to_vk(
+                (input.extent, input.usage_layers()),
+                (output.extent, output.usage_layers()),
+            ),
             is_final: output.is_default(),
             input,
             output: if output.is_default()  {
@@ -643,7 +1182,269 @@ impl Pipeline {
             } else {
                 Some(output)
             },
+        })
+    }
+
+    fn build_mips_stage<'a>(
+        mips: &GenerateMipsPass,
+        barrier_gen: &BarrierGen,
+        index: usize,
+        is_validation_layer_enabled: bool,
+        attachments_by_name: &HashMap<String, Attachment>,
+    ) -> RendResult<crate::pipeline::mips_stage::MipsStage<'a>> {
+        let attachment = attachments_by_name
+            .get(&mips.target)
+            .ok_or_else(|| RendError::Pipeline(format!("attachment {} missing!", mips.target)))?
+            .clone();
+        // Reads the first level, writes all of the others
+        let level_of = |level_usage| Attachment {
+            level_usage,
+            ..attachment.clone()
+        };
+        let inputs = [level_of(0)];
+        let outputs: Vec<_> = (1..attachment.levels()).map(level_of).collect();
+        let image_barriers = barrier_gen.gen_image_barriers_for(index, &inputs, &outputs);
+        // Depth and integer formats can't be filtered linearly
+        let filter = if attachment.format.has_depth_or_stencil() || attachment.format.is_integer() {
+            vk::Filter::NEAREST
+        } else {
+            vk::Filter::LINEAR
+        };
+        Ok(crate::pipeline::mips_stage::MipsStage {
+            name: mips.name.clone(),
+            index: index as u32,
+            is_validation_layer_enabled,
+            image_barriers,
+            filter,
+            attachment,
+        })
+    }
+
+    fn sampler_of(
+        ctx: &VulkanContext,
+        samplers_by_key: &mut HashMap<SamplerKey, Sampler>,
+        input: &AttachmentInput,
+    ) -> RendResult<Sampler> {
+        let sampler = Self::handle_option(input.sampler.clone())?;
+        let key = SamplerKey {
+            filter: sampler.filter,
+            wrap_mode: sampler.wrap_mode,
+            compare_func: sampler.compare_func,
+            anisotropy: sampler.anisotropy,
+        };
+        match samplers_by_key.get(&key) {
+            Some(s) => Ok(s.clone()),
+            None => {
+                let smp = Sampler::of_key(ctx, key, samplers_by_key.len() as u8);
+                samplers_by_key.insert(key, smp.clone());
+                Ok(smp)
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_compute_stage<'a>(
+        ctx: &VulkanContext,
+        compute: &ComputePass,
+        barrier_gen: &BarrierGen,
+        index: usize,
+        is_validation_layer_enabled: bool,
+        attachments_by_name: &HashMap<String, Attachment>,
+        buffers_by_name: &HashMap<&str, &PipelineBuffer>,
+        shader_programs_by_name: &HashMap<String, shader::ShaderProgram>,
+        stage_descriptor_pool: vk::DescriptorPool,
+        shared_set_layouts: &[vk::DescriptorSetLayout],
+        samplers_by_key: &mut HashMap<SamplerKey, Sampler>,
+    ) -> RendResult<crate::pipeline::compute_stage::ComputeStage<'a>> {
+        let compute_inputs: Vec<_> = compute.inputs.iter().map(|e| e.get()).collect();
+        let compute_outputs: Vec<_> = compute.outputs.iter().map(|e| e.get()).collect();
+        let attachment_inputs = Self::find_attachments(&compute_inputs, attachments_by_name)?;
+        let outputs = Self::find_attachments(&compute_outputs, attachments_by_name)?;
+        let samplers = compute_inputs
+            .iter()
+            .map(|i| Self::sampler_of(ctx, samplers_by_key, i))
+            .collect::<RendResult<Vec<_>>>()?;
+        let input_buffers = Self::find_buffers(&compute.input_buffers, buffers_by_name)?;
+        let output_buffers = Self::find_buffers(&compute.output_buffers, buffers_by_name)?;
+        let dispatch_target =
+            Self::find_attachments(&[compute.dispatch.target.get()], attachments_by_name)?
+                .remove(0);
+        let shader_stage = shader_programs_by_name
+            .get(&compute.program)
+            .ok_or_else(|| RendError::Pipeline(format!("program {} missing!", compute.program)))?
+            .shaders
+            .iter()
+            .find(|e| e.type_id() == ShaderStageFlags::COMPUTE)
+            .ok_or_else(|| {
+                RendError::Pipeline(format!(
+                    "program {} has no compute shader!",
+                    compute.program
+                ))
+            })?
+            .info;
+        let per_pass_updaters: Vec<_> = compute
+            .per_pass_updaters
+            .iter()
+            .map(|e| e.to_resource_kind())
+            .collect();
+        if !Self::is_pass_updater_order_valid(&per_pass_updaters) {
+            return Err(RendError::Pipeline(format!(
+                "per pass updaters in pass '{}' are not sorted correctly by resource size!",
+                compute.name
+            )));
+        }
+
+        let mut attachment_descriptors = (!compute_inputs.is_empty()).then(|| {
+            Box::new(Self::attachment_image_descriptors(
+                ctx,
+                stage_descriptor_pool,
+                &compute.name,
+                compute_inputs.len() as u32,
+            ))
+        });
+        // Sampled the same way render passes do
+        let inputs: Vec<_> = attachment_inputs
+            .iter()
+            .zip(samplers.iter())
+            .map(|(att, sampler)| {
+                let descriptor_index = attachment_descriptors
+                    .as_mut()
+                    .unwrap()
+                    .place_image_sampler(
+                        ctx,
+                        att.usage_view(),
+                        vk::ImageLayout::READ_ONLY_OPTIMAL,
+                        sampler.sampler,
+                    );
+                Attachment {
+                    descriptor_index,
+                    ..att.clone()
+                }
+            })
+            .collect();
+        // Passes writing only buffers still need the set, left unwritten
+        let mut storage_descriptors = Box::new(Self::storage_image_descriptors(
+            ctx,
+            stage_descriptor_pool,
+            &compute.name,
+            outputs.len().max(1) as u32,
+        ));
+        let outputs: Vec<_> = outputs
+            .into_iter()
+            .map(|att| {
+                // Storage image views can't encompass several mip levels and be written per level
+                let descriptor_index = storage_descriptors.place_image(
+                    ctx,
+                    att.render_view(),
+                    vk::ImageLayout::GENERAL,
+                );
+                Attachment {
+                    descriptor_index,
+                    ..att
+                }
+            })
+            .collect();
+        let image_barriers = barrier_gen.gen_image_barriers_for(index, &inputs, &outputs);
+        let buffer_barriers =
+            barrier_gen.gen_buffer_barriers_for(index, &input_buffers, &output_buffers);
+
+        let destroy_descriptors = || {
+            if let Some(d) = &attachment_descriptors {
+                d.destroy(&ctx.device)
+            }
+            storage_descriptors.destroy(&ctx.device);
+        };
+        let empty_set_layout = match attachment_descriptors {
+            Some(_) => None,
+            None => {
+                let info = vk::DescriptorSetLayoutCreateInfo::default();
+                let layout = unsafe { ctx.device.create_descriptor_set_layout(&info, None) }
+                    .map_err(|e| {
+                        destroy_descriptors();
+                        RendError::vulkan(e, "creating empty descriptor set layout")
+                    })?;
+                Some(layout)
+            }
+        };
+        let destroy_empty_set_layout = || {
+            if let Some(layout) = empty_set_layout {
+                unsafe { ctx.device.destroy_descriptor_set_layout(layout, None) };
+            }
+        };
+        let mut set_layouts = shared_set_layouts.to_vec();
+        set_layouts.push(match &attachment_descriptors {
+            Some(d) => d.layout,
+            None => empty_set_layout.unwrap(),
+        });
+        set_layouts.push(storage_descriptors.layout);
+        let pipeline_layout = unsafe {
+            let push_constant_ranges = [vk::PushConstantRange::default()
+                .offset(0)
+                .size(128)
+                .stage_flags(ShaderStageFlags::COMPUTE)];
+            let info = vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constant_ranges);
+            ctx.device.create_pipeline_layout(&info, None)
+        }
+        .map_err(|e| {
+            destroy_empty_set_layout();
+            destroy_descriptors();
+            RendError::vulkan(
+                e,
+                &format!("creating the pipeline layout of pass {}", compute.name),
+            )
+        })?;
+        ctx.try_set_debug_name(
+            &format!("{}_pipeline_layout", compute.name),
+            pipeline_layout,
+        );
+
+        let compute_pipeline_info = vk::ComputePipelineCreateInfo::default()
+            .stage(shader_stage)
+            .layout(pipeline_layout);
+        let compute_pipeline = unsafe {
+            ctx.device.create_compute_pipelines(
+                vk::PipelineCache::null(),
+                &[compute_pipeline_info],
+                None,
+            )
         }
+        .map_err(|(_, e)| {
+            unsafe { ctx.device.destroy_pipeline_layout(pipeline_layout, None) };
+            destroy_empty_set_layout();
+            destroy_descriptors();
+            RendError::vulkan(
+                e,
+                &format!("creating the compute pipeline of pass {}", compute.name),
+            )
+        })?[0];
+        ctx.try_set_debug_name(&format!("{}_pipeline", compute.name), compute_pipeline);
+
+        Ok(crate::pipeline::compute_stage::ComputeStage {
+            name: compute.name.clone(),
+            pipeline: compute_pipeline,
+            layout: pipeline_layout,
+            inputs,
+            outputs,
+            samplers: samplers.iter().map(|e| e.sampler).collect(),
+            per_pass_updaters,
+            per_pass_constant: match &compute.per_pass_constant {
+                Some(m) => m.iter().map(|p| *p.1).collect(),
+                None => Vec::new(),
+            },
+            buffer_addresses: Self::buffer_addresses_of(&input_buffers, &output_buffers),
+            attachment_descriptors,
+            storage_descriptors,
+            empty_set_layout,
+            group_count: compute.dispatch.group_count(dispatch_target.usage_extent()),
+            dispatch: compute.dispatch.clone(),
+            dispatch_target,
+            index: index as u32,
+            image_barriers,
+            buffer_barriers,
+            is_validation_layer_enabled,
+        })
     }
 
     pub fn image_descriptors(ctx: &VulkanContext, pool: vk::DescriptorPool) -> DescriptorGroup {
@@ -675,6 +1476,23 @@ impl Pipeline {
         )
     }
 
+    pub fn storage_image_descriptors(
+        ctx: &VulkanContext,
+        pool: vk::DescriptorPool,
+        prefix: &str,
+        size: u32,
+    ) -> DescriptorGroup {
+        DescriptorGroup::of(
+            ctx,
+            pool,
+            format!("{}_storage", prefix),
+            DescriptorType::STORAGE_IMAGE,
+            size,
+            false,
+            false,
+        )
+    }
+
     pub fn sampler_descriptors(ctx: &VulkanContext, pool: vk::DescriptorPool) -> DescriptorGroup {
         DescriptorGroup::of(
             ctx,
@@ -709,3 +1527,68 @@ impl Pipeline {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{fnv1a, Pipeline, FNV_OFFSET_BASIS};
+
+    /// Writes the shader sources into a temporary directory unique to the name, returning
+    /// its path
+    fn shader_dir(name: &str, sources: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rend-vk-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).expect("failed creating the shader directory!");
+        for (file, source) in sources {
+            std::fs::write(dir.join(file), source).expect("failed writing the shader!");
+        }
+        dir
+    }
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b""), FNV_OFFSET_BASIS);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"foobar"), 0x85944171f73967e8);
+        // Hashing in pieces is the same as hashing it whole
+        assert_eq!(
+            fnv1a(fnv1a(FNV_OFFSET_BASIS, b"foo"), b"bar"),
+            fnv1a(FNV_OFFSET_BASIS, b"foobar")
+        );
+    }
+
+    #[test]
+    fn changing_an_include_changes_the_hash() {
+        let dir = shader_dir(
+            "hash_include",
+            &[
+                ("main.frag", "#include \"common.glsl\"\nvoid main() {}\n"),
+                ("common.glsl", "  #include \"nested.glsl\"\n"),
+                ("nested.glsl", "const float SCALE = 1.0;\n"),
+            ],
+        );
+        let main = dir.join("main.frag");
+        let main = main.to_str().unwrap();
+        let hash = Pipeline::shader_hash_of(main, &["-O"]);
+        assert_eq!(hash, Pipeline::shader_hash_of(main, &["-O"]));
+        assert_ne!(hash, Pipeline::shader_hash_of(main, &["-O0"]));
+        std::fs::write(dir.join("nested.glsl"), "const float SCALE = 2.0;\n").unwrap();
+        assert_ne!(hash, Pipeline::shader_hash_of(main, &["-O"]));
+    }
+
+    #[test]
+    fn include_cycles_terminate() {
+        let dir = shader_dir(
+            "hash_cycle",
+            &[
+                ("main.frag", "#include \"a.glsl\"\nvoid main() {}\n"),
+                ("a.glsl", "#include \"b.glsl\"\n"),
+                ("b.glsl", "#include \"a.glsl\"\n#include \"main.frag\"\n"),
+            ],
+        );
+        let main = dir.join("main.frag");
+        let main = main.to_str().unwrap();
+        let hash = Pipeline::shader_hash_of(main, &[]);
+        assert_eq!(hash, Pipeline::shader_hash_of(main, &[]));
+        std::fs::write(dir.join("b.glsl"), "#include \"a.glsl\"\n// edited\n").unwrap();
+        assert_ne!(hash, Pipeline::shader_hash_of(main, &[]));
+    }
+}
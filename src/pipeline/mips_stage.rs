@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use crate::context::VulkanContext;
+
+use super::{
+    attachment::Attachment,
+    stage::{replace_barrier_images, Stage},
+};
+
+/// Fills the mip chain of an attachment by blitting each level into the next one.
+pub struct MipsStage<'a> {
+    pub name: String,
+    pub attachment: Attachment,
+    pub filter: vk::Filter,
+    pub index: u32,
+    pub image_barriers: Vec<vk::ImageMemoryBarrier2<'a>>,
+    pub is_validation_layer_enabled: bool,
+}
+
+impl<'a> Stage for MipsStage<'a> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    fn is_validation_layer_enabled(&self) -> bool {
+        self.is_validation_layer_enabled
+    }
+
+    fn image_barriers(&'_ self) -> Vec<vk::ImageMemoryBarrier2<'_>> {
+        self.image_barriers.clone()
+    }
+
+    fn destroy(&self, _device: &ash::Device) {
+        // Nothing to do
+    }
+
+    fn resize(
+        &mut self,
+        _ctx: &VulkanContext,
+        attachments_by_name: &HashMap<String, Attachment>,
+        images: &HashMap<vk::Image, vk::Image>,
+    ) {
+        self.attachment = self.attachment.resized(attachments_by_name);
+        replace_barrier_images(&mut self.image_barriers, images);
+    }
+
+    fn work(&mut self, ctx: super::RenderContext) {
+        // First level as transfer src, the rest as transfer dst
+        if !self.image_barriers.is_empty() {
+            let barrier_dep_info =
+                vk::DependencyInfo::default().image_memory_barriers(&self.image_barriers);
+            unsafe {
+                ctx.vulkan
+                    .device
+                    .cmd_pipeline_barrier2(ctx.command_buffer, &barrier_dep_info);
+            }
+        }
+        let aspect = self.attachment.format.aspect();
+        let extent = self.attachment.extent;
+        let offset_of = |level: u8| vk::Offset3D {
+            x: crate::texture::mip_dimensions_of(level as usize, extent.width) as i32,
+            y: crate::texture::mip_dimensions_of(level as usize, extent.height) as i32,
+            z: 1,
+        };
+        // Every layer gets its mip chain generated at the same time
+        let layers = self.attachment.layers as u32;
+        let layers_of = |level: u8| vk::ImageSubresourceLayers {
+            mip_level: level as u32,
+            base_array_layer: 0,
+            layer_count: layers,
+            aspect_mask: aspect,
+        };
+        for level in 1..self.attachment.levels() {
+            let regions = [vk::ImageBlit {
+                src_subresource: layers_of(level - 1),
+                src_offsets: [vk::Offset3D::default(), offset_of(level - 1)],
+                dst_subresource: layers_of(level),
+                dst_offsets: [vk::Offset3D::default(), offset_of(level)],
+            }];
+            /*
+             * Next blit reads from this level, the last level is transitioned too
+             * so the whole chain ends up in the same layout.
+             */
+            let barriers = [vk::ImageMemoryBarrier2::default()
+                .image(self.attachment.image)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .subresource_range(Attachment::subresource_range_wlayers(
+                    aspect,
+                    level as u32,
+                    1,
+                    0,
+                    layers,
+                ))];
+            let barrier_dep_info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+            unsafe {
+                ctx.vulkan.device.cmd_blit_image(
+                    ctx.command_buffer,
+                    self.attachment.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.attachment.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                    self.filter,
+                );
+                ctx.vulkan
+                    .device
+                    .cmd_pipeline_barrier2(ctx.command_buffer, &barrier_dep_info);
+            }
+        }
+    }
+}
@@ -5,37 +5,121 @@ use ash::vk;
 use self::descriptor::DescriptorGroup;
 use self::sampler::SamplerKey;
 
-use crate::buffer::DeviceAllocator;
+use crate::buffer::LinearAllocator;
+use crate::error::{RendError, RendResult};
 
 use crate::pipeline::attachment::Attachment;
+use crate::pipeline::buffer::PipelineBuffer;
 use crate::pipeline::sampler::Sampler;
 use crate::render_task::RenderTask;
 use crate::renderer::MeshBuffer;
 use crate::shader_resource::{ResourceKind, SingleResource};
 
+mod alias;
 pub mod attachment;
 mod barrier_gen;
 pub mod blit_stage;
+pub mod buffer;
+pub mod compute_stage;
+mod cull;
 pub mod descriptor;
 pub mod file;
+mod graph;
 mod load;
+pub mod mips_stage;
 pub mod render_stage;
+mod repeat;
 pub mod sampler;
 pub mod stage;
 mod state;
+pub mod validate;
 
 // Fixed descriptor set indices
 pub const DESCRIPTOR_SET_SAMPLER: u32 = 0;
 pub const DESCRIPTOR_SET_TEXTURE: u32 = 1;
 pub const DESCRIPTOR_SET_TARGET_IMAGE: u32 = 2;
+pub const DESCRIPTOR_SET_STORAGE_IMAGE: u32 = 3;
 
 pub struct Pipeline {
     pub stages: Vec<Box<dyn stage::Stage>>,
+    /// Stages using history targets, with each target swapped with its partner. Used instead
+    /// of the regular ones every other frame.
+    pub swapped_stages: Vec<Box<dyn stage::Stage>>,
+    pub is_history_swapped: bool,
     pub attachments: Vec<Attachment>,
+    /// Memory shared by the attachments of transient targets
+    pub aliased_memory: Vec<vk::DeviceMemory>,
+    pub buffers: Vec<PipelineBuffer>,
     pub descriptor_pool: vk::DescriptorPool,
+    /// Pool for the per pass descriptor sets, made again on reloads
+    pub stage_descriptor_pool: vk::DescriptorPool,
     pub image_descriptors: DescriptorGroup,
     pub sampler_descriptors: DescriptorGroup,
     pub samplers_by_key: HashMap<SamplerKey, Sampler>,
+    /// Description the stages were built from, kept around to rebuild them
+    pub file: file::Pipeline,
+    /// Path of the file the description was read from
+    pub path: String,
+    pub spirv_by_name: HashMap<String, Vec<u32>>,
+    pub internal_extent: vk::Extent2D,
+    pub external_extent: vk::Extent2D,
+    pub is_validation_layer_enabled: bool,
+}
+
+/// Everything a reload makes again, either the new resources being built or the current
+/// ones being replaced. Doesn't include the default attachment.
+struct Resources {
+    stages: Vec<Box<dyn stage::Stage>>,
+    swapped_stages: Vec<Box<dyn stage::Stage>>,
+    attachments: Vec<Attachment>,
+    aliased_memory: Vec<vk::DeviceMemory>,
+    buffers: Vec<PipelineBuffer>,
+    stage_descriptor_pool: vk::DescriptorPool,
+    file: file::Pipeline,
+    spirv_by_name: HashMap<String, Vec<u32>>,
+}
+
+impl Resources {
+    fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            for stage in self.stages.iter().chain(&self.swapped_stages) {
+                stage.destroy(device);
+            }
+            for attachment in &self.attachments {
+                attachment.destroy(device);
+            }
+            for memory in &self.aliased_memory {
+                device.free_memory(*memory, None);
+            }
+            for buffer in &self.buffers {
+                buffer.destroy(device);
+            }
+            // Frees the stages' descriptor sets too
+            device.destroy_descriptor_pool(self.stage_descriptor_pool, None);
+        }
+    }
+}
+
+/// Everything a resize makes again, either the resized attachments being made or the current
+/// ones being replaced. Only the attachments whose size changes are included.
+struct ResizedResources {
+    /// Along with the index of the pipeline attachment they replace
+    attachments: Vec<(usize, Attachment)>,
+    /// Only if the aliased attachments were made again
+    aliased_memory: Option<Vec<vk::DeviceMemory>>,
+    internal_extent: vk::Extent2D,
+    external_extent: vk::Extent2D,
+}
+
+impl ResizedResources {
+    fn destroy(&self, device: &ash::Device) {
+        for (_, attachment) in &self.attachments {
+            attachment.destroy(device);
+        }
+        for memory in self.aliased_memory.iter().flatten() {
+            unsafe { device.free_memory(*memory, None) };
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -46,14 +130,27 @@ pub struct RenderContext<'a> {
     pub shader_resources_by_kind: &'a HashMap<ResourceKind, SingleResource>,
     pub sampler_descriptors: &'a DescriptorGroup,
     pub image_descriptors: &'a DescriptorGroup,
-    pub buffer_allocator: &'a DeviceAllocator,
+    /// Transient allocator for the frame being recorded, reset once the frame finishes
+    pub frame_allocator: &'a LinearAllocator,
     pub command_buffer: vk::CommandBuffer,
+    /// Index of the frame in flight being recorded
+    pub frame_slot: usize,
     pub default_attachment: &'a Attachment,
+    /// Layout the default attachment is left in after the final pass
+    pub default_attachment_final_layout: vk::ImageLayout,
 }
 
 impl Pipeline {
     pub fn process_stages(&mut self, render_context: RenderContext) {
         for stage in self.stages.iter_mut() {
+            let swapped = match self.is_history_swapped {
+                true => self
+                    .swapped_stages
+                    .iter_mut()
+                    .find(|e| e.index() == stage.index()),
+                false => None,
+            };
+            let stage = swapped.unwrap_or(stage);
             render_context
                 .vulkan
                 .try_begin_debug_label(render_context.command_buffer, stage.name());
@@ -62,6 +159,280 @@ impl Pipeline {
                 .vulkan
                 .try_end_debug_label(render_context.command_buffer);
         }
+        // What was written this frame gets read as the previous frame's contents in the next
+        if !self.swapped_stages.is_empty() {
+            self.is_history_swapped = !self.is_history_swapped;
+        }
+    }
+
+    /// Resources a task batched under the key has to carry for the stages that draw it.
+    /// Swapped stages mirror the regular ones so only those are looked at.
+    pub fn per_instance_updaters_of(&self, key: u64) -> impl Iterator<Item = ResourceKind> + '_ {
+        self.stages
+            .iter()
+            .flat_map(move |e| e.per_instance_updaters(key).iter().copied())
+    }
+
+    /// First per pass resource that processing the batches would need but isn't placed
+    pub fn missing_pass_resource(
+        &self,
+        batches_by_task_type: &HashMap<u64, Vec<RenderTask>>,
+        shader_resources_by_kind: &HashMap<ResourceKind, SingleResource>,
+    ) -> Option<ResourceKind> {
+        self.stages
+            .iter()
+            .find_map(|e| e.missing_pass_resource(batches_by_task_type, shader_resources_by_kind))
+    }
+
+    /// Builds both the regular and swapped stages, starting over from the regular ones
+    /// since the initial barriers are generated for them. Current stages are only replaced
+    /// if both of them get built.
+    fn build_stages(&mut self, ctx: &crate::context::VulkanContext) -> RendResult<()> {
+        let stages = file::Pipeline::make_stages(ctx, self, false)?;
+        let swapped_stages = match file::Pipeline::make_stages(ctx, self, true) {
+            Ok(swapped_stages) => swapped_stages,
+            Err(err) => {
+                for stage in stages {
+                    stage.destroy(&ctx.device);
+                }
+                return Err(err);
+            }
+        };
+        self.stages = stages;
+        self.swapped_stages = swapped_stages;
+        self.is_history_swapped = false;
+        Ok(())
+    }
+
+    pub fn default_attachment(&self) -> &Attachment {
+        self.attachments
+            .iter()
+            .find(|e| e.is_default())
+            .expect("default attachment missing!")
+    }
+
+    /// Re-allocates every attachment whose size changed with the new extents, then points the
+    /// stages to them without recreating their pipelines. Without a default attachment the
+    /// current one is kept, unless the pipeline owns it (ie, renderer is headless) and its size
+    /// changed. If making any of the attachments fails the pipeline is kept as it was.
+    /// Caller must ensure the device isn't using any of the pipeline's resources.
+    pub fn resize(
+        &mut self,
+        ctx: &crate::context::VulkanContext,
+        internal_extent: vk::Extent2D,
+        external_extent: vk::Extent2D,
+        default_attachment: Option<Attachment>,
+    ) -> RendResult<()> {
+        let lifetimes = self.file.transient_lifetimes()?;
+        let mut resized = ResizedResources {
+            attachments: Vec::new(),
+            aliased_memory: None,
+            internal_extent,
+            external_extent,
+        };
+        let attachments_by_name = self
+            .make_resized_attachments(ctx, &lifetimes, default_attachment, &mut resized)
+            .and_then(|_| {
+                let mut attachments = self.attachments.clone();
+                for (index, attachment) in &resized.attachments {
+                    attachments[*index] = attachment.clone();
+                }
+                // Swapped stages see the history targets through their partners
+                Ok((
+                    self.file.attachments_by_name_of(&attachments, false)?,
+                    self.file.attachments_by_name_of(&attachments, true)?,
+                ))
+            });
+        let (attachments_by_name, swapped_attachments_by_name) = match attachments_by_name {
+            Ok(v) => v,
+            Err(err) => {
+                resized.destroy(&ctx.device);
+                return Err(err);
+            }
+        };
+        // Barriers refer to images directly, the ones being replaced are looked up
+        let images: HashMap<_, _> = resized
+            .attachments
+            .iter()
+            .map(|(index, e)| (self.attachments[*index].image, e.image))
+            .collect();
+        self.swap_resized(&mut resized);
+        for stage in &mut self.stages {
+            stage.resize(ctx, &attachments_by_name, &images);
+        }
+        for stage in &mut self.swapped_stages {
+            stage.resize(ctx, &swapped_attachments_by_name, &images);
+        }
+        // Only the replaced attachments are left in there
+        resized.destroy(&ctx.device);
+        Ok(())
+    }
+
+    /// Makes the attachments whose size changes with the extents into the resources, along with
+    /// the index of the attachment each replaces. Every lookup happens before making anything.
+    fn make_resized_attachments(
+        &self,
+        ctx: &crate::context::VulkanContext,
+        lifetimes: &HashMap<String, std::ops::RangeInclusive<usize>>,
+        default_attachment: Option<Attachment>,
+        resized: &mut ResizedResources,
+    ) -> RendResult<()> {
+        let (internal_extent, external_extent) = (resized.internal_extent, resized.external_extent);
+        let index_of = |name: &str| {
+            self.attachments
+                .iter()
+                .position(|e| e.name == name)
+                .ok_or_else(|| RendError::Pipeline(format!("attachment {} missing!", name)))
+        };
+        let mut separate_targets = Vec::new();
+        let mut aliased_targets = Vec::new();
+        let mut is_aliased_resized = false;
+        for target in &self.file.targets {
+            let extent = file::Pipeline::attachment_extent_of(
+                target.width,
+                target.height,
+                internal_extent,
+                external_extent,
+            );
+            let index = index_of(&target.name)?;
+            let attachment = &self.attachments[index];
+            if attachment.is_aliased {
+                // Shared memory may not fit anymore, these get placed again all at once
+                aliased_targets.push((index, target));
+                is_aliased_resized |= attachment.extent != extent;
+            } else if attachment.extent != extent {
+                separate_targets.push((index, target, extent));
+            }
+        }
+        let default_index = index_of(Attachment::DEFAULT_NAME)?;
+        let current_default = &self.attachments[default_index];
+        // Swapchain owned default attachments have no memory of their own
+        let is_default_owned = current_default.memory != vk::DeviceMemory::null();
+        let is_default_resized = is_default_owned && current_default.extent != external_extent;
+
+        for (index, target, extent) in separate_targets {
+            let attachment = file::Pipeline::make_attachment(ctx, target, extent)?;
+            resized.attachments.push((index, attachment));
+        }
+        if is_aliased_resized {
+            let targets: Vec<_> = aliased_targets.iter().map(|e| e.1).collect();
+            let (attachments, aliased_memory) = file::Pipeline::make_attachments(
+                ctx,
+                &targets,
+                lifetimes,
+                internal_extent,
+                external_extent,
+            )?;
+            let indices = aliased_targets.iter().map(|e| e.0);
+            resized.attachments.extend(indices.zip(attachments));
+            resized.aliased_memory = Some(aliased_memory);
+        }
+        let default_attachment = match default_attachment {
+            Some(attachment) => Some(attachment),
+            None if is_default_resized => Some(file::Pipeline::headless_default_attachment(
+                ctx,
+                external_extent,
+            )?),
+            None => None,
+        };
+        if let Some(attachment) = default_attachment {
+            resized.attachments.push((default_index, attachment));
+        }
+        Ok(())
+    }
+
+    /// Exchanges the resized resources with the pipeline's current ones
+    fn swap_resized(&mut self, resized: &mut ResizedResources) {
+        for (index, attachment) in &mut resized.attachments {
+            std::mem::swap(&mut self.attachments[*index], attachment);
+        }
+        if let Some(aliased_memory) = &mut resized.aliased_memory {
+            std::mem::swap(&mut self.aliased_memory, aliased_memory);
+        }
+        std::mem::swap(&mut self.internal_extent, &mut resized.internal_extent);
+        std::mem::swap(&mut self.external_extent, &mut resized.external_extent);
+    }
+
+    /// Reads the description again along its includes, recompiles the shaders and rebuilds
+    /// every stage and attachment, keeping the default attachment, samplers and texture
+    /// descriptors as they are. If reading, compiling or building any of the stages fails
+    /// the current pipeline is kept. Waits for the device to be idle before building anything.
+    pub fn reload(&mut self, ctx: &crate::context::VulkanContext) -> RendResult<()> {
+        let diagnostics = file::Pipeline::validate(Some(&self.path));
+        if !diagnostics.is_empty() {
+            let messages: Vec<_> = diagnostics.iter().map(|e| e.to_string()).collect();
+            return Err(RendError::Pipeline(messages.join("\n")));
+        }
+        let mut pip = file::Pipeline::read(Some(&self.path))?;
+        pip.cull()?;
+        let lifetimes = pip.transient_lifetimes()?;
+        let spirv_by_name = file::Pipeline::compile_shaders(&pip.programs)?;
+        unsafe {
+            ctx.device
+                .device_wait_idle()
+                .map_err(|e| RendError::vulkan(e, "waiting for the device to be idle"))?
+        };
+        // Targets may have been added, removed or changed, just make them all again
+        let targets: Vec<_> = pip.targets.iter().collect();
+        let (attachments, aliased_memory) = file::Pipeline::make_attachments(
+            ctx,
+            &targets,
+            &lifetimes,
+            self.internal_extent,
+            self.external_extent,
+        )?;
+        // Current sets stay allocated until the new stages replace them
+        let stage_descriptor_pool = descriptor::make_pool(ctx, false);
+        ctx.try_set_debug_name("stage_descriptor_pool", stage_descriptor_pool);
+        let mut resources = Resources {
+            stages: Vec::new(),
+            swapped_stages: Vec::new(),
+            attachments,
+            aliased_memory,
+            buffers: Vec::new(),
+            stage_descriptor_pool,
+            file: pip,
+            spirv_by_name,
+        };
+        match file::Pipeline::make_buffers(ctx, &resources.file.buffers) {
+            Ok(buffers) => resources.buffers = buffers,
+            Err(err) => {
+                resources.destroy(&ctx.device);
+                return Err(err);
+            }
+        }
+        // Stages get built out of the new resources, with the current ones set aside
+        self.swap_resources(&mut resources);
+        let result = self.build_stages(ctx);
+        if result.is_err() {
+            // Back to the current pipeline, what was made for the new one is dropped instead
+            self.swap_resources(&mut resources);
+        }
+        resources.destroy(&ctx.device);
+        result
+    }
+
+    /// Exchanges everything reloading makes again with the resources, the default attachment
+    /// stays with the pipeline.
+    fn swap_resources(&mut self, resources: &mut Resources) {
+        let default_attachment_index = self
+            .attachments
+            .iter()
+            .position(|e| e.is_default())
+            .expect("default attachment missing!");
+        let default_attachment = self.attachments.swap_remove(default_attachment_index);
+        std::mem::swap(&mut self.stages, &mut resources.stages);
+        std::mem::swap(&mut self.swapped_stages, &mut resources.swapped_stages);
+        std::mem::swap(&mut self.attachments, &mut resources.attachments);
+        std::mem::swap(&mut self.aliased_memory, &mut resources.aliased_memory);
+        std::mem::swap(&mut self.buffers, &mut resources.buffers);
+        std::mem::swap(
+            &mut self.stage_descriptor_pool,
+            &mut resources.stage_descriptor_pool,
+        );
+        std::mem::swap(&mut self.file, &mut resources.file);
+        std::mem::swap(&mut self.spirv_by_name, &mut resources.spirv_by_name);
+        self.attachments.push(default_attachment);
     }
 
     pub fn total_stages(&self) -> u32 {
@@ -76,9 +447,11 @@ impl Pipeline {
             .iter()
             .flat_map(|e| e.image_barriers())
             .collect();
-        // for every mip level of every attachment, find the first layout the render pipeline needs it to be in
+        // for every mip level of every layer of every attachment, find the first layout the render pipeline needs it to be in
         for att in &self.attachments {
-            for lvl in 0..att.levels() as u32 {
+            for (lvl, layer) in (0..att.levels() as u32)
+                .flat_map(|lvl| (0..att.layers as u32).map(move |layer| (lvl, layer)))
+            {
                 for barrier in &barriers {
                     if barrier.image != att.image {
                         // barrier doesn't corresponds to this image
@@ -91,8 +464,23 @@ impl Pipeline {
                         // barrier doesn't corresponds to level
                         continue;
                     }
-                    // found the first layout for this specific level
-                    first_layouts.push((att.image, barrier.old_layout, lvl, sub_range.aspect_mask));
+                    if layer < sub_range.base_array_layer
+                        || layer >= (sub_range.base_array_layer + sub_range.layer_count)
+                    {
+                        // barrier doesn't corresponds to layer
+                        continue;
+                    }
+                    // Aliased attachments transition from undefined on their first use each frame
+                    if barrier.old_layout != vk::ImageLayout::UNDEFINED {
+                        // found the first layout for this specific level and layer
+                        first_layouts.push((
+                            att.image,
+                            barrier.old_layout,
+                            lvl,
+                            layer,
+                            sub_range.aspect_mask,
+                        ));
+                    }
                     break;
                 }
             }
@@ -100,24 +488,26 @@ impl Pipeline {
         // generate all of the initial barriers transitioning into the first expected layout
         let initial_barriers: Vec<_> = first_layouts
             .into_iter()
-            .map(|(image, initial_layout, base_mip_level, aspect_mask)| {
-                vk::ImageMemoryBarrier2::default()
-                    .image(image)
-                    .src_access_mask(vk::AccessFlags2::NONE)
-                    .dst_access_mask(vk::AccessFlags2::NONE)
-                    .old_layout(vk::ImageLayout::UNDEFINED)
-                    .new_layout(initial_layout)
-                    .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-                    .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-                    // every mip level is transitioned individually, occurs only once so no problem
-                    .subresource_range(vk::ImageSubresourceRange {
-                        base_mip_level,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                        aspect_mask,
-                    })
-            })
+            .map(
+                |(image, initial_layout, base_mip_level, base_array_layer, aspect_mask)| {
+                    vk::ImageMemoryBarrier2::default()
+                        .image(image)
+                        .src_access_mask(vk::AccessFlags2::NONE)
+                        .dst_access_mask(vk::AccessFlags2::NONE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(initial_layout)
+                        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                        // every mip level and layer is transitioned individually, occurs only once so no problem
+                        .subresource_range(vk::ImageSubresourceRange {
+                            base_mip_level,
+                            level_count: 1,
+                            base_array_layer,
+                            layer_count: 1,
+                            aspect_mask,
+                        })
+                },
+            )
             .collect();
         // return initial barriers
         initial_barriers
@@ -129,15 +519,22 @@ impl Pipeline {
                 e.destroy(device);
             }
             device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_pool(self.stage_descriptor_pool, None);
             for e in self.samplers_by_key.values() {
                 e.destroy(device);
             }
-            for stage in &self.stages {
+            for stage in self.stages.iter().chain(&self.swapped_stages) {
                 stage.destroy(device);
             }
             for attachment in &self.attachments {
                 attachment.destroy(device);
             }
+            for memory in &self.aliased_memory {
+                device.free_memory(*memory, None);
+            }
+            for buffer in &self.buffers {
+                buffer.destroy(device);
+            }
         }
     }
 }
@@ -1,34 +1,49 @@
 use std::{collections::HashMap, mem::size_of};
 
 use crate::{
-    buffer::{DeviceAllocator, DeviceSlice},
-    pipeline::{attachment::Attachment, descriptor::DescriptorGroup},
+    buffer::LinearAllocator,
+    context::VulkanContext,
+    pipeline::{
+        attachment::Attachment,
+        descriptor::DescriptorGroup,
+        file::{DepthDesc, ScissorDesc, ViewportDesc},
+    },
     render_task::{RenderTask, TaskKind},
     shader_resource::{ResourceKind, SingleResource},
     updater,
 };
 use ash::vk::{self, ShaderStageFlags};
 
-use super::stage::Stage;
+use super::stage::{replace_barrier_images, Stage};
 
 pub struct RenderStage<'a> {
     pub name: String,
     pub rendering: Rendering<'a>,
     pub pipeline: vk::Pipeline,
     pub layout: vk::PipelineLayout,
-    pub render_area: vk::Rect2D,
+    /// Viewport and scissor get computed when rendering out of the current output extents
+    pub viewport: ViewportDesc,
+    pub scissor: ScissorDesc,
+    /// Depth range of the viewport
+    pub depth: DepthDesc,
+    /// Sets the scissor of every task, falling back to the stage one
+    pub scissor_per_task: bool,
     pub outputs: Vec<Attachment>,
     pub inputs: Vec<Attachment>,
+    /// Sampler of every input, in the same order
+    pub samplers: Vec<vk::Sampler>,
     pub per_instance_updaters: Vec<ResourceKind>,
     pub per_pass_updaters: Vec<ResourceKind>,
     pub per_pass_constant: Vec<f32>,
+    /// Addresses of the pipeline buffers it reads and writes, pushed after the per instance data
+    pub buffer_addresses: Vec<u64>,
     pub attachment_descriptors: Option<Box<DescriptorGroup>>,
     pub task_kind: TaskKind,
     pub batch_parent_id: u32,
     pub index: u32,
     pub is_final: bool,
     pub image_barriers: Vec<vk::ImageMemoryBarrier2<'a>>,
-    pub reserved_buffers: Vec<DeviceSlice>,
+    pub buffer_barriers: Vec<vk::BufferMemoryBarrier2<'a>>,
     pub is_validation_layer_enabled: bool,
 }
 
@@ -36,31 +51,66 @@ pub struct RenderStage<'a> {
 pub struct Rendering<'a> {
     pub attachments: Vec<vk::RenderingAttachmentInfo<'a>>,
     pub depth_stencil: Option<vk::RenderingAttachmentInfo<'a>>,
+    /// Attachment each output resolves into, if any
+    pub resolves: Vec<Option<Attachment>>,
+    pub depth_stencil_attachment: Option<Attachment>,
     pub default_attachment_index: Option<usize>,
+    /// Index of the attachment that resolves into the default attachment, if any
+    pub default_resolve_index: Option<usize>,
+    /// Layers every attachment gets rendered into
+    pub layer_count: u32,
+}
+
+impl RenderStage<'_> {
+    /// Minimum extent among the outputs, the default attachment one being the current target's
+    fn render_extent(&self, default_attachment: &Attachment) -> vk::Extent2D {
+        self.outputs
+            .iter()
+            .map(|e| {
+                if e.is_default() {
+                    default_attachment.usage_extent()
+                } else {
+                    e.usage_extent()
+                }
+            })
+            .reduce(|acc, e| vk::Extent2D {
+                width: e.width.min(acc.width),
+                height: e.height.min(acc.height),
+            })
+            .unwrap_or(default_attachment.extent)
+    }
 }
 
 impl<'a> Stage for RenderStage<'a> {
     fn work(&mut self, ctx: super::RenderContext) {
         let mut rendering_attachments = self.rendering.attachments.clone();
-        if self.is_final {
-            let dai = self.rendering.default_attachment_index.unwrap();
-            /*
-             * If it's a final stage, override the view with
-             * the current swapchain target
-             */
+        /*
+         * If it's a final stage, override the view with
+         * the current swapchain target
+         */
+        if let Some(dai) = self.rendering.default_attachment_index {
             rendering_attachments[dai] = vk::RenderingAttachmentInfo {
                 image_view: ctx.default_attachment.usage_view(),
                 ..rendering_attachments[dai]
             };
         }
+        if let Some(dri) = self.rendering.default_resolve_index {
+            rendering_attachments[dri] = vk::RenderingAttachmentInfo {
+                resolve_image_view: ctx.default_attachment.usage_view(),
+                ..rendering_attachments[dri]
+            };
+        }
+        let extent = self.render_extent(ctx.default_attachment);
+        let viewport = self.viewport.to_vk(&self.depth, extent);
+        let scissor = self.scissor.to_vk(extent);
         /*
          * New rendering info because lifetimes for the
          * arrays inside are too complex to keep around
          */
         let mut rendering_info_builder = vk::RenderingInfo::default()
             .color_attachments(&rendering_attachments)
-            .render_area(self.render_area)
-            .layer_count(1);
+            .render_area(scissor)
+            .layer_count(self.rendering.layer_count);
         if let Some(att) = &self.rendering.depth_stencil {
             rendering_info_builder = rendering_info_builder.depth_attachment(att);
         }
@@ -70,9 +120,10 @@ impl<'a> Stage for RenderStage<'a> {
                 ctx.default_attachment.image,
             ));
         }
-        if !image_barriers.is_empty() {
-            let barrier_dep_info =
-                vk::DependencyInfo::default().image_memory_barriers(&image_barriers);
+        if !image_barriers.is_empty() || !self.buffer_barriers.is_empty() {
+            let barrier_dep_info = vk::DependencyInfo::default()
+                .image_memory_barriers(&image_barriers)
+                .buffer_memory_barriers(&self.buffer_barriers);
             unsafe {
                 ctx.vulkan
                     .device
@@ -85,11 +136,6 @@ impl<'a> Stage for RenderStage<'a> {
                 .device
                 .cmd_begin_rendering(ctx.command_buffer, &rendering_info)
         };
-        /*
-         *  At this point we already waited for the previous stage invocation to finish,
-         *  we can free the buffers used back then.
-         */
-        self.release_reserved_buffers(ctx.buffer_allocator);
         // Compose the descriptor set array to bind for this stage
         let mut descriptor_sets = vec![ctx.sampler_descriptors.set, ctx.image_descriptors.set];
         if let Some(desc) = &self.attachment_descriptors {
@@ -110,18 +156,60 @@ impl<'a> Stage for RenderStage<'a> {
                 vk::PipelineBindPoint::GRAPHICS,
                 self.pipeline,
             );
+            // Dynamic state, pipelines of every stage leave it to be set here
+            ctx.vulkan
+                .device
+                .cmd_set_viewport(ctx.command_buffer, 0, &[viewport]);
+            ctx.vulkan
+                .device
+                .cmd_set_scissor(ctx.command_buffer, 0, &[scissor]);
         }
-        let tasks = &ctx
+        let tasks = ctx
             .batches_by_task_type
-            .get(&self.task_kind.to_key(self.batch_parent_id));
-        let per_pass_buffers = if tasks.is_none_or(|x| x.is_empty()) {
+            .get(&self.task_kind.to_key(self.batch_parent_id))
+            .map_or(&[][..], |e| e.as_slice());
+        let per_pass_buffers = if tasks.is_empty() {
             // Nothing to draw, nothing to reserve
-            Vec::new()
+            Some(Vec::new())
         } else {
-            self.reserve_pass_buffers(ctx.buffer_allocator, ctx.shader_resources_by_kind)
+            reserve_pass_buffers(
+                &self.per_pass_updaters,
+                &self.per_pass_constant,
+                ctx.frame_allocator,
+                ctx.shader_resources_by_kind,
+            )
+        };
+        let (tasks, per_pass_buffers) = match per_pass_buffers {
+            Some(per_pass_buffers) => (tasks, per_pass_buffers),
+            None => {
+                // Rendering checks these beforehand, shouldn't get here
+                log::warn!(
+                    "skipping tasks of stage {}, per pass resources are missing",
+                    self.name
+                );
+                (&[][..], Vec::new())
+            }
         };
-        for task in tasks.unwrap_or(&Vec::new()) {
-            let mesh_buffer = ctx.mesh_buffers_by_id.get(&task.mesh_buffer_id).unwrap();
+        for task in tasks {
+            let Some(mesh_buffer) = ctx.mesh_buffers_by_id.get(&task.mesh_buffer_id) else {
+                // Freeing meshes drops their tasks, shouldn't get here
+                log::warn!(
+                    "skipping task of stage {}, mesh {} is missing",
+                    self.name,
+                    task.mesh_buffer_id
+                );
+                continue;
+            };
+            let Some(per_instance_buffers) =
+                self.reserve_instance_buffers(ctx.frame_allocator, task)
+            else {
+                // Queueing checks these, only a reload in between could get here
+                log::warn!(
+                    "skipping task of stage {}, per instance resources are missing",
+                    self.name
+                );
+                continue;
+            };
             let is_indexed = !mesh_buffer.indices.is_empty();
             // Most of the time it's nowehere near going to be close to 32 addresses
             let mut push_constants: Vec<u64> = Vec::with_capacity(32);
@@ -136,9 +224,17 @@ impl<'a> Stage for RenderStage<'a> {
                 ]);
             }
             // Third, the per-instance date for the task, uploaded per task
-            push_constants.extend(&self.reserve_instance_buffers(ctx.buffer_allocator, task));
+            push_constants.extend(&per_instance_buffers);
+            // Last, the pipeline buffers the pass uses, so they don't shift any of the above
+            push_constants.extend(&self.buffer_addresses);
             // Now we push the data into the command stream and issue the draws
             unsafe {
+                if self.scissor_per_task {
+                    let scissor = task.scissor.map_or(scissor, |e| e.to_vk_within(scissor));
+                    ctx.vulkan
+                        .device
+                        .cmd_set_scissor(ctx.command_buffer, 0, &[scissor]);
+                }
                 if !push_constants.is_empty() {
                     let push_constants = push_constants.align_to::<u8>().1;
                     ctx.vulkan.device.cmd_push_constants(
@@ -184,6 +280,7 @@ impl<'a> Stage for RenderStage<'a> {
             // Need to transition for presenting
             let present_image_barriers = vec![Attachment::default_attachment_present_barrier(
                 ctx.default_attachment.image,
+                ctx.default_attachment_final_layout,
             )];
             let barrier_dep_info =
                 vk::DependencyInfo::default().image_memory_barriers(&present_image_barriers);
@@ -220,70 +317,147 @@ impl<'a> Stage for RenderStage<'a> {
             }
         }
     }
-}
 
-impl<'a> RenderStage<'a> {
-    fn release_reserved_buffers(&mut self, mem: &DeviceAllocator) {
-        for buffer in self.reserved_buffers.drain(..) {
-            mem.free(buffer);
+    fn resize(
+        &mut self,
+        ctx: &VulkanContext,
+        attachments_by_name: &HashMap<String, Attachment>,
+        images: &HashMap<vk::Image, vk::Image>,
+    ) {
+        let resized_all = |e: &[Attachment]| -> Vec<_> {
+            e.iter().map(|e| e.resized(attachments_by_name)).collect()
+        };
+        self.outputs = resized_all(&self.outputs);
+        self.inputs = resized_all(&self.inputs);
+        if let Some(desc) = &mut self.attachment_descriptors {
+            for (input, sampler) in self.inputs.iter().zip(&self.samplers) {
+                desc.place_image_sampler_at(
+                    ctx,
+                    input.descriptor_index,
+                    input.usage_view(),
+                    vk::ImageLayout::READ_ONLY_OPTIMAL,
+                    *sampler,
+                );
+            }
         }
-    }
-
-    fn reserve_instance_buffers(&mut self, mem: &DeviceAllocator, task: &RenderTask) -> Vec<u64> {
-        if self.per_instance_updaters.is_empty() {
-            // Nothing to upload
-            return Vec::new();
+        let rendering = &mut self.rendering;
+        for resolve in rendering.resolves.iter_mut().flatten() {
+            *resolve = resolve.resized(attachments_by_name);
         }
-        // We'll need the addresses to pass them to the shaders later
-        let mut device_addrs = Vec::with_capacity(self.per_instance_updaters.len());
-        for kind in self.per_instance_updaters.clone() {
-            if let Some(res) = task.resources.get(&kind) {
-                let buffer = updater::alloc_and_fill_multi(mem, res, task.instance_count);
-                device_addrs.push(buffer.device_addr);
-                self.reserved_buffers.push(buffer);
-            } else {
-                panic!("unavailable resource kind {}", kind)
+        for ((info, output), resolve) in rendering
+            .attachments
+            .iter_mut()
+            .zip(&self.outputs)
+            .zip(&rendering.resolves)
+        {
+            info.image_view = output.render_view();
+            if let Some(resolve) = resolve {
+                info.resolve_image_view = resolve.render_view();
+            }
+        }
+        if let Some(attachment) = &mut rendering.depth_stencil_attachment {
+            *attachment = attachment.resized(attachments_by_name);
+            if let Some(info) = &mut rendering.depth_stencil {
+                info.image_view = attachment.render_view();
             }
         }
-        device_addrs
+        replace_barrier_images(&mut self.image_barriers, images);
     }
 
-    fn reserve_pass_buffers(
-        &mut self,
-        mem: &DeviceAllocator,
+    fn per_instance_updaters(&self, key: u64) -> &[ResourceKind] {
+        if key == self.task_kind.to_key(self.batch_parent_id) {
+            &self.per_instance_updaters
+        } else {
+            &[]
+        }
+    }
+
+    fn missing_pass_resource(
+        &self,
+        batches_by_task_type: &HashMap<u64, Vec<RenderTask>>,
         shader_resources_by_kind: &HashMap<ResourceKind, SingleResource>,
-    ) -> Vec<u64> {
-        if self.per_pass_updaters.is_empty() && self.per_pass_constant.is_empty() {
+    ) -> Option<ResourceKind> {
+        let key = self.task_kind.to_key(self.batch_parent_id);
+        if batches_by_task_type.get(&key).is_none_or(|e| e.is_empty()) {
+            // Passes without tasks don't upload anything
+            return None;
+        }
+        missing_resource_of(&self.per_pass_updaters, shader_resources_by_kind)
+    }
+}
+
+impl<'a> RenderStage<'a> {
+    /// None if the task lacks any of the resources
+    fn reserve_instance_buffers(
+        &self,
+        mem: &LinearAllocator,
+        task: &RenderTask,
+    ) -> Option<Vec<u64>> {
+        if self.per_instance_updaters.is_empty() {
             // Nothing to upload
-            return Vec::new();
+            return Some(Vec::new());
         }
-        let aligned_pass_constant_size = (self.per_pass_constant.len() * size_of::<f32>())
-            .next_multiple_of(size_of::<glam::Vec4>());
-        let total_size = aligned_pass_constant_size
-            + self
-                .per_pass_updaters
-                .iter()
-                .map(|e| e.resource_size())
-                .sum::<usize>();
-        let dst = mem.alloc(total_size as u64).unwrap();
-        if !self.per_pass_constant.is_empty() {
-            unsafe {
-                let src = self.per_pass_constant.as_ptr();
-                let dst = dst.addr as *mut f32;
-                dst.copy_from_nonoverlapping(src, self.per_pass_constant.len());
-            }
+        if missing_resource_of(&self.per_instance_updaters, &task.resources).is_some() {
+            return None;
         }
-        let mut offset = aligned_pass_constant_size as u64;
-        for kind in self.per_pass_updaters.clone() {
-            if let Some(res) = shader_resources_by_kind.get(&kind) {
-                offset = updater::fill_single(res, &dst, offset);
-            } else {
-                panic!("unavailable resource kind {}", kind)
-            }
+        // We'll need the addresses to pass them to the shaders later
+        let device_addrs = self
+            .per_instance_updaters
+            .iter()
+            .map(|kind| {
+                let res = &task.resources[kind];
+                updater::alloc_and_fill_multi(mem, res, task.instance_count).device_addr
+            })
+            .collect();
+        Some(device_addrs)
+    }
+}
+
+/// First of the kinds that isn't among the resources
+pub(super) fn missing_resource_of<T>(
+    kinds: &[ResourceKind],
+    resources_by_kind: &HashMap<ResourceKind, T>,
+) -> Option<ResourceKind> {
+    kinds
+        .iter()
+        .find(|e| !resources_by_kind.contains_key(e))
+        .copied()
+}
+
+/// Uploads the per pass constant and updaters into a single buffer, shared with compute stages.
+/// None if any of the updaters' resources isn't placed.
+pub(super) fn reserve_pass_buffers(
+    per_pass_updaters: &[ResourceKind],
+    per_pass_constant: &[f32],
+    mem: &LinearAllocator,
+    shader_resources_by_kind: &HashMap<ResourceKind, SingleResource>,
+) -> Option<Vec<u64>> {
+    if per_pass_updaters.is_empty() && per_pass_constant.is_empty() {
+        // Nothing to upload
+        return Some(Vec::new());
+    }
+    if missing_resource_of(per_pass_updaters, shader_resources_by_kind).is_some() {
+        return None;
+    }
+    let aligned_pass_constant_size =
+        std::mem::size_of_val(per_pass_constant).next_multiple_of(size_of::<glam::Vec4>());
+    let total_size = aligned_pass_constant_size
+        + per_pass_updaters
+            .iter()
+            .map(|e| e.resource_size())
+            .sum::<usize>();
+    let dst = mem.alloc(total_size as u64);
+    if !per_pass_constant.is_empty() {
+        unsafe {
+            let src = per_pass_constant.as_ptr();
+            let dst = dst.addr as *mut f32;
+            dst.copy_from_nonoverlapping(src, per_pass_constant.len());
         }
-        // Will be freed later
-        self.reserved_buffers.push(dst);
-        // We'll need 1 address since all the data goes into the same buffer
-        vec![dst.device_addr]
     }
+    let mut offset = aligned_pass_constant_size as u64;
+    for kind in per_pass_updaters {
+        offset = updater::fill_single(&shader_resources_by_kind[kind], &dst, offset);
+    }
+    // We'll need 1 address since all the data goes into the same buffer
+    Some(vec![dst.device_addr])
 }
@@ -0,0 +1,232 @@
+use serde_json::Value;
+
+use super::file::*;
+
+/// Strings take the value as is, numbers their JSON representation
+fn text_of(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+fn substitute(template: &Value, pattern: &str, value: &Value) -> Value {
+    match template {
+        // Only the variable, keeps the value's type so it works for numeric fields too
+        Value::String(s) if s == pattern => value.clone(),
+        Value::String(s) => Value::String(s.replace(pattern, &text_of(value))),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|e| substitute(e, pattern, value))
+                .collect(),
+        ),
+        // Keys too, ie, the names of per pass constants
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.replace(pattern, &text_of(value)),
+                        substitute(v, pattern, value),
+                    )
+                })
+                .collect(),
+        ),
+        _ => template.clone(),
+    }
+}
+
+/// First string still referencing a variable, if any
+fn unresolved_in(template: &Value) -> Option<&str> {
+    match template {
+        Value::String(s) if s.contains("${") => Some(s),
+        Value::Array(items) => items.iter().find_map(unresolved_in),
+        Value::Object(fields) => fields.iter().find_map(|(k, v)| match k.contains("${") {
+            true => Some(k.as_str()),
+            false => unresolved_in(v),
+        }),
+        _ => None,
+    }
+}
+
+impl RepeatPass {
+    /// Values the variable takes, one per instance
+    fn values(&self) -> Result<Vec<Value>, String> {
+        match (&self.range, &self.values) {
+            (Some([start, end]), None) => Ok((*start..*end).map(Value::from).collect()),
+            (None, Some(values)) => {
+                match values.iter().find(|e| !e.is_string() && !e.is_number()) {
+                    Some(value) => Err(format!("value {} isn't a string nor a number", value)),
+                    None => Ok(values.clone()),
+                }
+            }
+            _ => Err("needs either a 'range' or 'values' to repeat over".to_string()),
+        }
+    }
+
+    /// Instantiates the template once per value in order, expanding nested repetitions
+    /// along the way. Instances of a disabled repetition are disabled too.
+    pub fn expand(&self) -> Result<Vec<PipelineStep>, String> {
+        let pattern = format!("${{{}}}", self.variable);
+        let mut steps = Vec::new();
+        for value in self.values()? {
+            let mut template = substitute(&self.template, &pattern, &value);
+            if let (true, Value::Object(fields)) = (self.is_disabled, &mut template) {
+                fields.insert("isDisabled".to_string(), Value::Bool(true));
+            }
+            let step = serde_json::from_value::<PipelineStep>(template.clone()).map_err(|e| {
+                format!("instance for {} {}: {}", self.variable, text_of(&value), e)
+            })?;
+            match step {
+                PipelineStep::Include(_) => return Err("includes can't be repeated".to_string()),
+                PipelineStep::Repeat(repeat) => steps.extend(repeat.expand()?),
+                step => {
+                    // Variables of nested repetitions are only resolved once these get expanded
+                    if let Some(unresolved) = unresolved_in(&template) {
+                        return Err(format!(
+                            "unresolved variable in '{}' of instance '{}'",
+                            unresolved,
+                            step.name()
+                        ));
+                    }
+                    steps.push(step);
+                }
+            }
+        }
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::super::file::*;
+
+    fn expand(repeat: Value) -> Result<Vec<PipelineStep>, String> {
+        serde_json::from_value::<RepeatPass>(repeat)
+            .unwrap()
+            .expand()
+    }
+
+    fn blit_pass(name: &str, input: &str, output: &str) -> Value {
+        let rect = json!({ "x": 0, "y": 0, "width": 1.0, "height": 1.0 });
+        json!({
+            "type": "blit",
+            "name": name,
+            "input": input,
+            "inputRect": rect,
+            "output": output,
+            "outputRect": rect,
+            "filter": "LINEAR",
+            "attributes": ["COLOR"],
+        })
+    }
+
+    fn names_of(steps: &[PipelineStep]) -> Vec<&str> {
+        steps.iter().map(|e| e.name()).collect()
+    }
+
+    #[test]
+    fn expands_ranges_and_values_in_order() {
+        let template = blit_pass("blur_${i}", "src", "out_${i}");
+        let steps = expand(json!({ "name": "blur", "range": [1, 4], "template": template }));
+        assert_eq!(names_of(&steps.unwrap()), ["blur_1", "blur_2", "blur_3"]);
+        let steps = expand(json!({
+            "name": "blur",
+            "values": ["near", 5],
+            "template": template,
+            "isDisabled": true,
+        }))
+        .unwrap();
+        assert_eq!(names_of(&steps), ["blur_near", "blur_5"]);
+        assert!(steps.iter().all(|e| e.is_disabled()));
+    }
+
+    #[test]
+    fn substitutes_nested_values_and_keys() {
+        let template = json!({
+            "type": "render",
+            "name": "shade_${i}",
+            "program": "blur",
+            "batch": "FULLSCREEN",
+            "outputs": ["out"],
+            "inputs": [{ "name": "level_${i}", "sampler": "DEFAULT" }],
+            "perPassUpdaters": [],
+            "perInstanceUpdaters": [],
+            "perPassConstant": { "radius_${i}": "${i}", "scale": 0.5 },
+            "state": { "type": "reference", "name": "fullscreen" },
+        });
+        let steps = expand(json!({ "name": "shade", "values": [2], "template": template }));
+        let Ok([PipelineStep::Render(pass)]) = steps.as_deref() else {
+            panic!("expected a single render pass!");
+        };
+        assert_eq!(pass.inputs[0].get().name, "level_2");
+        // Only the variable keeps the value's type, so it can be used in numeric fields
+        let constants: Vec<_> = pass.per_pass_constant.iter().flatten().collect();
+        assert_eq!(
+            constants,
+            [
+                (&"radius_2".to_string(), &2.0),
+                (&"scale".to_string(), &0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_nested_repetitions() {
+        let steps = expand(json!({
+            "name": "outer",
+            "variable": "face",
+            "values": ["x", "y"],
+            "template": {
+                "type": "repeat",
+                "name": "inner_${face}",
+                "range": [0, 2],
+                "template": blit_pass("copy_${face}_${i}", "src", "out"),
+            },
+        }));
+        assert_eq!(
+            names_of(&steps.unwrap()),
+            ["copy_x_0", "copy_x_1", "copy_y_0", "copy_y_1"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_repetitions() {
+        let template = blit_pass("blur_${i}", "src", "out");
+        let error_of = |repeat: Value| expand(repeat).err().expect("expected an error!");
+        assert_eq!(
+            error_of(json!({ "name": "blur", "template": template })),
+            "needs either a 'range' or 'values' to repeat over"
+        );
+        assert_eq!(
+            error_of(json!({ "name": "blur", "values": [[1]], "template": template })),
+            "value [1] isn't a string nor a number"
+        );
+        assert_eq!(
+            error_of(json!({
+                "name": "blur",
+                "variable": "level",
+                "range": [0, 1],
+                "template": template,
+            })),
+            "unresolved variable in 'blur_${i}' of instance 'blur_${i}'"
+        );
+        assert_eq!(
+            error_of(json!({
+                "name": "includes",
+                "range": [0, 1],
+                "template": { "type": "include", "name": "pipeline_${i}.json" },
+            })),
+            "includes can't be repeated"
+        );
+        let error = error_of(json!({
+            "name": "blur",
+            "values": ["near"],
+            "template": { "type": "blit", "name": "blur_${i}" },
+        }));
+        assert!(error.starts_with("instance for i near: "), "{}", error);
+    }
+}
@@ -1,5 +1,14 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
+use crate::{
+    context::VulkanContext,
+    pipeline::attachment::Attachment,
+    render_task::RenderTask,
+    shader_resource::{ResourceKind, SingleResource},
+};
+
 pub trait Stage {
     fn name(&self) -> &str;
     fn index(&self) -> u32;
@@ -7,4 +16,37 @@ pub trait Stage {
     fn image_barriers(&'_ self) -> Vec<vk::ImageMemoryBarrier2<'_>>;
     fn destroy(&self, device: &ash::Device);
     fn work(&mut self, ctx: super::RenderContext);
+    /// Points the stage to the attachments made again for a new extent, keeping its pipeline.
+    /// Attachments are looked up by name, barrier images by the image they replace.
+    fn resize(
+        &mut self,
+        ctx: &VulkanContext,
+        attachments_by_name: &HashMap<String, Attachment>,
+        images: &HashMap<vk::Image, vk::Image>,
+    );
+    /// Resources every task batched under the key has to carry for this stage
+    fn per_instance_updaters(&self, _key: u64) -> &[ResourceKind] {
+        &[]
+    }
+    /// First per pass resource the work would need with these batches that isn't placed.
+    /// Checked before recording since stages can't fail midway.
+    fn missing_pass_resource(
+        &self,
+        _batches_by_task_type: &HashMap<u64, Vec<RenderTask>>,
+        _shader_resources_by_kind: &HashMap<ResourceKind, SingleResource>,
+    ) -> Option<ResourceKind> {
+        None
+    }
+}
+
+/// Replaces the image of every barrier that refers to one made again
+pub fn replace_barrier_images(
+    barriers: &mut [vk::ImageMemoryBarrier2],
+    images: &HashMap<vk::Image, vk::Image>,
+) {
+    for barrier in barriers {
+        if let Some(image) = images.get(&barrier.image) {
+            barrier.image = *image;
+        }
+    }
 }
@@ -0,0 +1,1124 @@
+use std::collections::HashSet;
+
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{attachment::Attachment, file::*};
+use crate::{render_task::TaskKind, shader_resource::ResourceKind, texture::MipMap};
+
+/// Push constants are 128 bytes, which fit this many device addresses
+const MAX_PUSH_ADDRESSES: usize = 128 / std::mem::size_of::<u64>();
+
+/// Problem found in a pipeline description
+pub struct Diagnostic {
+    /// File the problem was found in
+    pub file: String,
+    /// JSON path of the offending value, ie, `$.passes[3].inputs[1]`
+    pub path: String,
+    /// Pass the offending value belongs to, if any
+    pub pass: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file, self.path)?;
+        if let Some(pass) = &self.pass {
+            write!(f, " (pass '{}')", pass)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Where an item of the pipeline was declared
+#[derive(Clone)]
+struct Origin {
+    file: String,
+    path: String,
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+    targets: IndexMap<String, (Target, Origin)>,
+    buffers: IndexMap<String, (Buffer, Origin)>,
+    programs: IndexMap<String, (Program, Origin)>,
+    shared_state: IndexMap<String, (State, Origin)>,
+    passes: Vec<(PipelineStep, Origin)>,
+    /// Files being read, to catch include cycles
+    include_stack: Vec<String>,
+}
+
+impl Pipeline {
+    /// Checks the pipeline description and all of its includes without touching the device,
+    /// reporting every problem found instead of stopping at the first one.
+    pub fn validate(name: Option<&str>) -> Vec<Diagnostic> {
+        let mut validator = Validator::default();
+        validator.read(name.unwrap_or("pipeline.json"), "$");
+        validator.check();
+        validator.diagnostics
+    }
+}
+
+impl Validator {
+    fn report(&mut self, origin: &Origin, pass: Option<&str>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: origin.file.clone(),
+            path: origin.path.clone(),
+            pass: pass.map(|e| e.to_string()),
+            message,
+        });
+    }
+
+    /// Reads the file and everything it includes, parsing each item on its own so
+    /// a single malformed item doesn't hide the problems in the rest of them.
+    fn read(&mut self, file: &str, included_from: &str) {
+        let origin = Origin {
+            file: file.to_string(),
+            path: "$".to_string(),
+        };
+        if self.include_stack.iter().any(|e| e == file) {
+            let message = format!(
+                "include cycle: {} -> {}",
+                self.include_stack.join(" -> "),
+                file
+            );
+            let includer = Origin {
+                file: self.include_stack.last().cloned().unwrap_or_default(),
+                path: included_from.to_string(),
+            };
+            self.report(&includer, None, message);
+            return;
+        }
+        let root = match std::fs::read_to_string(file) {
+            Ok(contents) => serde_json::from_str::<Value>(&contents),
+            Err(e) => {
+                self.report(&origin, None, format!("failed opening the pipeline: {}", e));
+                return;
+            }
+        };
+        let root = match root {
+            Ok(Value::Object(root)) => root,
+            Ok(_) => {
+                self.report(&origin, None, "expected an object".to_string());
+                return;
+            }
+            Err(e) => {
+                self.report(&origin, None, format!("couldn't parse the pipeline: {}", e));
+                return;
+            }
+        };
+        self.include_stack.push(file.to_string());
+
+        let array_of = |key: &str| match root.get(key) {
+            Some(Value::Array(items)) => Ok(items.clone()),
+            Some(_) => Err(format!("'{}' must be an array", key)),
+            None => Err(format!("missing '{}'", key)),
+        };
+        let items_of = |this: &mut Self, key: &str| match array_of(key) {
+            Ok(items) => items,
+            Err(message) => {
+                this.report(&origin, None, message);
+                Vec::new()
+            }
+        };
+
+        for (i, item) in items_of(self, "targets").into_iter().enumerate() {
+            let origin = origin.at(&format!("$.targets[{}]", i));
+            if let Some(target) = self.parse::<Target>(item, &origin, None) {
+                if let Some((_, prev)) = self.targets.get(&target.name) {
+                    let message = format!(
+                        "target '{}' already declared at {}: {}",
+                        target.name, prev.file, prev.path
+                    );
+                    self.report(&origin, None, message);
+                }
+                self.targets.insert(target.name.clone(), (target, origin));
+            }
+        }
+        // Buffers are optional, unlike the rest
+        let buffers = match array_of("buffers") {
+            Ok(items) => items,
+            Err(_) if root.get("buffers").is_none() => Vec::new(),
+            Err(message) => {
+                self.report(&origin, None, message);
+                Vec::new()
+            }
+        };
+        for (i, item) in buffers.into_iter().enumerate() {
+            let origin = origin.at(&format!("$.buffers[{}]", i));
+            if let Some(buffer) = self.parse::<Buffer>(item, &origin, None) {
+                if let Some((_, prev)) = self.buffers.get(&buffer.name) {
+                    let message = format!(
+                        "buffer '{}' already declared at {}: {}",
+                        buffer.name, prev.file, prev.path
+                    );
+                    self.report(&origin, None, message);
+                }
+                self.buffers.insert(buffer.name.clone(), (buffer, origin));
+            }
+        }
+        for (i, item) in items_of(self, "programs").into_iter().enumerate() {
+            let origin = origin.at(&format!("$.programs[{}]", i));
+            if let Some(program) = self.parse::<Program>(item, &origin, None) {
+                if let Some((_, prev)) = self.programs.get(&program.name) {
+                    let message = format!(
+                        "program '{}' already declared at {}: {}",
+                        program.name, prev.file, prev.path
+                    );
+                    self.report(&origin, None, message);
+                }
+                self.programs
+                    .insert(program.name.clone(), (program, origin));
+            }
+        }
+        match root.get("sharedState") {
+            Some(Value::Object(states)) => {
+                for (name, item) in states {
+                    let origin = origin.at(&format!("$.sharedState.{}", name));
+                    if let Some(state) = self.parse::<State>(item.clone(), &origin, None) {
+                        self.shared_state.insert(name.clone(), (state, origin));
+                    }
+                }
+            }
+            Some(_) => self.report(&origin, None, "'sharedState' must be an object".to_string()),
+            None => self.report(&origin, None, "missing 'sharedState'".to_string()),
+        }
+        for (i, item) in items_of(self, "passes").into_iter().enumerate() {
+            let origin = origin.at(&format!("$.passes[{}]", i));
+            let name = item.get("name").and_then(|e| e.as_str()).map(String::from);
+            let Some(step) = self.parse::<PipelineStep>(item, &origin, name.as_deref()) else {
+                continue;
+            };
+            match step {
+                PipelineStep::Include(include) => {
+                    // Same as when reading, disabled includes still contribute everything but passes
+                    let pass_count = self.passes.len();
+                    self.read(&include.name, &origin.path);
+                    if include.is_disabled {
+                        self.passes.truncate(pass_count);
+                    }
+                }
+                PipelineStep::Repeat(repeat) => match repeat.expand() {
+                    Ok(steps) => {
+                        let origin = origin.at(".template");
+                        self.passes
+                            .extend(steps.into_iter().map(|e| (e, origin.clone())));
+                    }
+                    Err(message) => self.report(&origin, Some(&repeat.name), message),
+                },
+                _ => self.passes.push((step, origin)),
+            }
+        }
+
+        self.include_stack.pop();
+    }
+
+    fn parse<T: DeserializeOwned>(
+        &mut self,
+        item: Value,
+        origin: &Origin,
+        pass: Option<&str>,
+    ) -> Option<T> {
+        match serde_json::from_value(item) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.report(origin, pass, e.to_string());
+                None
+            }
+        }
+    }
+
+    fn check(&mut self) {
+        for (target, origin) in self.targets.values() {
+            let mut problems = Vec::new();
+            if target.name == Attachment::DEFAULT_NAME {
+                problems.push(format!("target name '{}' is reserved", target.name));
+            }
+            if target.name.contains('@') {
+                problems.push(format!(
+                    "target names can't contain '@', it's reserved for '{}' references",
+                    Target::HISTORY_SUFFIX
+                ));
+            }
+            if target.level < 1 {
+                problems.push("level must be at least 1".to_string());
+            }
+            if target.layers < 1 {
+                problems.push("layers must be at least 1".to_string());
+            }
+            if target.cube {
+                let is_square = match (target.width, target.height) {
+                    (U32OrF32::U32(w), U32OrF32::U32(h)) => w == h,
+                    _ => false,
+                };
+                if !is_square {
+                    problems.push("cube targets need the same fixed width and height".to_string());
+                }
+                if target.samples > 1 {
+                    problems.push("cube targets can't be multisampled".to_string());
+                }
+                if target.layers as u32 * 6 > u8::MAX as u32 {
+                    problems.push(format!("{} cubes are too many layers", target.layers));
+                }
+            }
+            if is_zero(target.width) || is_zero(target.height) {
+                problems.push("width and height can't be zero".to_string());
+            }
+            if !target.samples.is_power_of_two() || target.samples > 64 {
+                problems.push(format!("{} is an invalid sample count", target.samples));
+            } else if target.samples > 1 && target.level > 1 {
+                problems.push("multisampled targets can't have more than one level".to_string());
+            }
+            for message in problems {
+                self.diagnostics.push(origin.diagnostic(None, message));
+            }
+        }
+        // Same as when reading, history targets get a second one with the previous frame
+        let history_targets: Vec<_> = self
+            .targets
+            .values()
+            .filter(|e| e.0.history)
+            .map(|(target, origin)| (target.history_target(), origin.clone()))
+            .collect();
+        for (target, origin) in history_targets {
+            self.targets.insert(target.name.clone(), (target, origin));
+        }
+        for (buffer, origin) in self.buffers.values() {
+            let mut problems = Vec::new();
+            if buffer.size == 0 {
+                problems.push("size can't be zero".to_string());
+            }
+            if buffer.name == Attachment::DEFAULT_NAME || self.targets.contains_key(&buffer.name) {
+                problems.push(format!("buffer '{}' has the name of a target", buffer.name));
+            }
+            for message in problems {
+                self.diagnostics.push(origin.diagnostic(None, message));
+            }
+        }
+        for (program, origin) in self.programs.values() {
+            let shaders = [
+                &program.vertex,
+                &program.fragment,
+                &program.geometry,
+                &program.compute,
+            ];
+            if !program.compute.is_empty() {
+                if shaders[..3].iter().any(|e| !e.is_empty()) {
+                    let message = format!(
+                        "compute program '{}' can't have other shaders",
+                        program.name
+                    );
+                    self.diagnostics.push(origin.diagnostic(None, message));
+                }
+            } else if program.vertex.is_empty() {
+                let message = format!("program '{}' has no vertex shader", program.name);
+                self.diagnostics.push(origin.diagnostic(None, message));
+            }
+            for shader in shaders.into_iter().filter(|e| !e.is_empty()) {
+                let path = Pipeline::source_path_of(shader);
+                if !std::path::Path::new(&path).is_file() {
+                    let message = format!("shader source {} not found", path);
+                    self.diagnostics.push(origin.diagnostic(None, message));
+                }
+            }
+        }
+        for (state, origin) in self.shared_state.values() {
+            for message in check_state(state) {
+                self.diagnostics.push(origin.diagnostic(None, message));
+            }
+        }
+        let mut pass_names = HashSet::new();
+        for (pass, origin) in &self.passes {
+            if !pass_names.insert(pass.name()) {
+                let message = format!("pass name '{}' is used more than once", pass.name());
+                self.diagnostics
+                    .push(origin.diagnostic(Some(pass.name()), message));
+            }
+            // Disabled passes are never built, so broken references in them are harmless
+            if pass.is_disabled() {
+                continue;
+            }
+            let problems = match pass {
+                PipelineStep::Render(render) => self.check_render(render),
+                PipelineStep::Blit(blit) => self.check_blit(blit),
+                PipelineStep::Compute(compute) => self.check_compute(compute),
+                PipelineStep::GenerateMips(mips) => self.check_mips(mips),
+                PipelineStep::Include(_) | PipelineStep::Repeat(_) => Vec::new(),
+            };
+            for (path, message) in problems {
+                self.diagnostics
+                    .push(origin.at(&path).diagnostic(Some(pass.name()), message));
+            }
+        }
+    }
+
+    /// Returns the problems found in the pass, along the path relative to the pass.
+    fn check_render(&self, pass: &RenderPass) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+        match self.programs.get(&pass.program) {
+            None => problems.push((
+                ".program".to_string(),
+                format!("unknown program '{}'", pass.program),
+            )),
+            Some((program, _)) if !program.compute.is_empty() => problems.push((
+                ".program".to_string(),
+                format!("'{}' is a compute program", pass.program),
+            )),
+            Some(_) => (),
+        }
+        let state = match &pass.state {
+            BaseState::State(state) => Some(state),
+            BaseState::Reference(reference) => {
+                let state = self.shared_state.get(&reference.name).map(|e| &e.0);
+                if state.is_none() {
+                    problems.push((
+                        ".state.name".to_string(),
+                        format!("unresolved state reference '{}'", reference.name),
+                    ));
+                }
+                state
+            }
+        };
+        if let (Some(state), BaseState::State(_)) = (state, &pass.state) {
+            for message in check_state(state) {
+                problems.push((".state".to_string(), message));
+            }
+        }
+        if let Some(depth_stencil) = &pass.depth_stencil {
+            let depth_stencil = depth_stencil.get();
+            if let Some(message) = self
+                .check_output_ref(&depth_stencil.name, depth_stencil.level)
+                .or_else(|| self.check_face_ref(&depth_stencil))
+                .or_else(|| self.check_layer_ref(&depth_stencil.name, depth_stencil.layer()))
+            {
+                problems.push((".depthStencil".to_string(), message));
+            }
+        } else if state.is_some_and(needs_depth_stencil) {
+            problems.push((
+                ".depthStencil".to_string(),
+                "pass writes or tests depth/stencil but has no 'depthStencil' target".to_string(),
+            ));
+        }
+        let depth_stencil = pass.depth_stencil.as_ref().map(|e| e.get());
+        let mut pass_samples = depth_stencil
+            .as_ref()
+            .and_then(|e| self.samples_of(&e.name));
+        // Layered rendering writes the same amount of layers into every attachment
+        let mut pass_layers = depth_stencil
+            .as_ref()
+            .and_then(|e| self.usage_layers_of(&e.name, e.layer()));
+        for (i, output) in pass.outputs.iter().enumerate() {
+            let output = output.get();
+            let path = format!(".outputs[{}]", i);
+            if let Some(message) = self
+                .check_output_ref(&output.name, output.level)
+                .or_else(|| self.check_face_ref(&output))
+                .or_else(|| self.check_layer_ref(&output.name, output.layer()))
+            {
+                problems.push((path, message));
+                continue;
+            }
+            let layers = self
+                .usage_layers_of(&output.name, output.layer())
+                .unwrap_or(1);
+            if pass_layers.is_some_and(|e| e != layers) {
+                problems.push((
+                    path.clone(),
+                    "all outputs and the depthStencil target must write the same amount of layers"
+                        .to_string(),
+                ));
+            }
+            pass_layers.get_or_insert(layers);
+            let samples = self.samples_of(&output.name).unwrap_or(1);
+            if pass_samples.is_some_and(|e| e != samples) {
+                problems.push((
+                    path.clone(),
+                    "all outputs and the depthStencil target must have the same sample count"
+                        .to_string(),
+                ));
+            }
+            pass_samples.get_or_insert(samples);
+            let Some(resolve) = &output.resolve else {
+                continue;
+            };
+            let path = format!("{}.resolve", path);
+            if samples < 2 {
+                problems.push((
+                    path,
+                    "only multisampled outputs can be resolved".to_string(),
+                ));
+                continue;
+            }
+            if let Some(message) = self.check_output_ref(resolve, 0) {
+                problems.push((path, message));
+                continue;
+            }
+            if self.samples_of(resolve).unwrap_or(1) > 1 {
+                problems.push((path, "can't resolve into a multisampled target".to_string()));
+                continue;
+            }
+            if let (Some((from, _)), Some((to, _))) =
+                (self.targets.get(&output.name), self.targets.get(resolve))
+            {
+                if from.format != to.format {
+                    problems.push((path, "can't resolve into a different format".to_string()));
+                } else if from.image_layers() != to.image_layers() {
+                    problems.push((
+                        path,
+                        "can't resolve into a different amount of layers".to_string(),
+                    ));
+                }
+            }
+        }
+        self.check_inputs(&pass.inputs, &mut problems);
+        self.check_buffers(&pass.input_buffers, &pass.output_buffers, &mut problems);
+        let per_pass_updaters: Vec<ResourceKind> = pass
+            .per_pass_updaters
+            .iter()
+            .map(|e| e.to_resource_kind())
+            .collect();
+        if !Pipeline::is_pass_updater_order_valid(&per_pass_updaters) {
+            problems.push((
+                ".perPassUpdaters".to_string(),
+                "per pass updaters are not sorted by resource size".to_string(),
+            ));
+        }
+        // Per pass data, buffers, vertex data and per instance data, in that order
+        let has_pass_data = !per_pass_updaters.is_empty()
+            || pass
+                .per_pass_constant
+                .as_ref()
+                .is_some_and(|e| !e.is_empty());
+        let addresses = has_pass_data as usize
+            + pass.input_buffers.len()
+            + pass.output_buffers.len()
+            + if pass.batch == TaskKind::Fullscreen {
+                0
+            } else {
+                3
+            }
+            + pass.per_instance_updaters.len();
+        if addresses > MAX_PUSH_ADDRESSES {
+            problems.push((
+                ".outputBuffers".to_string(),
+                format!(
+                    "pushes {} addresses, push constants only fit {}",
+                    addresses, MAX_PUSH_ADDRESSES
+                ),
+            ));
+        }
+        problems
+    }
+
+    fn check_compute(&self, pass: &ComputePass) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+        match self.programs.get(&pass.program) {
+            None => problems.push((
+                ".program".to_string(),
+                format!("unknown program '{}'", pass.program),
+            )),
+            Some((program, _)) if program.compute.is_empty() => problems.push((
+                ".program".to_string(),
+                format!("'{}' has no compute shader", pass.program),
+            )),
+            Some(_) => (),
+        }
+        if pass.outputs.is_empty() && pass.output_buffers.is_empty() {
+            problems.push((
+                ".outputs".to_string(),
+                "compute pass writes no outputs nor buffers".to_string(),
+            ));
+        }
+        let inputs: Vec<_> = pass.inputs.iter().map(|e| e.get()).collect();
+        for (i, output) in pass.outputs.iter().enumerate() {
+            let output = output.get();
+            let path = format!(".outputs[{}]", i);
+            if output.name == Attachment::DEFAULT_NAME {
+                problems.push((path, "default target can't be a storage image".to_string()));
+                continue;
+            }
+            if let Some(message) = self
+                .check_output_ref(&output.name, output.level)
+                .or_else(|| self.check_face_ref(&output))
+                .or_else(|| self.check_layer_ref(&output.name, output.layer()))
+            {
+                problems.push((path, message));
+                continue;
+            }
+            let format = self.targets[&output.name].0.format;
+            if !format.supports_storage() {
+                problems.push((
+                    path.clone(),
+                    format!("format {} can't be a storage image", format),
+                ));
+            }
+            if self.samples_of(&output.name).unwrap_or(1) > 1 {
+                problems.push((
+                    path.clone(),
+                    "multisampled targets can't be storage images".to_string(),
+                ));
+            }
+            let is_read = inputs.iter().any(|e| {
+                e.name == output.name
+                    && (e.level.get() == output.level || MipMap::is_all_levels_value(e.level.get()))
+            });
+            if is_read {
+                problems.push((path, "can't write into its input".to_string()));
+            }
+        }
+        self.check_inputs(&pass.inputs, &mut problems);
+        let target = pass.dispatch.target.get();
+        if let Some(message) = self.check_target_ref(&target.name, target.level) {
+            problems.push((".dispatch.target".to_string(), message));
+        }
+        if is_zero(pass.dispatch.width) || is_zero(pass.dispatch.height) {
+            problems.push((
+                ".dispatch".to_string(),
+                "width and height can't be zero".to_string(),
+            ));
+        }
+        if pass.dispatch.group_size.contains(&0) {
+            problems.push((
+                ".dispatch.groupSize".to_string(),
+                "group size can't be zero".to_string(),
+            ));
+        }
+        let per_pass_updaters: Vec<ResourceKind> = pass
+            .per_pass_updaters
+            .iter()
+            .map(|e| e.to_resource_kind())
+            .collect();
+        if !Pipeline::is_pass_updater_order_valid(&per_pass_updaters) {
+            problems.push((
+                ".perPassUpdaters".to_string(),
+                "per pass updaters are not sorted by resource size".to_string(),
+            ));
+        }
+        self.check_buffers(&pass.input_buffers, &pass.output_buffers, &mut problems);
+        let has_pass_data = !per_pass_updaters.is_empty()
+            || pass
+                .per_pass_constant
+                .as_ref()
+                .is_some_and(|e| !e.is_empty());
+        let addresses =
+            has_pass_data as usize + pass.input_buffers.len() + pass.output_buffers.len();
+        if addresses > MAX_PUSH_ADDRESSES {
+            problems.push((
+                ".outputBuffers".to_string(),
+                format!(
+                    "pushes {} addresses, push constants only fit {}",
+                    addresses, MAX_PUSH_ADDRESSES
+                ),
+            ));
+        }
+        problems
+    }
+
+    fn check_mips(&self, pass: &GenerateMipsPass) -> Vec<(String, String)> {
+        let path = ".target".to_string();
+        if pass.target == Attachment::DEFAULT_NAME {
+            return vec![(path, "default target has no mip chain".to_string())];
+        }
+        if let Some(message) = self.check_output_ref(&pass.target, 0) {
+            return vec![(path, message)];
+        }
+        match self.targets.get(&pass.target) {
+            None => vec![(path, format!("unknown target '{}'", pass.target))],
+            Some((target, _)) if target.level < 2 => vec![(
+                path,
+                format!("target '{}' has no levels to generate", pass.target),
+            )],
+            Some(_) => Vec::new(),
+        }
+    }
+
+    fn check_inputs(
+        &self,
+        inputs: &[StrOrObj<AttachmentInput>],
+        problems: &mut Vec<(String, String)>,
+    ) {
+        for (i, input) in inputs.iter().enumerate() {
+            let input = input.get();
+            let path = format!(".inputs[{}]", i);
+            if input.name == Attachment::DEFAULT_NAME {
+                problems.push((path, "default target can't be sampled".to_string()));
+                continue;
+            }
+            let level = match &input.level {
+                StrOrObj::Obj(level) => *level,
+                StrOrObj::Str(s) if MipMap::is_all_levels_name(s) => 0,
+                StrOrObj::Str(s) => {
+                    problems.push((path, format!("{} is an invalid mip map level", s)));
+                    continue;
+                }
+            };
+            if let Some(message) = self
+                .check_target_ref(&input.name, level)
+                .or_else(|| self.check_layer_ref(&input.name, input.layer))
+            {
+                problems.push((path.clone(), message));
+            }
+            let is_cube = self.targets.get(&input.name).is_some_and(|e| e.0.cube);
+            if is_cube && input.layer.is_some() {
+                problems.push((
+                    path.clone(),
+                    "cube targets are sampled whole, without a layer".to_string(),
+                ));
+            }
+            if !is_option_valid(&input.sampler, &SAMPLER_OPTIONS) {
+                problems.push((path, "invalid sampler option".to_string()));
+            }
+        }
+    }
+
+    fn check_buffers(
+        &self,
+        inputs: &[String],
+        outputs: &[String],
+        problems: &mut Vec<(String, String)>,
+    ) {
+        for (key, names) in [("inputBuffers", inputs), ("outputBuffers", outputs)] {
+            for (i, name) in names.iter().enumerate() {
+                let path = format!(".{}[{}]", key, i);
+                if !self.buffers.contains_key(name) {
+                    problems.push((path, format!("unknown buffer '{}'", name)));
+                } else if names[..i].contains(name) {
+                    problems.push((path, format!("buffer '{}' is used more than once", name)));
+                }
+            }
+        }
+    }
+
+    fn check_blit(&self, pass: &BlitPass) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+        let input = pass.input.get();
+        let output = pass.output.get();
+        if let Some(message) = self
+            .check_target_ref(&input.name, input.level)
+            .or_else(|| self.check_face_ref(&input))
+            .or_else(|| self.check_layer_ref(&input.name, input.layer()))
+        {
+            problems.push((".input".to_string(), message));
+        }
+        if let Some(message) = self
+            .check_output_ref(&output.name, output.level)
+            .or_else(|| self.check_face_ref(&output))
+            .or_else(|| self.check_layer_ref(&output.name, output.layer()))
+        {
+            problems.push((".output".to_string(), message));
+        }
+        if self.usage_layers_of(&input.name, input.layer())
+            != self.usage_layers_of(&output.name, output.layer())
+        {
+            problems.push((
+                ".output".to_string(),
+                "input and output must have the same amount of layers".to_string(),
+            ));
+        }
+        if input.name == output.name && input.level == output.level {
+            problems.push((
+                ".output".to_string(),
+                "can't blit into its input".to_string(),
+            ));
+        }
+        for (path, name) in [(".input", &input.name), (".output", &output.name)] {
+            if self.samples_of(name).unwrap_or(1) > 1 {
+                problems.push((
+                    path.to_string(),
+                    "multisampled targets can't be blitted, resolve them instead".to_string(),
+                ));
+            }
+        }
+        if pass.attributes.is_empty() {
+            problems.push((
+                ".attributes".to_string(),
+                "missing attributes to blit".to_string(),
+            ));
+        }
+        problems
+    }
+
+    /// None if the target is unknown, default target is single sampled
+    fn samples_of(&self, name: &str) -> Option<u8> {
+        if name == Attachment::DEFAULT_NAME {
+            return Some(1);
+        }
+        self.targets.get(name).map(|e| e.0.samples)
+    }
+
+    /// Amount of layers a reference uses, None if the target is unknown
+    fn usage_layers_of(&self, name: &str, layer: Option<u8>) -> Option<u8> {
+        if layer.is_some() || name == Attachment::DEFAULT_NAME {
+            return Some(1);
+        }
+        self.targets.get(name).map(|e| e.0.image_layers())
+    }
+
+    /// Faces only exist in cube targets, which need one to write into a single layer
+    fn check_face_ref(&self, output: &AttachmentOutput) -> Option<String> {
+        let is_cube = self.targets.get(&output.name).is_some_and(|e| e.0.cube);
+        match output.face {
+            Some(_) if !is_cube => Some(format!("'{}' isn't a cube target", output.name)),
+            Some(face) if face >= 6 => Some(format!("face {} out of range", face)),
+            None if is_cube && output.layer.is_some() => {
+                Some("needs a 'face' to write into a single layer of a cube".to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn check_layer_ref(&self, name: &str, layer: Option<u8>) -> Option<String> {
+        let layer = layer?;
+        let layers = match name == Attachment::DEFAULT_NAME {
+            true => 1,
+            false => self.targets.get(name)?.0.image_layers(),
+        };
+        (layer >= layers).then(|| {
+            format!(
+                "layer {} out of range for '{}' with {} layers",
+                layer, name, layers
+            )
+        })
+    }
+
+    /// Same as [Validator::check_target_ref], for references that get written into
+    fn check_output_ref(&self, name: &str, level: u8) -> Option<String> {
+        if name.ends_with(Target::HISTORY_SUFFIX) && self.targets.contains_key(name) {
+            return Some(format!(
+                "previous frame of '{}' can only be read",
+                name.trim_end_matches(Target::HISTORY_SUFFIX)
+            ));
+        }
+        self.check_target_ref(name, level)
+    }
+
+    fn check_target_ref(&self, name: &str, level: u8) -> Option<String> {
+        if name == Attachment::DEFAULT_NAME {
+            // Default target only has a single level
+            return (level > 0).then(|| format!("level {} out of range for '{}'", level, name));
+        }
+        match self.targets.get(name) {
+            None => match name.strip_suffix(Target::HISTORY_SUFFIX) {
+                Some(current) if self.targets.contains_key(current) => Some(format!(
+                    "'{}' isn't a history target, can't read its previous frame",
+                    current
+                )),
+                _ => Some(format!("unknown target '{}'", name)),
+            },
+            Some((target, _)) if level >= target.level => Some(format!(
+                "level {} out of range for '{}' with {} levels",
+                level, name, target.level
+            )),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Origin {
+    fn at(&self, path: &str) -> Origin {
+        // Relative paths start with a dot, absolute ones with the root
+        let path = match path.starts_with('.') {
+            true => format!("{}{}", self.path, path),
+            false => path.to_string(),
+        };
+        Origin {
+            file: self.file.clone(),
+            path,
+        }
+    }
+
+    fn diagnostic(&self, pass: Option<&str>, message: String) -> Diagnostic {
+        Diagnostic {
+            file: self.file.clone(),
+            path: self.path.clone(),
+            pass: pass.map(|e| e.to_string()),
+            message,
+        }
+    }
+}
+
+/// Predefined and specific values each option supports, mirroring the [Predefined] and
+/// [DescHandler] implementations.
+struct Options {
+    predefined: &'static [&'static str],
+    specific: &'static [&'static str],
+}
+
+const SAMPLER_OPTIONS: Options = Options {
+    predefined: &["DEFAULT"],
+    specific: &[],
+};
+const WRITING_OPTIONS: Options = Options {
+    predefined: &["DEFAULT", "YES", "NO"],
+    specific: &["COLOR", "DEPTH", "STENCIL"],
+};
+const DEPTH_OPTIONS: Options = Options {
+    predefined: &["DEFAULT", "NO"],
+    specific: &["CLAMP"],
+};
+const STENCIL_OPTIONS: Options = Options {
+    predefined: &["NO"],
+    specific: &[],
+};
+const BLENDING_OPTIONS: Options = Options {
+    predefined: &["YES", "NO"],
+    specific: &[],
+};
+const CLEARING_OPTIONS: Options = Options {
+    predefined: &["DEFAULT", "YES", "NO"],
+    specific: &["COLOR", "DEPTH", "STENCIL"],
+};
+// Scissor, viewport and triangle
+const DEFAULT_ONLY_OPTIONS: Options = Options {
+    predefined: &["DEFAULT"],
+    specific: &[],
+};
+
+fn check_state(state: &State) -> Vec<String> {
+    let options = [
+        ("writing", is_option_valid(&state.writing, &WRITING_OPTIONS)),
+        ("depth", is_option_valid(&state.depth, &DEPTH_OPTIONS)),
+        (
+            "scissor",
+            is_option_valid(&state.scissor, &DEFAULT_ONLY_OPTIONS),
+        ),
+        (
+            "viewport",
+            is_option_valid(&state.viewport, &DEFAULT_ONLY_OPTIONS),
+        ),
+        ("stencil", is_option_valid(&state.stencil, &STENCIL_OPTIONS)),
+        (
+            "triangle",
+            is_option_valid(&state.triangle, &DEFAULT_ONLY_OPTIONS),
+        ),
+        (
+            "blending",
+            is_option_valid(&state.blending, &BLENDING_OPTIONS),
+        ),
+        (
+            "clearing",
+            is_option_valid(&state.clearing, &CLEARING_OPTIONS),
+        ),
+    ];
+    options
+        .into_iter()
+        .filter(|e| !e.1)
+        .map(|e| format!("invalid '{}' option", e.0))
+        .collect()
+}
+
+fn is_option_valid<T>(desc: &DescOption<T>, options: &Options) -> bool {
+    match desc {
+        DescOption::Predefined(v) => options.predefined.contains(&v.name()),
+        DescOption::Specific(v) => options.specific.contains(&v.as_str()),
+        DescOption::Configured(_) => true,
+    }
+}
+
+fn needs_depth_stencil(state: &State) -> bool {
+    // Invalid options are reported on their own
+    if !is_option_valid(&state.writing, &WRITING_OPTIONS)
+        || !is_option_valid(&state.depth, &DEPTH_OPTIONS)
+        || !is_option_valid(&state.stencil, &STENCIL_OPTIONS)
+    {
+        return false;
+    }
+    let (Ok(writing), Ok(depth), Ok(stencil)) = (
+        Pipeline::handle_option(state.writing.clone()),
+        Pipeline::handle_option(state.depth.clone()),
+        Pipeline::handle_option(state.stencil.clone()),
+    ) else {
+        return false;
+    };
+    writing.depth_or_stencil() || depth.testing || !stencil.disabled
+}
+
+fn is_zero(v: U32OrF32) -> bool {
+    match v {
+        U32OrF32::U32(v) => v == 0,
+        U32OrF32::F32(v) => v == 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{super::file::Pipeline, Diagnostic};
+
+    /// Path of a temporary file unique to the name
+    fn path_of(name: &str) -> String {
+        let file_name = format!("rend-vk-validate-{}-{}.json", std::process::id(), name);
+        let path = std::env::temp_dir().join(file_name);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn write(name: &str, description: &Value) -> String {
+        let path = path_of(name);
+        std::fs::write(&path, description.to_string()).unwrap();
+        path
+    }
+
+    /// Color targets, a "copy" program and a "fullscreen" shared state along the passes
+    fn description_of(targets: &[&str], passes: Value) -> Value {
+        let targets: Vec<_> = targets
+            .iter()
+            .map(|e| json!({ "name": e, "format": "R8G8B8A8_UNORM", "width": 1.0, "height": 1.0 }))
+            .collect();
+        json!({
+            "targets": targets,
+            "programs": [{ "name": "copy", "vertex": "fullscreen.vert", "fragment": "copy.frag" }],
+            "sharedState": { "fullscreen": state_of("COLOR") },
+            "passes": passes,
+        })
+    }
+
+    fn state_of(writing: &str) -> Value {
+        json!({
+            "writing": writing,
+            "depth": "DEFAULT",
+            "scissor": "DEFAULT",
+            "viewport": "DEFAULT",
+            "stencil": "NO",
+            "triangle": "DEFAULT",
+            "blending": "NO",
+            "clearing": "YES",
+        })
+    }
+
+    fn render_pass(name: &str, program: &str, state: &str, output: &str) -> Value {
+        json!({
+            "type": "render",
+            "name": name,
+            "program": program,
+            "batch": "FULLSCREEN",
+            "outputs": [output],
+            "inputs": [],
+            "perPassUpdaters": [],
+            "perInstanceUpdaters": [],
+            "state": { "type": "reference", "name": state },
+        })
+    }
+
+    fn blit_pass(name: &str, input: &str, output: &str) -> Value {
+        let rect = json!({ "x": 0, "y": 0, "width": 1.0, "height": 1.0 });
+        json!({
+            "type": "blit",
+            "name": name,
+            "input": input,
+            "inputRect": rect,
+            "output": output,
+            "outputRect": rect,
+            "filter": "LINEAR",
+            "attributes": ["COLOR"],
+        })
+    }
+
+    /// Single diagnostic with the message, failing if there isn't exactly one
+    fn find<'a>(diagnostics: &'a [Diagnostic], message: &str) -> &'a Diagnostic {
+        let found: Vec<_> = diagnostics
+            .iter()
+            .filter(|e| e.message.contains(message))
+            .collect();
+        match found[..] {
+            [diagnostic] => diagnostic,
+            _ => panic!(
+                "expected one '{}' diagnostic, got: {:?}",
+                message,
+                diagnostics
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+            ),
+        }
+    }
+
+    #[test]
+    fn reports_unknown_targets_and_programs() {
+        let passes = json!([
+            blit_pass("copy", "src", "nowhere"),
+            render_pass("shade", "missing", "fullscreen", "out"),
+        ]);
+        let path = write("unknown", &description_of(&["src", "out"], passes));
+        let diagnostics = Pipeline::validate(Some(&path));
+        let target = find(&diagnostics, "unknown target 'nowhere'");
+        assert_eq!(target.path, "$.passes[0].output");
+        assert_eq!(target.pass.as_deref(), Some("copy"));
+        let program = find(&diagnostics, "unknown program 'missing'");
+        assert_eq!(program.path, "$.passes[1].program");
+        assert_eq!(program.pass.as_deref(), Some("shade"));
+    }
+
+    #[test]
+    fn reports_unresolved_state_references() {
+        let passes = json!([render_pass("shade", "copy", "missing", "out")]);
+        let path = write("state", &description_of(&["out"], passes));
+        let diagnostics = Pipeline::validate(Some(&path));
+        let state = find(&diagnostics, "unresolved state reference 'missing'");
+        assert_eq!(state.path, "$.passes[0].state.name");
+        assert_eq!(state.pass.as_deref(), Some("shade"));
+    }
+
+    #[test]
+    fn reports_depth_passes_without_depth_stencil() {
+        let passes = json!([render_pass("shade", "copy", "depth", "out")]);
+        let mut description = description_of(&["out"], passes);
+        description["sharedState"]["depth"] = state_of("DEPTH");
+        let diagnostics = Pipeline::validate(Some(&write("depth", &description)));
+        let depth = find(&diagnostics, "has no 'depthStencil' target");
+        assert_eq!(depth.path, "$.passes[0].depthStencil");
+        assert_eq!(depth.pass.as_deref(), Some("shade"));
+    }
+
+    #[test]
+    fn reports_bad_sample_counts() {
+        let passes = json!([blit_pass("copy", "src", "multisampled")]);
+        let mut description = description_of(&["src", "multisampled"], passes);
+        description["targets"][1]["samples"] = json!(3);
+        let diagnostics = Pipeline::validate(Some(&write("samples", &description)));
+        let samples = find(&diagnostics, "3 is an invalid sample count");
+        assert_eq!(samples.path, "$.targets[1]");
+        assert_eq!(samples.pass, None);
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        let first = path_of("cycle_first");
+        let include = |name: &str| json!({ "type": "include", "name": name });
+        let second = write(
+            "cycle_second",
+            &description_of(&[], json!([include(&first)])),
+        );
+        let passes = json!([blit_pass("copy", "src", "out"), include(&second)]);
+        write("cycle_first", &description_of(&["src", "out"], passes));
+        let diagnostics = Pipeline::validate(Some(&first));
+        let cycle = find(&diagnostics, "include cycle");
+        assert_eq!(
+            cycle.message,
+            format!("include cycle: {} -> {} -> {}", first, second, first)
+        );
+        // Reported where the second file includes the first one again
+        assert_eq!(cycle.file, second);
+        assert_eq!(cycle.path, "$.passes[0]");
+        assert_eq!(cycle.pass, None);
+    }
+
+    #[test]
+    fn reports_writes_to_previous_frames() {
+        let passes = json!([
+            blit_pass("gbuffer", "src", "velocity"),
+            render_pass("smear", "copy", "fullscreen", "velocity@prev"),
+        ]);
+        let mut description = description_of(&["src", "velocity"], passes);
+        description["targets"][1]["history"] = json!(true);
+        let diagnostics = Pipeline::validate(Some(&write("history", &description)));
+        let history = find(
+            &diagnostics,
+            "previous frame of 'velocity' can only be read",
+        );
+        assert_eq!(history.path, "$.passes[1].outputs[0]");
+        assert_eq!(history.pass.as_deref(), Some("smear"));
+    }
+}
@@ -1,7 +1,9 @@
 use std::{collections::HashMap, hash::Hash};
 
+use ash::vk;
 use serde::{Deserialize, Serialize};
 
+use crate::error::{RendError, RendResult};
 use crate::shader_resource::{MultiResource, ResourceKind};
 use crate::UsedAsIndex;
 
@@ -41,6 +43,18 @@ impl TaskKind {
         Self::of_u64(v as u64)
     }
 
+    pub fn try_of_u32(v: u32) -> RendResult<Self> {
+        Self::try_of_u64(v as u64)
+    }
+
+    pub fn try_of_u64(v: u64) -> RendResult<Self> {
+        if v < 1 || v > (Self::MAX_VALUE as u64) {
+            Err(RendError::InvalidKind(format!("task kind {}", v)))
+        } else {
+            Ok(Self::of_u64(v))
+        }
+    }
+
     pub const fn of_u64(v: u64) -> Self {
         if v < 1 || v > (Self::MAX_VALUE as u64) {
             panic!("invalid task kind!")
@@ -74,4 +88,37 @@ pub struct RenderTask {
     pub vertex_count: u32,
     pub indices_offset: u32,
     pub resources: HashMap<ResourceKind, MultiResource>,
+    /// Only used by passes that clip per task
+    pub scissor: Option<TaskScissor>,
+}
+
+/// Rectangle the draws of a task get clipped to, in pixels of the pass outputs.
+#[derive(Copy, Clone, Serialize)]
+pub struct TaskScissor {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TaskScissor {
+    /// Clipped to the bounds, since scissors can't have negative offsets
+    pub fn to_vk_within(&self, bounds: vk::Rect2D) -> vk::Rect2D {
+        let start_x = (self.x as i64).max(bounds.offset.x as i64);
+        let start_y = (self.y as i64).max(bounds.offset.y as i64);
+        let end_x = (self.x as i64 + self.width as i64)
+            .min(bounds.offset.x as i64 + bounds.extent.width as i64);
+        let end_y = (self.y as i64 + self.height as i64)
+            .min(bounds.offset.y as i64 + bounds.extent.height as i64);
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: start_x as i32,
+                y: start_y as i32,
+            },
+            extent: vk::Extent2D {
+                width: (end_x - start_x).max(0) as u32,
+                height: (end_y - start_y).max(0) as u32,
+            },
+        }
+    }
 }
@@ -1,4 +1,3 @@
-use core::panic;
 use std::{
     alloc::Layout,
     collections::HashMap,
@@ -16,9 +15,10 @@ use ash::{
 use bitvec::vec::BitVec;
 
 use crate::{
-    buffer::{DeviceAllocator, DeviceSlice},
+    buffer::{DeviceAllocator, DeviceSlice, LinearAllocator},
     context::{self, ExtensionContext, VulkanContext},
     debug::{self, DebugContext},
+    error::{RendError, RendResult},
     format::Format,
     pipeline::{
         self,
@@ -72,42 +72,78 @@ pub struct AllocatorStats {
     pub chunks: u64,
 }
 
+/// Resources that can only be released once the GPU is done using them
+enum Deletion {
+    Mesh(u32, MeshBuffer),
+    Texture(Texture),
+}
+
+/// Resources owned by each of the frames in flight
+struct FrameSlot {
+    command_buffer: vk::CommandBuffer,
+    /// Signaled once the GPU is done with the frame submitted from this slot
+    finished_fence: vk::Fence,
+    image_acquired_semaphore: vk::Semaphore,
+    /// Per instance and per pass data uploaded while recording the frame
+    allocator: LinearAllocator,
+}
+
 pub struct Renderer {
     pub vulkan_context: Rc<context::VulkanContext>,
-    swapchain_context: Box<swapchain::SwapchainContext>,
+    /// Not present if the renderer is headless
+    swapchain_context: Option<Box<swapchain::SwapchainContext>>,
     debug_context: Option<Box<debug::DebugContext>>,
     pipeline: Box<Pipeline>,
     general_allocator: Box<DeviceAllocator>,
     mesh_buffers_by_id: HashMap<u32, MeshBuffer>,
     textures_by_id: HashMap<u32, Texture>,
+    /// How many times each texture descriptor slot was released, part of the texture ids
+    texture_generations: Vec<u16>,
     shader_resources_by_kind: HashMap<ResourceKind, SingleResource>,
     batches_by_task_type: HashMap<u64, Vec<RenderTask>>,
     mesh_buffer_ids: BitVec,
 
     optimal_transition_queue: Vec<u32>,
     ongoing_optimal_transitions: Vec<(u32, u64)>,
+    /// Deletions along the frame they were requested in
+    pending_deletions: Vec<(Deletion, u64)>,
 
     main_queue: vk::Queue,
 
     pool: vk::CommandPool,
-    draw_command_buffer: vk::CommandBuffer,
+    setup_command_buffer: vk::CommandBuffer,
+    frame_slots: Vec<FrameSlot>,
 
     rendering_complete_semaphore: vk::Semaphore,
     pass_timeline_semaphore: vk::Semaphore,
 
-    draw_commands_finished_fence: vk::Fence,
-
     current_frame: AtomicU64,
 }
 
 impl Renderer {
     pub const ID_TEST_TRIANGLE: u32 = 0;
 
+    pub const ID_DEFAULT_TEXTURE: u32 = 0;
+
+    /// Texture ids keep their descriptor slot in the low bits and the slot generation in the
+    /// high bits, so the id of a freed texture never addresses the one that reuses its slot.
+    /// Shaders mask the generation out before indexing the texture array.
+    pub const TEXTURE_SLOT_BITS: u32 = 16;
+
+    pub const TEXTURE_SLOT_MASK: u32 = (1 << Self::TEXTURE_SLOT_BITS) - 1;
+
     pub const MAX_MESH_IDS: u32 = 1024;
 
     pub fn destroy(&mut self) {
         log::trace!("destroying renderer...");
         unsafe { self.vulkan_context.device.device_wait_idle().unwrap() };
+        // Nothing is in flight anymore, release everything regardless of the frame
+        for (deletion, _) in std::mem::take(&mut self.pending_deletions) {
+            self.delete(deletion);
+        }
+        for texture in self.textures_by_id.values() {
+            texture.destroy(&self.vulkan_context.device);
+        }
         self.pipeline.destroy(&self.vulkan_context.device);
         self.general_allocator.destroy(&self.vulkan_context.device);
         unsafe {
@@ -115,11 +151,17 @@ impl Renderer {
             let destroy_fence = |s| self.vulkan_context.device.destroy_fence(s, None);
             destroy_semaphore(self.rendering_complete_semaphore);
             destroy_semaphore(self.pass_timeline_semaphore);
-            destroy_fence(self.draw_commands_finished_fence);
+            for slot in &self.frame_slots {
+                destroy_fence(slot.finished_fence);
+                destroy_semaphore(slot.image_acquired_semaphore);
+                slot.allocator.destroy(&self.vulkan_context.device);
+            }
             self.vulkan_context
                 .device
                 .destroy_command_pool(self.pool, None);
-            self.swapchain_context.destroy(&self.vulkan_context);
+            if let Some(swapchain_context) = &self.swapchain_context {
+                swapchain_context.destroy(&self.vulkan_context);
+            }
             self.vulkan_context.device.destroy_device(None);
         }
         // TODO: Read about Drop
@@ -131,28 +173,43 @@ impl Renderer {
         log::trace!("renderer destroyed!");
     }
 
-    pub fn add_task_to_queue(&mut self, task: RenderTask, parent_id: u32) {
+    pub fn add_task_to_queue(&mut self, task: RenderTask, parent_id: u32) -> RendResult<()> {
+        if !self.mesh_buffers_by_id.contains_key(&task.mesh_buffer_id) {
+            return Err(RendError::MissingMesh(task.mesh_buffer_id));
+        }
         let key = task.kind.to_key(parent_id);
+        let missing = self
+            .pipeline
+            .per_instance_updaters_of(key)
+            .find(|e| !task.resources.contains_key(e));
+        if let Some(kind) = missing {
+            return Err(RendError::InvalidArgument(format!(
+                "task of kind {} lacks the per instance resource {}",
+                task.kind.to_u32(),
+                kind
+            )));
+        }
         let tasks = self.batches_by_task_type.entry(key).or_default();
         tasks.push(task);
+        Ok(())
     }
 
     pub fn try_get_sampler(&self, key: SamplerKey) -> Option<u8> {
         self.pipeline.samplers_by_key.get(&key).map(|s| s.position)
     }
 
-    pub fn get_sampler(&mut self, key: SamplerKey) -> u8 {
+    pub fn get_sampler(&mut self, key: SamplerKey) -> RendResult<u8> {
         let id = self.try_get_sampler(key);
         if let Some(id) = id {
-            return id;
+            return Ok(id);
         }
         //  Sampler for this key not found, generate one
         let id = self.pipeline.samplers_by_key.len() as u32;
-        if id as u32 >= self.pipeline.sampler_descriptors.capacity {
-            panic!(
-                "can't allocate more samplers than {}!",
+        if id >= self.pipeline.sampler_descriptors.capacity {
+            return Err(RendError::OutOfIds(format!(
+                "sampler, max {}",
                 self.pipeline.sampler_descriptors.capacity
-            );
+            )));
         }
         let sampler = Sampler::of_key(&self.vulkan_context, key, id as u8);
         let samplers_by_key = &mut self.pipeline.samplers_by_key;
@@ -162,33 +219,29 @@ impl Renderer {
         // Write its descriptor into the GPU for later shader usage
         sampler_descriptors.place_sampler_at(&self.vulkan_context, id, sampler.sampler);
         // Return the ID for referencing on the client side
-        id as u8
+        Ok(id as u8)
     }
 
     pub fn fetch_mesh(&self, id: u32) -> Option<&MeshBuffer> {
         self.mesh_buffers_by_id.get(&id)
     }
 
-    pub fn fetch_mesh_or_fail(&self, id: u32) -> &MeshBuffer {
-        self.fetch_mesh(id)
-            .unwrap_or_else(|| panic!("couldn't find mesh with id {}", id))
+    pub fn fetch_mesh_or_fail(&self, id: u32) -> RendResult<&MeshBuffer> {
+        self.fetch_mesh(id).ok_or(RendError::MissingMesh(id))
     }
 
-    pub fn free_mesh(&mut self, id: u32) {
+    /// Mesh buffers and id are released once the frames that could be using them finish.
+    /// Tasks queued for the mesh that weren't rendered yet get dropped.
+    pub fn free_mesh(&mut self, id: u32) -> RendResult<()> {
         let mesh = self
             .mesh_buffers_by_id
             .remove(&id)
-            .unwrap_or_else(|| panic!("couldn't find mesh with id {}", id));
-        let free_if_not_empty = |v: &DeviceSlice| {
-            if v.size > 0 {
-                self.general_allocator.free(*v);
-            }
-        };
-        free_if_not_empty(&mesh.vertices);
-        free_if_not_empty(&mesh.normals);
-        free_if_not_empty(&mesh.tex_coords);
-        free_if_not_empty(&mesh.indices);
-        self.mesh_buffer_ids.set(id as usize, false);
+            .ok_or(RendError::MissingMesh(id))?;
+        for batch in self.batches_by_task_type.values_mut() {
+            batch.retain(|e| e.mesh_buffer_id != id);
+        }
+        self.queue_deletion(Deletion::Mesh(id, mesh));
+        Ok(())
     }
 
     pub fn gen_mesh(
@@ -198,28 +251,43 @@ impl Renderer {
         tex_coords_size: u32,
         indices_size: u32,
         count: u32,
-    ) -> u32 {
-        let alloc_or_empty = |size: u32, purpose: &str| {
-            if size > 0 {
-                self.general_allocator
-                    .alloc(size as u64)
-                    .unwrap_or_else(|| {
-                        panic!("couldnt allocate '{}' buffer of size {}", purpose, size)
-                    })
-            } else {
-                DeviceSlice::empty()
-            }
-        };
-
-        let vertices = alloc_or_empty(vertices_size, "vertex");
-        let normals = alloc_or_empty(normals_size, "normal");
-        let tex_coords = alloc_or_empty(tex_coords_size, "tex_coord");
-        let indices = alloc_or_empty(indices_size, "index");
+    ) -> RendResult<u32> {
         // Reserve mesh id
         let mesh_id = self
             .mesh_buffer_ids
             .first_zero()
-            .expect("ran out of mesh ids!") as u32;
+            .ok_or_else(|| RendError::OutOfIds(format!("mesh, max {}", Self::MAX_MESH_IDS)))?
+            as u32;
+
+        let sizes = [
+            (vertices_size, "vertex"),
+            (normals_size, "normal"),
+            (tex_coords_size, "tex_coord"),
+            (indices_size, "index"),
+        ];
+        let mut slices = Vec::with_capacity(sizes.len());
+        for (size, purpose) in sizes {
+            if size == 0 {
+                slices.push(DeviceSlice::empty());
+                continue;
+            }
+            match self.general_allocator.alloc(size as u64) {
+                Some(slice) => slices.push(slice),
+                None => {
+                    // Don't leak whatever was allocated before failing
+                    for slice in slices.iter().filter(|e| e.size > 0) {
+                        self.general_allocator.free(*slice);
+                    }
+                    return Err(RendError::OutOfMemory(format!(
+                        "couldn't allocate '{}' buffer of size {}",
+                        purpose, size
+                    )));
+                }
+            }
+        }
+        let [vertices, normals, tex_coords, indices] = slices[..] else {
+            unreachable!()
+        };
 
         self.mesh_buffer_ids.set(mesh_id as usize, true);
 
@@ -234,16 +302,30 @@ impl Renderer {
             },
         );
 
-        mesh_id
+        Ok(mesh_id)
     }
 
     pub fn fetch_texture(&self, id: u32) -> Option<&Texture> {
         self.textures_by_id.get(&id)
     }
 
-    pub fn fetch_texture_or_fail(&self, id: u32) -> &Texture {
+    pub fn fetch_texture_or_fail(&self, id: u32) -> RendResult<&Texture> {
         self.fetch_texture(id)
-            .unwrap_or_else(|| panic!("couldn't find texture with id {}", id))
+            .ok_or_else(|| self.missing_texture(id))
+    }
+
+    fn missing_texture(&self, id: u32) -> RendError {
+        if self.is_texture_pending_deletion(id) {
+            RendError::FreedTexture(id)
+        } else {
+            RendError::MissingTexture(id)
+        }
+    }
+
+    fn is_texture_pending_deletion(&self, id: u32) -> bool {
+        self.pending_deletions
+            .iter()
+            .any(|e| matches!(&e.0, Deletion::Texture(t) if t.id == id))
     }
 
     pub fn gen_texture(
@@ -253,20 +335,69 @@ impl Renderer {
         kind: TextureKind,
         mip_maps: &[MipMap],
         staging_size: u32,
-    ) -> u32 {
-        // Reserve texture id
-        let texture_id = self.pipeline.image_descriptors.next_free() as u32;
+    ) -> RendResult<u32> {
+        if mip_maps.is_empty() {
+            return Err(RendError::InvalidArgument(format!(
+                "texture {} has no mip maps",
+                name
+            )));
+        }
+        let base = &mip_maps[0];
+        let problem = match kind {
+            _ if base.width == 0 || base.height == 0 || base.depth == 0 || base.layers == 0 => {
+                Some("can't have empty dimensions")
+            }
+            TextureKind::T1D if base.height > 1 || base.depth > 1 => {
+                Some("1D textures can't have height or depth")
+            }
+            TextureKind::T2D if base.depth > 1 => Some("2D textures can't have depth"),
+            TextureKind::T3D if base.layers > 1 => Some("3D textures can't have layers"),
+            TextureKind::CUBEMAP if !base.layers.is_multiple_of(6) || base.width != base.height => {
+                Some("cube maps need square faces and six layers per cube")
+            }
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            return Err(RendError::InvalidArgument(format!(
+                "texture {} of kind {}: {}",
+                name, kind, problem
+            )));
+        }
+        // Reserve texture slot, the id also carries its generation
+        let texture_slot = self
+            .pipeline
+            .image_descriptors
+            .try_next_free()
+            .ok_or_else(|| {
+                RendError::OutOfIds(format!(
+                    "texture, max {}",
+                    self.pipeline.image_descriptors.capacity
+                ))
+            })?;
+        let texture_id = ((self.texture_generations[texture_slot] as u32)
+            << Self::TEXTURE_SLOT_BITS)
+            | texture_slot as u32;
+        let texture = crate::texture::make(
+            &self.vulkan_context,
+            name,
+            base.extent_3d(),
+            mip_maps.len() as u8,
+            base.layers / kind.layer_count(),
+            format,
+            kind,
+            false,
+        )?;
         let staging = if staging_size > 0 {
-            Some(Box::new(
-                self.general_allocator
-                    .alloc(staging_size as u64)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "can't allocate staging buffer of size {} for {}",
-                            name, staging_size
-                        )
-                    }),
-            ))
+            match self.general_allocator.alloc(staging_size as u64) {
+                Some(staging) => Some(Box::new(staging)),
+                None => {
+                    texture.destroy(&self.vulkan_context.device);
+                    return Err(RendError::OutOfMemory(format!(
+                        "can't allocate staging buffer of size {} for {}",
+                        staging_size, texture.name
+                    )));
+                }
+            }
         } else {
             None
         };
@@ -274,42 +405,92 @@ impl Renderer {
             id: texture_id,
             mip_maps: mip_maps.into(),
             staging,
-            ..crate::texture::make(
-                &self.vulkan_context,
-                name,
-                mip_maps[0].width,
-                mip_maps[0].height,
-                mip_maps.len() as u8,
-                format,
-                kind,
-                false,
-            )
+            ..texture
         };
         // Generate descriptor and place it in the image descriptor array buffer
         self.pipeline.image_descriptors.place_image_at(
             &self.vulkan_context,
-            texture_id,
+            texture_slot as u32,
             texture.view,
             vk::ImageLayout::READ_ONLY_OPTIMAL,
         );
         self.textures_by_id.insert(texture_id, texture);
-        texture_id
+        Ok(texture_id)
     }
 
-    pub fn queue_texture_for_uploading(&mut self, id: u32) {
+    /// Texture resources and id are released once the frames that could be using them finish.
+    pub fn free_texture(&mut self, id: u32) -> RendResult<()> {
+        if id == Self::ID_DEFAULT_TEXTURE {
+            return Err(RendError::InvalidArgument(format!(
+                "default texture with id {} can't be freed",
+                id
+            )));
+        }
+        let texture = match self.textures_by_id.remove(&id) {
+            Some(texture) => texture,
+            None => return Err(self.missing_texture(id)),
+        };
+        // Texture might not have been uploaded yet
+        self.optimal_transition_queue.retain(|e| *e != id);
+        self.ongoing_optimal_transitions.retain(|e| e.0 != id);
+        self.queue_deletion(Deletion::Texture(texture));
+        Ok(())
+    }
+
+    fn queue_deletion(&mut self, deletion: Deletion) {
+        let current_frame = self.get_current_frame();
+        self.pending_deletions.push((deletion, current_frame));
+    }
+
+    fn delete(&mut self, deletion: Deletion) {
+        match deletion {
+            Deletion::Mesh(id, mesh) => {
+                let free_if_not_empty = |v: &DeviceSlice| {
+                    if v.size > 0 {
+                        self.general_allocator.free(*v);
+                    }
+                };
+                free_if_not_empty(&mesh.vertices);
+                free_if_not_empty(&mesh.normals);
+                free_if_not_empty(&mesh.tex_coords);
+                free_if_not_empty(&mesh.indices);
+                self.mesh_buffer_ids.set(id as usize, false);
+            }
+            Deletion::Texture(texture) => {
+                if let Some(staging) = &texture.staging {
+                    self.general_allocator.free(*staging.as_ref());
+                }
+                let slot = texture.id & Self::TEXTURE_SLOT_MASK;
+                // Don't leave the slot pointing to a destroyed view while it's unused
+                if let Some(placeholder) = self.textures_by_id.get(&Self::ID_DEFAULT_TEXTURE) {
+                    self.pipeline.image_descriptors.place_image_at(
+                        &self.vulkan_context,
+                        slot,
+                        placeholder.view,
+                        vk::ImageLayout::READ_ONLY_OPTIMAL,
+                    );
+                }
+                texture.destroy(&self.vulkan_context.device);
+                // Slot can be reused by new textures from now on, under a new id
+                self.pipeline.image_descriptors.remove_at(slot);
+                let generation = &mut self.texture_generations[slot as usize];
+                *generation = generation.wrapping_add(1);
+            }
+        }
+    }
+
+    pub fn queue_texture_for_uploading(&mut self, id: u32) -> RendResult<()> {
         if !self.textures_by_id.contains_key(&id) {
-            panic!("missing texture with id {}", id);
+            return Err(self.missing_texture(id));
         }
         self.optimal_transition_queue.push(id);
+        Ok(())
     }
 
-    pub fn is_texture_uploaded(&self, id: u32) -> bool {
-        let texture = self
-            .textures_by_id
-            .get(&id)
-            .unwrap_or_else(|| panic!("missing texture with id {}", id));
+    pub fn is_texture_uploaded(&self, id: u32) -> RendResult<bool> {
+        let texture = self.fetch_texture_or_fail(id)?;
         // If it no longer has staging memory, then it's uploaded
-        texture.staging.is_none()
+        Ok(texture.staging.is_none())
     }
 
     pub fn place_shader_resource(&mut self, kind: ResourceKind, item: SingleResource) {
@@ -330,7 +511,7 @@ impl Renderer {
         }
     }
 
-    pub fn render(&mut self) {
+    pub fn render(&mut self) -> RendResult<()> {
         // TODO: Trace feature to write RenderTask to files
         // if log::log_enabled!(log::Level::Trace) {
         //     let base_path = std::path::Path::new("log");
@@ -351,36 +532,258 @@ impl Renderer {
         //     writer.flush().unwrap();
         // }
 
-        let acquired = self.swapchain_context.acquire_next();
+        // Stages can't fail midway recording, check what they need beforehand
+        let missing = self
+            .pipeline
+            .missing_pass_resource(&self.batches_by_task_type, &self.shader_resources_by_kind);
+        if let Some(kind) = missing {
+            for batch in &mut self.batches_by_task_type.values_mut() {
+                batch.clear();
+            }
+            return Err(RendError::InvalidArgument(format!(
+                "per pass resource {} wasn't placed",
+                kind
+            )));
+        }
 
-        self.setup_frame();
+        let frame_slot_index = self.current_frame_slot();
+        let FrameSlot {
+            finished_fence,
+            image_acquired_semaphore,
+            ..
+        } = self.frame_slots[frame_slot_index];
+        // Wait until the GPU is done with the last frame submitted from this slot
+        self.vulkan_context.wait_fence(finished_fence);
+        // Which means the transient data it uploaded can be discarded
+        self.frame_slots[frame_slot_index].allocator.reset();
+
+        let acquired = match self.swapchain_context.as_mut() {
+            Some(swapchain) => match swapchain.acquire_next(image_acquired_semaphore)? {
+                Some(acquired) => Some(acquired),
+                None => {
+                    // Swapchain is out of date, re-create it and drop this frame
+                    let extent = swapchain.surface_extent;
+                    self.resize(extent.width, extent.height)?;
+                    for batch in &mut self.batches_by_task_type.values_mut() {
+                        batch.clear();
+                    }
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
 
-        self.vulkan_context
-            .wait_and_reset_fence(self.draw_commands_finished_fence);
+        self.setup_frame();
 
-        self.record_and_submit_draw_commands(
-            &acquired.attachment,
-            &[acquired.acquire_semaphore],
-            &[acquired.render_semaphore],
-            self.draw_commands_finished_fence,
-        );
+        self.vulkan_context.reset_fence(finished_fence);
+
+        match &acquired {
+            Some(acquired) => self.record_and_submit_draw_commands(
+                frame_slot_index,
+                &acquired.attachment,
+                &[acquired.acquire_semaphore],
+                &[acquired.render_semaphore],
+            )?,
+            None => {
+                // Headless, render into the pipeline owned default attachment
+                let default_attachment = self.pipeline.default_attachment().clone();
+                self.record_and_submit_draw_commands(
+                    frame_slot_index,
+                    &default_attachment,
+                    &[],
+                    &[],
+                )?
+            }
+        }
 
-        self.swapchain_context.present(
-            acquired.index,
-            self.main_queue,
-            &[acquired.render_semaphore],
-        );
+        if let (Some(swapchain), Some(acquired)) = (&self.swapchain_context, &acquired) {
+            let is_outdated = swapchain.present(
+                acquired.index,
+                self.main_queue,
+                &[acquired.render_semaphore],
+            )?;
+            if is_outdated {
+                let extent = swapchain.surface_extent;
+                self.resize(extent.width, extent.height)?;
+            }
+        }
 
         // Clear batch queues for next frame
         for batch in &mut self.batches_by_task_type.values_mut() {
             batch.clear();
         }
         // Signal current frame and increment ID for next frame
-        self.signal_frame();
+        self.signal_frame()
+    }
+
+    /// Re-creates the swapchain for the new window size, along with every pipeline
+    /// attachment whose size is relative to it. For headless renderers, only the
+    /// pipeline attachments are re-created.
+    pub fn resize(&mut self, width: u32, height: u32) -> RendResult<()> {
+        if width == 0 || height == 0 {
+            // Minimized window, nothing to do until it gets restored
+            return Ok(());
+        }
+        log::trace!("resizing renderer to {}x{}...", width, height);
+        unsafe {
+            self.vulkan_context
+                .device
+                .device_wait_idle()
+                .map_err(|e| RendError::vulkan(e, "waiting for the device to be idle"))?
+        };
+        let default_attachment = match self.swapchain_context.as_mut() {
+            Some(swapchain) => {
+                if !swapchain.recreate(&self.vulkan_context, width, height)? {
+                    // Surface has no area, keep everything until it gets restored
+                    return Ok(());
+                }
+                Some(swapchain.attachments[0].clone())
+            }
+            None => None,
+        };
+        let external_extent = default_attachment
+            .as_ref()
+            .map_or(Extent2D { width, height }, |e| e.extent);
+        let internal_extent = self.pipeline.internal_extent;
+        self.pipeline.resize(
+            &self.vulkan_context,
+            internal_extent,
+            external_extent,
+            default_attachment,
+        )?;
+        self.issue_initial_barriers();
+        log::trace!("renderer resized!");
+        Ok(())
+    }
+
+    /// Changes the internal extent the pipeline renders at, re-creating only the attachments
+    /// sized relative to it. The swapchain and absolute sized attachments are left as they are.
+    pub fn set_render_extent(&mut self, width: u32, height: u32) -> RendResult<()> {
+        if width == 0 || height == 0 {
+            return Err(RendError::InvalidArgument(format!(
+                "render extent {}x{} can't be empty",
+                width, height
+            )));
+        }
+        let internal_extent = Extent2D { width, height };
+        if self.pipeline.internal_extent == internal_extent {
+            return Ok(());
+        }
+        log::trace!("setting render extent to {}x{}...", width, height);
+        unsafe {
+            self.vulkan_context
+                .device
+                .device_wait_idle()
+                .map_err(|e| RendError::vulkan(e, "waiting for the device to be idle"))?
+        };
+        // Default attachment doesn't depends on the internal extent, it's kept as is
+        let external_extent = self.pipeline.external_extent;
+        self.pipeline
+            .resize(&self.vulkan_context, internal_extent, external_extent, None)?;
+        self.issue_initial_barriers();
+        log::trace!("render extent set!");
+        Ok(())
+    }
+
+    /// Re-reads the pipeline file and its includes, recompiling the shaders and rebuilding the
+    /// stages and targets. Textures and samplers handed out so far remain valid. If the pipeline
+    /// can't be read or compiled, the current one is kept and the error returned.
+    pub fn reload_pipeline(&mut self) -> RendResult<()> {
+        log::trace!("reloading pipeline...");
+        self.pipeline.reload(&self.vulkan_context)?;
+        self.issue_initial_barriers();
+        log::trace!("pipeline reloaded!");
+        Ok(())
+    }
+
+    fn issue_initial_barriers(&mut self) {
+        self.submit_and_wait(|r, c| {
+            let barriers = r.pipeline.gen_initial_barriers();
+            let barrier_dep_info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+            unsafe {
+                r.vulkan_context
+                    .device
+                    .cmd_pipeline_barrier2(c, &barrier_dep_info);
+            }
+        });
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frame_slots.len() as u32
+    }
+
+    fn current_frame_slot(&self) -> usize {
+        (self.get_current_frame() % self.frame_slots.len() as u64) as usize
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.swapchain_context.is_none()
+    }
+
+    /// Reads back the contents of the default attachment as of the last rendered frame.
+    /// Only available for headless renderers, since there is no swapchain image to read from.
+    pub fn read_default_attachment(&mut self) -> RendResult<Vec<u8>> {
+        if !self.is_headless() {
+            return Err(RendError::InvalidArgument(
+                "default attachment can only be read from headless renderers".to_string(),
+            ));
+        }
+        if self.get_current_frame() < 2 {
+            return Err(RendError::InvalidArgument(
+                "no frame has been rendered yet".to_string(),
+            ));
+        }
+        let attachment = self.pipeline.default_attachment().clone();
+        let texel_size = attachment.format.texel_size().ok_or_else(|| {
+            RendError::InvalidArgument(format!(
+                "default attachment format {} can't be read back",
+                attachment.format
+            ))
+        })?;
+        let size = texel_size * attachment.extent.width * attachment.extent.height;
+        let staging = self.general_allocator.alloc(size as u64).ok_or_else(|| {
+            RendError::OutOfMemory(format!("can't allocate readback buffer of size {}", size))
+        })?;
+        self.submit_and_wait(|r, c| {
+            // Last frame left it as a transfer source, make its writes visible
+            let barriers = [vk::ImageMemoryBarrier2::default()
+                .image(attachment.image)
+                .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .subresource_range(Attachment::color_subresource_range())];
+            let regions = [vk::BufferImageCopy::default()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(attachment.format.aspect())
+                        .layer_count(1),
+                )
+                .image_extent(attachment.extent.into())
+                .buffer_offset(staging.offset)];
+            let barrier_dep_info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
+            unsafe {
+                r.vulkan_context
+                    .device
+                    .cmd_pipeline_barrier2(c, &barrier_dep_info);
+                r.vulkan_context.device.cmd_copy_image_to_buffer(
+                    c,
+                    attachment.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    staging.buffer,
+                    &regions,
+                );
+            }
+        });
+        let pixels = staging.read()[..size as usize].to_vec();
+        self.general_allocator.free(staging);
+        Ok(pixels)
     }
 
     fn setup_frame(&mut self) {
-        if self.ongoing_optimal_transitions.is_empty() {
+        if self.ongoing_optimal_transitions.is_empty() && self.pending_deletions.is_empty() {
             return;
         }
         // Process any queued texture transitions
@@ -394,28 +797,33 @@ impl Renderer {
             if e.1 >= current_timeline_counter {
                 return true;
             }
-            let texture = &mut self.textures_by_id.get_mut(&e.0).unwrap();
-            // Free the staging buffer after it has been used
-            match &texture.staging {
-                Some(staging) => {
-                    let device = *staging.as_ref();
-                    self.general_allocator.free(device);
-                }
-                _ => panic!(
-                    "staging buffer for texture {} {} is missing!",
-                    texture.id, texture.name
-                ),
+            // Free the staging buffer after it has been used, taking it marks the texture as
+            // "uploaded". Freeing a texture drops its transitions so it should be there.
+            let staging = self
+                .textures_by_id
+                .get_mut(&e.0)
+                .and_then(|texture| texture.staging.take());
+            match staging {
+                Some(staging) => self.general_allocator.free(*staging),
+                None => log::warn!("staging buffer for texture {} is missing!", e.0),
             }
-            // Set staging to None to mark the texture as "uploaded"
-            texture.staging = None;
             // No longer retain the transition, already uploaded
             false
         });
+        // Process any deletions the GPU is done with
+        let (finished, pending) = std::mem::take(&mut self.pending_deletions)
+            .into_iter()
+            .partition(|e| e.1 < current_timeline_counter);
+        self.pending_deletions = pending;
+        for (deletion, _) in finished {
+            self.delete(deletion);
+        }
     }
 
     fn process_pipeline(
         &mut self,
         command_buffer: vk::CommandBuffer,
+        frame_slot: usize,
         default_attachment: &Attachment,
     ) {
         let current_frame = self.get_current_frame();
@@ -426,7 +834,7 @@ impl Renderer {
             .try_begin_debug_label(command_buffer, "issue_queued_transitions");
         for texture_id in self.optimal_transition_queue.drain(..) {
             let texture = &self.textures_by_id[&texture_id];
-            texture.transition_to_optimal(&self.vulkan_context, self.draw_command_buffer);
+            texture.transition_to_optimal(&self.vulkan_context, command_buffer);
             self.ongoing_optimal_transitions
                 .push((texture_id, current_frame))
         }
@@ -439,13 +847,20 @@ impl Renderer {
             shader_resources_by_kind: &self.shader_resources_by_kind,
             sampler_descriptors: &sampler_descriptors,
             image_descriptors: &image_descriptors,
-            buffer_allocator: &self.general_allocator,
+            frame_allocator: &self.frame_slots[frame_slot].allocator,
             command_buffer,
+            frame_slot,
             default_attachment,
+            default_attachment_final_layout: if self.is_headless() {
+                // Leave it ready for reading back
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+            } else {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            },
         });
     }
 
-    fn signal_frame(&self) {
+    fn signal_frame(&self) -> RendResult<()> {
         let frame_index = self.current_frame.fetch_add(1, Ordering::Relaxed);
         let pass_semaphore_signal_info = [vk::SemaphoreSubmitInfo::default()
             .semaphore(self.pass_timeline_semaphore)
@@ -457,45 +872,54 @@ impl Renderer {
             self.vulkan_context
                 .device
                 .queue_submit2(self.main_queue, &signal_submit_infos, vk::Fence::null())
-                .unwrap()
-        };
+                .map_err(|e| RendError::vulkan(e, "signaling the frame"))
+        }
     }
 
     /// Main draw command recording and submission logic
     fn record_and_submit_draw_commands(
         &mut self,
+        frame_slot_index: usize,
         dest_attachment: &Attachment,
         to_wait_sem: &[vk::Semaphore],
         to_signal_sem: &[vk::Semaphore],
-        to_signal_fen: vk::Fence,
-    ) {
+    ) -> RendResult<()> {
+        let FrameSlot {
+            command_buffer,
+            finished_fence,
+            ..
+        } = self.frame_slots[frame_slot_index];
         unsafe {
             self.vulkan_context
                 .device
                 .reset_command_buffer(
-                    self.draw_command_buffer,
+                    command_buffer,
                     vk::CommandBufferResetFlags::RELEASE_RESOURCES,
                 )
-                .expect("reset command buffer failed!");
+                .map_err(|e| RendError::vulkan(e, "resetting the command buffer"))?;
 
             let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
                 .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
             self.vulkan_context
                 .device
-                .begin_command_buffer(self.draw_command_buffer, &command_buffer_begin_info)
-                .expect("begin commandbuffer failed!");
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+                .map_err(|e| RendError::vulkan(e, "beginning the command buffer"))?;
 
-            self.process_pipeline(self.draw_command_buffer, &dest_attachment);
+            self.process_pipeline(command_buffer, frame_slot_index, &dest_attachment);
 
             self.vulkan_context
                 .device
-                .end_command_buffer(self.draw_command_buffer)
-                .expect("end command buffer failed!");
+                .end_command_buffer(command_buffer)
+                .map_err(|e| RendError::vulkan(e, "ending the command buffer"))?;
 
-            let command_buffers = [self.draw_command_buffer];
+            let command_buffers = [command_buffer];
 
-            let wait_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            // Headless rendering has no swapchain image to wait on
+            let wait_mask: Vec<_> = to_wait_sem
+                .iter()
+                .map(|_| vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .collect();
 
             let submit_info = vk::SubmitInfo::default()
                 .wait_semaphores(&to_wait_sem)
@@ -507,8 +931,8 @@ impl Renderer {
 
             self.vulkan_context
                 .device
-                .queue_submit(self.main_queue, &submit_infos, to_signal_fen)
-                .expect("queue submit failed!");
+                .queue_submit(self.main_queue, &submit_infos, finished_fence)
+                .map_err(|e| RendError::vulkan(e, "submitting the draw commands"))
         }
     }
 
@@ -520,7 +944,7 @@ impl Renderer {
     where
         F: Fn(&mut Renderer, vk::CommandBuffer),
     {
-        let cmd_buffer = self.draw_command_buffer;
+        let cmd_buffer = self.setup_command_buffer;
         unsafe {
             self.vulkan_context
                 .device
@@ -554,16 +978,7 @@ impl Renderer {
 
             self.vulkan_context
                 .device
-                .reset_fences(&[self.draw_commands_finished_fence])
-                .expect("fence reset failed!");
-
-            self.vulkan_context
-                .device
-                .queue_submit(
-                    self.main_queue,
-                    &submit_infos,
-                    self.draw_commands_finished_fence,
-                )
+                .queue_submit(self.main_queue, &submit_infos, vk::Fence::null())
                 .expect("queue submit failed!");
 
             self.vulkan_context
@@ -574,19 +989,78 @@ impl Renderer {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn make_renderer<F>(
     render_width: u32,
     render_height: u32,
+    frames_in_flight: u32,
     is_vsync_enabled: bool,
     is_debug_enabled: bool,
     is_validation_layer_enabled: bool,
     instance_extensions: &[*const i8],
     create_surface: F,
-) -> Renderer
+) -> RendResult<Renderer>
+where
+    F: FnOnce(&ash::Entry, &ash::Instance, *mut vk::SurfaceKHR) -> vk::Result,
+{
+    make_renderer_of(
+        render_width,
+        render_height,
+        frames_in_flight,
+        is_vsync_enabled,
+        is_debug_enabled,
+        is_validation_layer_enabled,
+        instance_extensions,
+        Some(create_surface),
+    )
+}
+
+/// Makes a renderer without a window surface nor a swapchain. The final pass renders
+/// into a pipeline owned image that can be read back with [Renderer::read_default_attachment].
+pub fn make_headless_renderer(
+    render_width: u32,
+    render_height: u32,
+    frames_in_flight: u32,
+    is_debug_enabled: bool,
+    is_validation_layer_enabled: bool,
+) -> RendResult<Renderer> {
+    make_renderer_of::<fn(&ash::Entry, &ash::Instance, *mut vk::SurfaceKHR) -> vk::Result>(
+        render_width,
+        render_height,
+        frames_in_flight,
+        false,
+        is_debug_enabled,
+        is_validation_layer_enabled,
+        &[],
+        None,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn make_renderer_of<F>(
+    render_width: u32,
+    render_height: u32,
+    frames_in_flight: u32,
+    is_vsync_enabled: bool,
+    is_debug_enabled: bool,
+    is_validation_layer_enabled: bool,
+    instance_extensions: &[*const i8],
+    create_surface: Option<F>,
+) -> RendResult<Renderer>
 where
     F: FnOnce(&ash::Entry, &ash::Instance, *mut vk::SurfaceKHR) -> vk::Result,
 {
     log::trace!("entering make_renderer");
+    if frames_in_flight == 0 {
+        return Err(RendError::InvalidArgument(
+            "frames in flight can't be 0".to_string(),
+        ));
+    }
+    /*
+     * Whatever was created before an error is returned is leaked, there is no
+     * sensible way to recover from a half initialized renderer other than to
+     * report it and bail out.
+     */
 
     log::trace!("creating entry...");
     let entry = Entry::linked();
@@ -597,23 +1071,35 @@ where
         instance_extensions,
         is_debug_enabled,
         is_validation_layer_enabled,
-    );
+    )?;
     log::trace!("instance created!");
 
-    log::trace!("creating surface...");
-    let surface_layout = Layout::new::<vk::SurfaceKHR>();
-    let surface = unsafe { std::alloc::alloc(surface_layout) as *mut vk::SurfaceKHR };
-    let create_surface_result = create_surface(&entry, &instance, surface);
-    if create_surface_result != vk::Result::SUCCESS {
-        panic!("error creating surface: {}", create_surface_result);
+    let surface = match create_surface {
+        Some(create_surface) => {
+            log::trace!("creating surface...");
+            let surface_layout = Layout::new::<vk::SurfaceKHR>();
+            let surface = unsafe { std::alloc::alloc(surface_layout) as *mut vk::SurfaceKHR };
+            let create_surface_result = create_surface(&entry, &instance, surface);
+            if create_surface_result != vk::Result::SUCCESS {
+                return Err(RendError::vulkan(
+                    create_surface_result,
+                    "creating the surface",
+                ));
+            }
+            let surface = unsafe { *surface };
+            log::trace!("surface created!");
+            Some(surface)
+        }
+        None => None,
+    };
+    if surface.is_none() {
+        log::trace!("no surface, renderer will be headless");
     }
-    let surface = unsafe { *surface };
-    log::trace!("surface created!");
     let surface_extension = khr::surface::Instance::new(&entry, &instance);
     // let make_surface = func: unsafe extern "C" fn(u64, *mut c_void),
     log::trace!("selecting physical device...");
     let (physical_device, name, queue_family_index) =
-        select_physical_device(&instance, &surface_extension, surface);
+        select_physical_device(&instance, &surface_extension, surface)?;
     log::trace!("physical device {name} with queue index {queue_family_index} selected!");
     log::trace!("creating device...");
     let device = make_device(
@@ -621,7 +1107,8 @@ where
         physical_device,
         queue_family_index,
         is_debug_enabled,
-    );
+        surface.is_none(),
+    )?;
     log::trace!("device created!");
 
     let (debug_context, debug_utils_ext) = if is_debug_enabled {
@@ -669,29 +1156,40 @@ where
     let command_pool = unsafe {
         ctx.device
             .create_command_pool(&pool_create_info, None)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "creating the command pool"))?
     };
     ctx.try_set_debug_name("main_command_pool", command_pool);
 
     let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
-        .command_buffer_count(2)
+        .command_buffer_count(1 + frames_in_flight)
         .command_pool(command_pool)
         .level(vk::CommandBufferLevel::PRIMARY);
 
     let command_buffers = unsafe {
         ctx.device
             .allocate_command_buffers(&command_buffer_allocate_info)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "allocating the command buffers"))?
     };
     let setup_command_buffer = command_buffers[0];
-    let draw_command_buffer = command_buffers[1];
     ctx.try_set_debug_name("setup_command_buffer", setup_command_buffer);
-    ctx.try_set_debug_name("draw_command_buffer", draw_command_buffer);
     log::trace!("command buffers created!");
 
-    log::trace!("creating fences...");
-    let draw_commands_finished_fence = ctx.create_fence("draw_commands_finished_fence");
-    log::trace!("fences created!");
+    log::trace!("creating frame slots...");
+    let frame_slots: Vec<_> = command_buffers[1..]
+        .iter()
+        .enumerate()
+        .map(|(i, &command_buffer)| {
+            ctx.try_set_debug_name(&format!("draw_command_buffer_{}", i), command_buffer);
+            FrameSlot {
+                command_buffer,
+                finished_fence: ctx.create_fence(&format!("draw_commands_finished_fence_{}", i)),
+                image_acquired_semaphore: ctx
+                    .create_semaphore(&format!("swapchain_image_aquired_semaphore_{}", i)),
+                allocator: LinearAllocator::new(ctx.clone()),
+            }
+        })
+        .collect();
+    log::trace!("frame slots created!");
 
     log::trace!("creating semaphores...");
     let rendering_complete_semaphore = ctx.create_semaphore("rendering_complete_semaphore");
@@ -702,22 +1200,37 @@ where
     let general_allocator = DeviceAllocator::new_general(ctx.clone());
     log::trace!("allocators created!");
 
-    log::trace!("creating swapchain...");
-    let swapchain_context = swapchain::SwapchainContext::make(&ctx, surface, is_vsync_enabled);
-    log::trace!("swapchain created!");
+    let render_extent = Extent2D {
+        width: render_width,
+        height: render_height,
+    };
+    let swapchain_context = surface
+        .map(|surface| {
+            log::trace!("creating swapchain...");
+            let swapchain_context = swapchain::SwapchainContext::make(
+                &ctx,
+                surface,
+                is_vsync_enabled,
+                render_width,
+                render_height,
+            )?;
+            log::trace!("swapchain created!");
+            Ok(swapchain_context)
+        })
+        .transpose()?;
 
     log::trace!("creating pipeline...");
     let pip = pipeline::file::Pipeline::load(
         &ctx,
-        Extent2D {
-            width: render_width,
-            height: render_height,
-        },
-        swapchain_context.attachments[0].extent,
-        swapchain_context.attachments[0].clone(),
+        render_extent,
+        // Headless renderers have no desktop, external and internal extents are the same
+        swapchain_context
+            .as_ref()
+            .map_or(render_extent, |s| s.attachments[0].extent),
+        swapchain_context.as_ref().map(|s| s.attachments[0].clone()),
         is_validation_layer_enabled,
         Some("pipeline.json"),
-    );
+    )?;
     log::trace!("pipeline created!");
 
     log::trace!("finishing renderer...");
@@ -725,20 +1238,22 @@ where
         pipeline: Box::new(pip),
         batches_by_task_type: HashMap::with_capacity(TaskKind::MAX_SIZE * 2),
         debug_context,
-        swapchain_context: Box::new(swapchain_context),
+        swapchain_context: swapchain_context.map(Box::new),
         vulkan_context: ctx,
         general_allocator: Box::new(general_allocator),
         mesh_buffers_by_id: HashMap::new(),
         mesh_buffer_ids: BitVec::repeat(false, 1024),
         textures_by_id: HashMap::new(),
-        draw_command_buffer,
+        texture_generations: vec![0; crate::pipeline::descriptor::MAX_DESCRIPTOR_IMAGE as usize],
+        setup_command_buffer,
+        frame_slots,
         main_queue,
         rendering_complete_semaphore,
         pass_timeline_semaphore,
-        draw_commands_finished_fence,
         pool: command_pool,
         optimal_transition_queue: Vec::new(),
         ongoing_optimal_transitions: Vec::new(),
+        pending_deletions: Vec::new(),
         shader_resources_by_kind: HashMap::new(),
         current_frame: AtomicU64::new(1),
     };
@@ -750,7 +1265,7 @@ where
         tri_geom.2.len() as u32,
         0,
         3,
-    );
+    )?;
     let tri_mesh = renderer.fetch_mesh(tri_id).unwrap();
     tri_mesh.write_vertices(&tri_geom.0);
     tri_mesh.write_normals(&tri_geom.1);
@@ -768,24 +1283,18 @@ where
             offset: 0,
             width: 1,
             height: 1,
+            depth: 1,
+            layers: 1,
         }],
         0,
-    );
+    )?;
     log::trace!("test texture with id {tex_id} created!");
     log::trace!("issuing initial layout transitions...");
-    renderer.submit_and_wait(|r, c| {
-        let barriers = r.pipeline.gen_initial_barriers();
-        let barrier_dep_info = vk::DependencyInfo::default().image_memory_barriers(&barriers);
-        unsafe {
-            r.vulkan_context
-                .device
-                .cmd_pipeline_barrier2(c, &barrier_dep_info);
-        }
-    });
+    renderer.issue_initial_barriers();
     log::trace!("initial layout transitions issued!");
     log::trace!("renderer finished!");
     // Return initialized renderer
-    renderer
+    Ok(renderer)
 }
 
 pub fn make_device(
@@ -793,11 +1302,17 @@ pub fn make_device(
     physical_device: vk::PhysicalDevice,
     queue_family_index: u32,
     is_debug_enabled: bool,
-) -> ash::Device {
-    let mut device_extension_names_raw = vec![
-        khr::swapchain::NAME.as_ptr(),
-        ext::swapchain_maintenance1::NAME.as_ptr(),
-    ];
+    is_headless: bool,
+) -> RendResult<ash::Device> {
+    let mut device_extension_names_raw = if is_headless {
+        // No presenting, software implementations may not even support swapchains
+        vec![]
+    } else {
+        vec![
+            khr::swapchain::NAME.as_ptr(),
+            ext::swapchain_maintenance1::NAME.as_ptr(),
+        ]
+    };
     if is_debug_enabled {
         // this allows for shader printf
         device_extension_names_raw.push(ash::khr::shader_non_semantic_info::NAME.as_ptr());
@@ -854,11 +1369,11 @@ pub fn make_device(
     let device: ash::Device = unsafe {
         instance
             .create_device(physical_device, &device_create_info, None)
-            .expect("couldn't create the device!")
+            .map_err(|e| RendError::vulkan(e, "creating the device"))?
     };
     log::info!("device initialized!");
     // Return initialized device
-    device
+    Ok(device)
 }
 
 pub fn make_instance(
@@ -866,7 +1381,7 @@ pub fn make_instance(
     extensions: &[*const i8],
     is_debug_enabled: bool,
     is_validation_layer_enabled: bool,
-) -> ash::Instance {
+) -> RendResult<ash::Instance> {
     let app_name = c"rend-vk";
 
     let mut layers_names_raw = vec![];
@@ -905,22 +1420,22 @@ pub fn make_instance(
     let instance: ash::Instance = unsafe {
         entry
             .create_instance(&create_info, None)
-            .expect("instance creation error!")
+            .map_err(|e| RendError::vulkan(e, "creating the instance"))?
     };
     log::info!("instance initialized!");
     // Return initialized instance
-    instance
+    Ok(instance)
 }
 
 pub fn select_physical_device(
     instance: &ash::Instance,
     surface_extension: &khr::surface::Instance,
-    window_surface: vk::SurfaceKHR,
-) -> (vk::PhysicalDevice, String, u32) {
+    window_surface: Option<vk::SurfaceKHR>,
+) -> RendResult<(vk::PhysicalDevice, String, u32)> {
     let devices = unsafe {
         instance
             .enumerate_physical_devices()
-            .expect("Physical device error")
+            .map_err(|e| RendError::vulkan(e, "enumerating the physical devices"))?
     };
     let mut tmp: Vec<_> = devices
         .iter()
@@ -941,13 +1456,16 @@ pub fn select_physical_device(
                     .find_map(|(index, info)| {
                         let supports_graphic_and_surface =
                             info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                                && surface_extension
-                                    .get_physical_device_surface_support(
-                                        *pdevice,
-                                        index as u32,
-                                        window_surface,
-                                    )
-                                    .unwrap();
+                                // Headless renderers don't need surface support
+                                && window_surface.is_none_or(|window_surface| {
+                                    surface_extension
+                                        .get_physical_device_surface_support(
+                                            *pdevice,
+                                            index as u32,
+                                            window_surface,
+                                        )
+                                        .unwrap()
+                                });
                         if supports_graphic_and_surface {
                             Some((
                                 *pdevice,
@@ -981,9 +1499,10 @@ pub fn select_physical_device(
         std::cmp::Ordering::Equal
     });
     // Just pick the first and use it
-    tmp.into_iter()
-        .find_map(|e| e.1)
-        .expect("couldn't find a suitable physical device!")
+    tmp.into_iter().find_map(|e| e.1).ok_or(RendError::vulkan(
+        vk::Result::ERROR_INCOMPATIBLE_DRIVER,
+        "finding a suitable physical device",
+    ))
 }
 
 fn gen_triangle_geometry() -> ([f32; 9], [f32; 9], [f32; 6]) {
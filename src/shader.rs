@@ -1,6 +1,9 @@
 use ash::{vk, Device};
 
-use crate::context::VulkanContext;
+use crate::{
+    context::VulkanContext,
+    error::{RendError, RendResult},
+};
 
 pub const ATTRIB_LOC_POSITION: u32 = 0;
 pub const ATTRIB_LOC_NORMAL: u32 = 1;
@@ -34,43 +37,52 @@ impl<'a> ShaderProgram<'a> {
         vertex: Option<(String, Vec<u32>)>,
         fragment: Option<(String, Vec<u32>)>,
         geometry: Option<(String, Vec<u32>)>,
-    ) -> Self {
+        compute: Option<(String, Vec<u32>)>,
+    ) -> RendResult<Self> {
         let shader_entry_name = c"main";
-        let stage_infos: Vec<Shader> = vec![vertex, fragment, geometry]
-            .into_iter()
-            .enumerate()
-            .filter_map(|(i, c)| match c {
-                Some(shader_bin) => {
-                    let (sh_type, sh_type_name) = match i {
-                        0 => (vk::ShaderStageFlags::VERTEX, "vert"),
-                        1 => (vk::ShaderStageFlags::FRAGMENT, "frag"),
-                        2 => (vk::ShaderStageFlags::GEOMETRY, "geom"),
-                        _ => panic!("unrecognized shader type {}", i),
-                    };
-                    let info = vk::ShaderModuleCreateInfo::default().code(&shader_bin.1);
-                    let module = unsafe { ctx.device.create_shader_module(&info, None) }
-                        .unwrap_or_else(|_| panic!("shader module error, type: {}", i));
-                    ctx.try_set_debug_name(
-                        &format!("{}_shader_module_{}", name, sh_type_name),
-                        module,
-                    );
-                    Some(Shader {
-                        name: shader_bin.0,
-                        info: vk::PipelineShaderStageCreateInfo {
-                            module,
-                            p_name: shader_entry_name.as_ptr(),
-                            stage: sh_type,
-                            ..Default::default()
-                        },
-                    })
-                }
-                None => None,
-            })
-            .collect();
-
-        ShaderProgram {
+        let stages = [
+            (vk::ShaderStageFlags::VERTEX, "vert", vertex),
+            (vk::ShaderStageFlags::FRAGMENT, "frag", fragment),
+            (vk::ShaderStageFlags::GEOMETRY, "geom", geometry),
+            (vk::ShaderStageFlags::COMPUTE, "comp", compute),
+        ];
+        let mut program = ShaderProgram {
             name,
-            shaders: stage_infos,
+            shaders: Vec::with_capacity(stages.len()),
+        };
+        for (sh_type, sh_type_name, shader_bin) in stages {
+            let Some(shader_bin) = shader_bin else {
+                continue;
+            };
+            let info = vk::ShaderModuleCreateInfo::default().code(&shader_bin.1);
+            let module = match unsafe { ctx.device.create_shader_module(&info, None) } {
+                Ok(module) => module,
+                Err(err) => {
+                    // Modules created so far are no use without the rest
+                    program.destroy(&ctx.device);
+                    return Err(RendError::vulkan(
+                        err,
+                        &format!(
+                            "creating the {} shader module of {}",
+                            sh_type_name, program.name
+                        ),
+                    ));
+                }
+            };
+            ctx.try_set_debug_name(
+                &format!("{}_shader_module_{}", program.name, sh_type_name),
+                module,
+            );
+            program.shaders.push(Shader {
+                name: shader_bin.0,
+                info: vk::PipelineShaderStageCreateInfo {
+                    module,
+                    p_name: shader_entry_name.as_ptr(),
+                    stage: sh_type,
+                    ..Default::default()
+                },
+            });
         }
+        Ok(program)
     }
 }
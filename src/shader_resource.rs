@@ -6,7 +6,10 @@ use std::{
 use glam::{Mat4, Vec3, Vec4};
 use serde::Serialize;
 
-use crate::UsedAsIndex;
+use crate::{
+    error::{RendError, RendResult},
+    UsedAsIndex,
+};
 
 #[derive(PartialEq, Eq, Clone, Copy, strum_macros::Display, Hash, Serialize)]
 #[repr(u8)]
@@ -42,6 +45,18 @@ impl ResourceKind {
         }
     }
 
+    pub fn try_of_u32(v: u32) -> RendResult<Self> {
+        Self::try_of_usize(v as usize)
+    }
+
+    pub fn try_of_usize(v: usize) -> RendResult<Self> {
+        if v > (Self::MAX_VALUE as usize) {
+            Err(RendError::InvalidKind(format!("resource kind {}", v)))
+        } else {
+            Ok(Self::of_usize(v))
+        }
+    }
+
     pub const fn of_u32(v: u32) -> Self {
         if v > (Self::MAX_VALUE as u32) {
             panic!()
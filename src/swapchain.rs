@@ -2,7 +2,11 @@ use std::collections::VecDeque;
 
 use ash::vk;
 
-use crate::{context::VulkanContext, pipeline::attachment::Attachment};
+use crate::{
+    context::VulkanContext,
+    error::{RendError, RendResult},
+    pipeline::attachment::Attachment,
+};
 
 pub struct SwapchainContext {
     pub surface: vk::SurfaceKHR,
@@ -12,7 +16,6 @@ pub struct SwapchainContext {
     pub present_mode: vk::PresentModeKHR,
     pub attachments: Vec<Attachment>,
     pub semaphores: VecDeque<vk::Semaphore>,
-    image_acquired_semaphore: vk::Semaphore,
     funcs: ash::khr::swapchain::Device,
 }
 
@@ -24,20 +27,27 @@ pub struct AcquiredImage {
 }
 
 impl SwapchainContext {
-    pub fn make(ctx: &VulkanContext, surface: vk::SurfaceKHR, is_vsync_enabled: bool) -> Self {
-        let present_mode = present_mode(ctx, surface, is_vsync_enabled);
-        let surface_extent = surface_extent(ctx, surface, 1280, 720);
-        let surface_format = surface_format(ctx, surface);
-        let swapchain = swapchain(ctx, surface, surface_extent, present_mode);
-        let attachments = attachments(ctx, surface, swapchain, surface_extent);
+    pub fn make(
+        ctx: &VulkanContext,
+        surface: vk::SurfaceKHR,
+        is_vsync_enabled: bool,
+        width: u32,
+        height: u32,
+    ) -> RendResult<Self> {
+        let present_mode = present_mode(ctx, surface, is_vsync_enabled)?;
+        let surface_extent = surface_extent(ctx, surface, width, height)?;
+        let surface_format = surface_format(ctx, surface)?;
+        let swapchain = swapchain(
+            ctx,
+            surface,
+            surface_extent,
+            present_mode,
+            vk::SwapchainKHR::null(),
+        )?;
+        let attachments = attachments(ctx, surface, swapchain, surface_extent)?;
         ctx.try_set_debug_name("swapchain_main", swapchain);
-        let semaphores = attachments
-            .iter()
-            .enumerate()
-            .map(|(idx, _)| ctx.create_semaphore(&format!("swapchain_semaphore_{}", idx)))
-            .collect();
-        let image_acquired_semaphore = ctx.create_semaphore("swapchain_image_aquired_semaphore");
-        Self {
+        let semaphores = semaphores(ctx, &attachments);
+        Ok(Self {
             present_mode,
             surface,
             surface_extent,
@@ -45,30 +55,70 @@ impl SwapchainContext {
             swapchain,
             attachments,
             semaphores,
-            image_acquired_semaphore,
             funcs: ctx.extension.swapchain.clone(),
+        })
+    }
+
+    /// Re-creates the swapchain with a new extent, ie, when the window was resized.
+    /// Caller must ensure the device isn't using any of the swapchain images.
+    /// Returns false if the surface has no area (ie, minimized window) and nothing was done.
+    pub fn recreate(&mut self, ctx: &VulkanContext, width: u32, height: u32) -> RendResult<bool> {
+        let surface_extent = surface_extent(ctx, self.surface, width, height)?;
+        if surface_extent.width == 0 || surface_extent.height == 0 {
+            return Ok(false);
         }
+        let swapchain = swapchain(
+            ctx,
+            self.surface,
+            surface_extent,
+            self.present_mode,
+            self.swapchain,
+        )?;
+        let attachments = match attachments(ctx, self.surface, swapchain, surface_extent) {
+            Ok(attachments) => attachments,
+            Err(err) => {
+                unsafe { self.funcs.destroy_swapchain(swapchain, None) };
+                return Err(err);
+            }
+        };
+        self.destroy_attachments(ctx);
+        unsafe { self.funcs.destroy_swapchain(self.swapchain, None) };
+        ctx.try_set_debug_name("swapchain_main", swapchain);
+        self.swapchain = swapchain;
+        self.surface_extent = surface_extent;
+        self.attachments = attachments;
+        self.semaphores = semaphores(ctx, &self.attachments);
+        Ok(true)
     }
 
-    pub fn acquire_next(&mut self) -> AcquiredImage {
-        let (present_index, _) = unsafe {
-            self.funcs
-                .acquire_next_image(
-                    self.swapchain,
-                    u64::MAX,
-                    self.image_acquired_semaphore,
-                    vk::Fence::null(),
-                )
-                .unwrap()
+    /// Acquired image will signal the semaphore once ready.
+    /// Returns None if the swapchain is out of date and has to be re-created.
+    pub fn acquire_next(
+        &mut self,
+        acquire_semaphore: vk::Semaphore,
+    ) -> RendResult<Option<AcquiredImage>> {
+        let acquired = unsafe {
+            self.funcs.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                acquire_semaphore,
+                vk::Fence::null(),
+            )
+        };
+        // Suboptimal images can still be presented, re-creation is handled after presenting
+        let present_index = match acquired {
+            Ok((index, _)) => index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(None),
+            Err(e) => return Err(RendError::vulkan(e, "acquiring the next image")),
         };
         let attachment = self.attachments[present_index as usize].clone();
         let semaphore = self.semaphores[present_index as usize].clone();
-        AcquiredImage {
+        Ok(Some(AcquiredImage {
             attachment: Box::new(attachment),
             render_semaphore: semaphore,
-            acquire_semaphore: self.image_acquired_semaphore,
+            acquire_semaphore,
             index: present_index,
-        }
+        }))
     }
 
     // fn next_semaphore(&mut self) -> vk::Semaphore {
@@ -77,21 +127,28 @@ impl SwapchainContext {
     //     n
     // }
 
-    pub fn present(&self, attachment_index: u32, queue: vk::Queue, to_wait: &[vk::Semaphore]) {
+    /// Returns true if the swapchain no longer matches the surface and has to be re-created.
+    pub fn present(
+        &self,
+        attachment_index: u32,
+        queue: vk::Queue,
+        to_wait: &[vk::Semaphore],
+    ) -> RendResult<bool> {
         let swapchains = [self.swapchain];
         let image_indices = [attachment_index];
         let present_info = vk::PresentInfoKHR::default()
             .wait_semaphores(to_wait)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
-        unsafe {
-            self.funcs
-                .queue_present(queue, &present_info)
-                .expect("queue present failed!");
-        };
+        match unsafe { self.funcs.queue_present(queue, &present_info) } {
+            // Ok(true) means suboptimal
+            Ok(is_suboptimal) => Ok(is_suboptimal),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(true),
+            Err(e) => Err(RendError::vulkan(e, "presenting")),
+        }
     }
 
-    pub fn destroy(&self, ctx: &VulkanContext) {
+    fn destroy_attachments(&self, ctx: &VulkanContext) {
         for att in self.attachments.iter() {
             unsafe {
                 ctx.device.destroy_image_view(att.view, None);
@@ -100,10 +157,10 @@ impl SwapchainContext {
         for sem in self.semaphores.iter() {
             unsafe { ctx.device.destroy_semaphore(sem.clone(), None) };
         }
-        unsafe {
-            ctx.device
-                .destroy_semaphore(self.image_acquired_semaphore, None)
-        };
+    }
+
+    pub fn destroy(&self, ctx: &VulkanContext) {
+        self.destroy_attachments(ctx);
         unsafe {
             ctx.extension
                 .swapchain
@@ -118,41 +175,43 @@ pub fn attachments(
     surface: vk::SurfaceKHR,
     swapchain: vk::SwapchainKHR,
     surface_extent: vk::Extent2D,
-) -> Vec<Attachment> {
+) -> RendResult<Vec<Attachment>> {
     let images = unsafe {
         ctx.extension
             .swapchain
             .get_swapchain_images(swapchain)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "getting the swapchain images"))?
     };
-    let surface_format = surface_format(ctx, surface);
-    let image_views: Vec<vk::ImageView> = images
-        .iter()
-        .map(|&image| {
-            let create_view_info = vk::ImageViewCreateInfo::default()
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(surface_format.format)
-                .components(vk::ComponentMapping {
-                    r: vk::ComponentSwizzle::R,
-                    g: vk::ComponentSwizzle::G,
-                    b: vk::ComponentSwizzle::B,
-                    a: vk::ComponentSwizzle::A,
-                })
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })
-                .image(image);
-            unsafe {
-                ctx.device
-                    .create_image_view(&create_view_info, None)
-                    .unwrap()
+    let surface_format = surface_format(ctx, surface)?;
+    let mut image_views = Vec::with_capacity(images.len());
+    for &image in &images {
+        let create_view_info = vk::ImageViewCreateInfo::default()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(surface_format.format)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::R,
+                g: vk::ComponentSwizzle::G,
+                b: vk::ComponentSwizzle::B,
+                a: vk::ComponentSwizzle::A,
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image(image);
+        match unsafe { ctx.device.create_image_view(&create_view_info, None) } {
+            Ok(view) => image_views.push(view),
+            Err(e) => {
+                for view in image_views {
+                    unsafe { ctx.device.destroy_image_view(view, None) };
+                }
+                return Err(RendError::vulkan(e, "creating the swapchain image views"));
             }
-        })
-        .collect();
+        }
+    }
     images
         .iter()
         .zip(image_views.iter())
@@ -170,7 +229,15 @@ pub fn attachments(
         })
         .collect();
 
+    Ok(attachments)
+}
+
+pub fn semaphores(ctx: &VulkanContext, attachments: &[Attachment]) -> VecDeque<vk::Semaphore> {
     attachments
+        .iter()
+        .enumerate()
+        .map(|(idx, _)| ctx.create_semaphore(&format!("swapchain_semaphore_{}", idx)))
+        .collect()
 }
 
 pub fn swapchain(
@@ -178,11 +245,12 @@ pub fn swapchain(
     surface: vk::SurfaceKHR,
     surface_extent: vk::Extent2D,
     present_mode: vk::PresentModeKHR,
-) -> vk::SwapchainKHR {
-    let surface_format = surface_format(ctx, surface);
+    old_swapchain: vk::SwapchainKHR,
+) -> RendResult<vk::SwapchainKHR> {
+    let surface_format = surface_format(ctx, surface)?;
     let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
         .surface(surface)
-        .min_image_count(desired_image_count(ctx, surface))
+        .min_image_count(desired_image_count(ctx, surface)?)
         .image_color_space(surface_format.color_space)
         .image_format(surface_format.format)
         .image_extent(surface_extent)
@@ -193,13 +261,14 @@ pub fn swapchain(
         .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
         .present_mode(present_mode)
         .clipped(true)
+        .old_swapchain(old_swapchain)
         .image_array_layers(1);
 
     unsafe {
         ctx.extension
             .swapchain
             .create_swapchain(&swapchain_create_info, None)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "creating the swapchain"))
     }
 }
 
@@ -208,32 +277,40 @@ pub fn surface_extent(
     surface: vk::SurfaceKHR,
     width: u32,
     height: u32,
-) -> vk::Extent2D {
-    let surface_caps = surface_capabilities(ctx, surface);
-    match surface_caps.current_extent.width {
+) -> RendResult<vk::Extent2D> {
+    let surface_caps = surface_capabilities(ctx, surface)?;
+    let extent = match surface_caps.current_extent.width {
         std::u32::MAX => vk::Extent2D { width, height },
         _ => surface_caps.current_extent,
-    }
+    };
+    let (min, max) = (surface_caps.min_image_extent, surface_caps.max_image_extent);
+    Ok(vk::Extent2D {
+        width: extent.width.clamp(min.width, max.width.max(min.width)),
+        height: extent.height.clamp(min.height, max.height.max(min.height)),
+    })
 }
 
-pub fn surface_format(ctx: &VulkanContext, surface: vk::SurfaceKHR) -> vk::SurfaceFormatKHR {
+pub fn surface_format(
+    ctx: &VulkanContext,
+    surface: vk::SurfaceKHR,
+) -> RendResult<vk::SurfaceFormatKHR> {
     let formats = unsafe {
         ctx.extension
             .surface
             .get_physical_device_surface_formats(ctx.physical_device, surface)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "listing the surface formats"))?
     };
     // Try finding the first SRGB format available
     let srgb = formats.iter().find(|e| {
         let name = format!("{:?}", e.format);
         name.to_uppercase().contains("SRGB")
     });
-    if let Some(fmt) = srgb {
-        *fmt
-    } else {
-        *formats
-            .first()
-            .expect("couldn't list the device's surface formats!")
+    match srgb.or(formats.first()) {
+        Some(fmt) => Ok(*fmt),
+        None => Err(RendError::vulkan(
+            vk::Result::ERROR_FORMAT_NOT_SUPPORTED,
+            "finding a surface format",
+        )),
     }
 }
 
@@ -241,14 +318,14 @@ pub fn present_mode(
     ctx: &VulkanContext,
     surface: vk::SurfaceKHR,
     is_vsync_enabled: bool,
-) -> vk::PresentModeKHR {
+) -> RendResult<vk::PresentModeKHR> {
     let present_modes = unsafe {
         ctx.extension
             .surface
             .get_physical_device_surface_present_modes(ctx.physical_device, surface)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "listing the surface present modes"))?
     };
-    present_modes
+    let present_mode = present_modes
         .iter()
         .cloned()
         // if vsync is enabled, prefer dynamic vsync
@@ -259,26 +336,27 @@ pub fn present_mode(
         } else {
             // no vsync at all
             vk::PresentModeKHR::IMMEDIATE
-        })
+        });
+    Ok(present_mode)
 }
 
-pub fn desired_image_count(ctx: &VulkanContext, surface: vk::SurfaceKHR) -> u32 {
-    let surface_caps = surface_capabilities(ctx, surface);
+pub fn desired_image_count(ctx: &VulkanContext, surface: vk::SurfaceKHR) -> RendResult<u32> {
+    let surface_caps = surface_capabilities(ctx, surface)?;
     let desired_image_count = surface_caps.min_image_count + 1;
     if surface_caps.max_image_count > 0 && desired_image_count > surface_caps.max_image_count {
-        return surface_caps.max_image_count;
+        return Ok(surface_caps.max_image_count);
     }
-    desired_image_count
+    Ok(desired_image_count)
 }
 
 pub fn surface_capabilities(
     ctx: &VulkanContext,
     surface: vk::SurfaceKHR,
-) -> vk::SurfaceCapabilitiesKHR {
+) -> RendResult<vk::SurfaceCapabilitiesKHR> {
     unsafe {
         ctx.extension
             .surface
             .get_physical_device_surface_capabilities(ctx.physical_device, surface)
-            .unwrap()
+            .map_err(|e| RendError::vulkan(e, "querying the surface capabilities"))
     }
 }
@@ -1,6 +1,11 @@
 use ash::vk;
 
-use crate::{buffer::DeviceSlice, context::VulkanContext, UsedAsIndex};
+use crate::{
+    buffer::DeviceSlice,
+    context::VulkanContext,
+    error::{RendError, RendResult},
+    UsedAsIndex,
+};
 
 #[derive(PartialEq, Eq, Clone, Copy, strum_macros::Display, Hash)]
 pub enum TextureKind {
@@ -28,6 +33,11 @@ pub struct MipMap {
     pub index: u32,
     pub width: u32,
     pub height: u32,
+    /// Only more than one for 3D textures
+    pub depth: u32,
+    /// Six per cube for cube maps, otherwise more than one makes it an array
+    pub layers: u32,
+    /// Size of a single layer
     pub size: u32,
     pub offset: u32,
 }
@@ -50,6 +60,14 @@ impl MipMap {
             height: self.height,
         }
     }
+
+    pub const fn extent_3d(&self) -> vk::Extent3D {
+        vk::Extent3D {
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+        }
+    }
 }
 
 impl Texture {
@@ -69,6 +87,15 @@ impl Texture {
         self.mip_maps[0].height
     }
 
+    pub fn depth(&self) -> u32 {
+        self.mip_maps[0].depth
+    }
+
+    /// Layers of the image, counting every face of cube maps
+    pub fn layers(&self) -> u32 {
+        self.mip_maps[0].layers
+    }
+
     pub fn extent(&self) -> vk::Extent2D {
         vk::Extent2D {
             width: self.width(),
@@ -77,23 +104,24 @@ impl Texture {
     }
 
     pub fn size(&self) -> u32 {
-        self.mip_maps.iter().map(|e| e.size).sum()
+        self.mip_maps.iter().map(|e| e.size).sum::<u32>() * self.layers()
     }
 
     fn subresource_range(&self) -> vk::ImageSubresourceRange {
         vk::ImageSubresourceRange {
             aspect_mask: self.format.aspect(),
             level_count: self.mip_map_count(),
-            layer_count: self.kind.layer_count(),
+            layer_count: self.layers(),
             ..Default::default()
         }
     }
 
+    /// One region per mip map of every layer, each layer has its whole mip chain laid out before the next one
     pub fn buffer_copy_regions(&self, offset: u64) -> Vec<vk::BufferImageCopy> {
         let mut dest: Vec<vk::BufferImageCopy> =
-            Vec::with_capacity(self.kind.layer_count() as usize * self.mip_maps.len());
+            Vec::with_capacity(self.layers() as usize * self.mip_maps.len());
         let mut offset = offset;
-        for sidei in 0..self.kind.layer_count() {
+        for sidei in 0..self.layers() {
             for mm in &self.mip_maps {
                 dest.push(
                     vk::BufferImageCopy::default()
@@ -104,7 +132,7 @@ impl Texture {
                                 .layer_count(1)
                                 .mip_level(mm.index),
                         )
-                        .image_extent(mm.extent().into())
+                        .image_extent(mm.extent_3d())
                         .buffer_offset(offset),
                 );
                 // advance to next mip map
@@ -186,6 +214,15 @@ impl Texture {
         };
     }
 
+    /// Staging buffer, if any, has to be freed separately since it belongs to an allocator.
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+
     pub fn read_staging(&self) -> Vec<u8> {
         if let Some(device) = &self.staging {
             let slice = unsafe {
@@ -206,8 +243,18 @@ impl TextureKind {
         match self {
             TextureKind::T1D => 1,
             TextureKind::T2D => 1,
+            TextureKind::T3D => 1,
             TextureKind::CUBEMAP => 6,
-            TextureKind::T3D => panic!("unsupported T3D kind!"),
+        }
+    }
+
+    pub const fn image_type(self) -> vk::ImageType {
+        match self {
+            TextureKind::T1D => vk::ImageType::TYPE_1D,
+            TextureKind::T2D => vk::ImageType::TYPE_2D,
+            TextureKind::T3D => vk::ImageType::TYPE_3D,
+            // Cube maps are 2D images with six layers
+            TextureKind::CUBEMAP => vk::ImageType::TYPE_2D,
         }
     }
 
@@ -215,8 +262,34 @@ impl TextureKind {
         match self {
             TextureKind::T1D => vk::ImageViewType::TYPE_1D,
             TextureKind::T2D => vk::ImageViewType::TYPE_2D,
+            TextureKind::T3D => vk::ImageViewType::TYPE_3D,
             TextureKind::CUBEMAP => vk::ImageViewType::CUBE,
-            TextureKind::T3D => panic!("unsupported T3D kind!"),
+        }
+    }
+
+    /// View type of a view spanning that many layers
+    pub const fn view_type_of(self, layers: u32) -> vk::ImageViewType {
+        if layers > self.layer_count() {
+            self.array_view_type()
+        } else {
+            self.view_type()
+        }
+    }
+
+    pub const fn array_view_type(self) -> vk::ImageViewType {
+        match self {
+            TextureKind::T1D => vk::ImageViewType::TYPE_1D_ARRAY,
+            TextureKind::T2D => vk::ImageViewType::TYPE_2D_ARRAY,
+            TextureKind::CUBEMAP => vk::ImageViewType::CUBE_ARRAY,
+            TextureKind::T3D => panic!("T3D kind can't be an array!"),
+        }
+    }
+
+    pub fn try_of_u32(v: u32) -> RendResult<Self> {
+        if v > (Self::MAX_VALUE as u32) {
+            Err(RendError::InvalidKind(format!("texture kind {}", v)))
+        } else {
+            Ok(Self::of_u32(v))
         }
     }
 
@@ -262,7 +335,7 @@ pub fn mip_dimensions_of(index: usize, value: u32) -> u32 {
     (value as f32 / 2.0f32.powi(index as i32)).floor().max(1.0) as u32
 }
 
-fn usage_flags_for(format: crate::format::Format, is_attachment: bool) -> vk::ImageUsageFlags {
+pub fn usage_flags_for(format: crate::format::Format, is_attachment: bool) -> vk::ImageUsageFlags {
     if !is_attachment {
         return vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
     }
@@ -279,27 +352,103 @@ fn usage_flags_for(format: crate::format::Format, is_attachment: bool) -> vk::Im
     | depth_or_color
 }
 
+/// Layers are counted in units of the kind, ie, cubes for cube maps. More than one makes an array.
+#[allow(clippy::too_many_arguments)]
 pub fn make(
     ctx: &VulkanContext,
     name: String,
-    width: u32,
-    height: u32,
+    extent: vk::Extent3D,
     levels: u8,
+    layers: u32,
     format: crate::format::Format,
     kind: TextureKind,
     is_attachment: bool,
-) -> Texture {
+) -> RendResult<Texture> {
+    let usage = usage_flags_for(format, is_attachment);
+    let samples = vk::SampleCountFlags::TYPE_1;
+    make_of_usage(
+        ctx, name, extent, levels, layers, format, kind, usage, samples,
+    )
+}
+
+/// Same as [make] but with explicit usage flags and sample count, ie, for attachments
+/// used as storage images or multisampled ones.
+#[allow(clippy::too_many_arguments)]
+pub fn make_of_usage(
+    ctx: &VulkanContext,
+    name: String,
+    extent: vk::Extent3D,
+    levels: u8,
+    layers: u32,
+    format: crate::format::Format,
+    kind: TextureKind,
+    usage: vk::ImageUsageFlags,
+    samples: vk::SampleCountFlags,
+) -> RendResult<Texture> {
+    let image = image_of_usage(ctx, extent, levels, layers, format, kind, usage, samples)?;
+    let (memory_req, _) = memory_requirements_of(ctx, image);
+
+    let Some(memory_type_index) = ctx.memory_type_index_for(
+        memory_req.memory_type_bits,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    ) else {
+        unsafe { ctx.device.destroy_image(image, None) };
+        return Err(RendError::OutOfMemory(format!(
+            "no device local memory type for image {}",
+            name
+        )));
+    };
+
+    let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(image);
+
+    let memory_allocate_info = vk::MemoryAllocateInfo::default()
+        .push_next(&mut dedicated_info)
+        .allocation_size(memory_req.size)
+        .memory_type_index(memory_type_index);
+
+    let memory = match unsafe { ctx.device.allocate_memory(&memory_allocate_info, None) } {
+        Ok(memory) => memory,
+        Err(err) => {
+            unsafe { ctx.device.destroy_image(image, None) };
+            return Err(RendError::vulkan(
+                err,
+                &format!("allocating the memory of image {}", name),
+            ));
+        }
+    };
+
+    bind_texture(ctx, name, image, memory, levels, layers, format, kind).inspect_err(|_| unsafe {
+        ctx.device.destroy_image(image, None);
+        ctx.device.free_memory(memory, None);
+    })
+}
+
+/// Creates the image alone, without any memory bound to it.
+#[allow(clippy::too_many_arguments)]
+pub fn image_of_usage(
+    ctx: &VulkanContext,
+    extent: vk::Extent3D,
+    levels: u8,
+    layers: u32,
+    format: crate::format::Format,
+    kind: TextureKind,
+    usage: vk::ImageUsageFlags,
+    samples: vk::SampleCountFlags,
+) -> RendResult<vk::Image> {
     assert!(levels > 0, "levels can't be 0!");
-    let vk_format = format.to_vk();
+    assert!(
+        kind != TextureKind::T3D || layers == 1,
+        "T3D kind can't have layers!"
+    );
     let create_info = vk::ImageCreateInfo {
-        image_type: vk::ImageType::TYPE_2D,
-        format: vk_format,
-        extent: vk::Extent2D { width, height }.into(),
+        image_type: kind.image_type(),
+        format: format.to_vk(),
+        extent,
         mip_levels: levels as u32,
-        array_layers: kind.layer_count(),
-        samples: vk::SampleCountFlags::TYPE_1,
+        array_layers: kind.layer_count() * layers,
+        samples,
         tiling: vk::ImageTiling::OPTIMAL,
-        usage: usage_flags_for(format, is_attachment),
+        usage,
         sharing_mode: vk::SharingMode::EXCLUSIVE,
         flags: if kind == TextureKind::CUBEMAP {
             vk::ImageCreateFlags::CUBE_COMPATIBLE
@@ -308,7 +457,15 @@ pub fn make(
         },
         ..Default::default()
     };
-    let image = unsafe { ctx.device.create_image(&create_info, None) }.unwrap();
+    unsafe { ctx.device.create_image(&create_info, None) }
+        .map_err(|e| RendError::vulkan(e, "creating an image"))
+}
+
+/// Memory requirements of the image, and if it requires a dedicated allocation
+pub fn memory_requirements_of(
+    ctx: &VulkanContext,
+    image: vk::Image,
+) -> (vk::MemoryRequirements, bool) {
     let mut dedicated_req = vk::MemoryDedicatedRequirements {
         ..Default::default()
     };
@@ -319,54 +476,50 @@ pub fn make(
         ctx.device
             .get_image_memory_requirements2(&requirements_info, &mut memory_req)
     };
+    let requirements = memory_req.memory_requirements;
+    (
+        requirements,
+        dedicated_req.requires_dedicated_allocation == vk::TRUE,
+    )
+}
 
-    let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(image);
-
-    let memory_allocate_info = vk::MemoryAllocateInfo::default()
-        .push_next(&mut dedicated_info)
-        .allocation_size(memory_req.memory_requirements.size)
-        .memory_type_index(
-            ctx.memory_type_index_for(
-                memory_req.memory_requirements.memory_type_bits,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            )
-            .unwrap(),
-        );
-
-    let memory = unsafe {
-        ctx.device
-            .allocate_memory(&memory_allocate_info, None)
-            .expect("failed image memory alloc")
-    };
-
-    unsafe {
-        ctx.device
-            .bind_image_memory(image, memory, 0)
-            .expect("failed image memory bind")
-    };
+/// Binds the image to the start of the memory and creates a view of all of it.
+/// Memory may be shared with other images, the texture owns it only if destroyed as one.
+/// On failure both the image and memory are left for the caller to release.
+#[allow(clippy::too_many_arguments)]
+pub fn bind_texture(
+    ctx: &VulkanContext,
+    name: String,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    levels: u8,
+    layers: u32,
+    format: crate::format::Format,
+    kind: TextureKind,
+) -> RendResult<Texture> {
+    unsafe { ctx.device.bind_image_memory(image, memory, 0) }
+        .map_err(|e| RendError::vulkan(e, &format!("binding the memory of image {}", name)))?;
 
+    let vk_format = format.to_vk();
     let image_view_info = vk::ImageViewCreateInfo::default()
         .subresource_range(
             vk::ImageSubresourceRange::default()
                 .aspect_mask(format.aspect())
                 .level_count(levels as u32)
-                .layer_count(kind.layer_count()),
+                .layer_count(kind.layer_count() * layers),
         )
         .image(image)
         .format(vk_format)
-        .view_type(kind.view_type());
+        .view_type(kind.view_type_of(kind.layer_count() * layers));
 
-    let view = unsafe {
-        ctx.device
-            .create_image_view(&image_view_info, None)
-            .expect("failed creating image view")
-    };
+    let view = unsafe { ctx.device.create_image_view(&image_view_info, None) }
+        .map_err(|e| RendError::vulkan(e, &format!("creating the view of image {}", name)))?;
 
     ctx.try_set_debug_name(&format!("{name}_tex_image"), image);
     ctx.try_set_debug_name(&format!("{name}_tex_image_memory"), memory);
     ctx.try_set_debug_name(&format!("{name}_tex_image_view"), view);
 
-    Texture {
+    Ok(Texture {
         id: 0,
         mip_maps: Vec::new(),
         name,
@@ -376,5 +529,5 @@ pub fn make(
         view,
         kind,
         staging: None,
-    }
+    })
 }
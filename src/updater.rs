@@ -1,9 +1,9 @@
 use crate::{
-    buffer::{DeviceAllocator, DeviceSlice},
+    buffer::{DeviceSlice, LinearAllocator},
     shader_resource::{MultiResource, SingleResource},
 };
 
-fn alloc_and_copy_into<T>(mem: &DeviceAllocator, src: &[T], count: u32) -> DeviceSlice {
+fn alloc_and_copy_into<T>(mem: &LinearAllocator, src: &[T], count: u32) -> DeviceSlice {
     if count as usize != src.len() {
         panic!(
             "expected {} resources of type {}, found {}",
@@ -14,7 +14,7 @@ fn alloc_and_copy_into<T>(mem: &DeviceAllocator, src: &[T], count: u32) -> Devic
     }
     let per_item_size = std::mem::size_of::<T>() as u64;
     let total_size = per_item_size * count as u64;
-    let device = mem.alloc(total_size).unwrap();
+    let device = mem.alloc(total_size);
     let src = src.as_ptr() as *const u8;
     let dst = device.addr as *mut u8;
     unsafe {
@@ -34,7 +34,7 @@ fn copy_into<T>(src: *const T, dst: &DeviceSlice, offset: u64) -> u64 {
 }
 
 pub fn alloc_and_fill_multi(
-    mem: &DeviceAllocator,
+    mem: &LinearAllocator,
     resource: &MultiResource,
     instance_count: u32,
 ) -> DeviceSlice {